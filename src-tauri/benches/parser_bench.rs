@@ -1,5 +1,5 @@
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
 use valuator::Parser;
 
 fn bench_parse(c: &mut Criterion) {
@@ -14,6 +14,42 @@ fn bench_parse(c: &mut Criterion) {
     let res = node.eval(&mut p2);
     res
   })));
+
+  let long = (0..100).map(|i| format!("({i}+{i}*2)")).collect::<Vec<_>>().join("+");
+  let end = long.len();
+
+  let mut group = c.benchmark_group("throughput");
+  group.throughput(Throughput::Bytes(long.len() as u64));
+  group.bench_function("new and parse long", |b|b.iter(||black_box({
+    Parser::new(long.as_str()).parse()
+  })));
+  group.finish();
+
+  let mut p3 = Parser::new(long.clone());
+  c.bench_function("reparse long", |b|b.iter(||black_box({
+    p3.reparse()
+  })));
+
+  p3.reparse();
+  c.bench_function("edit long", |b|b.iter(||black_box({
+    p3.edit(end-2..end-1, "7")
+  })));
+
+  let mut p4 = Parser::new(format!("{}1{}", "(".repeat(100), ")".repeat(100)));
+  c.bench_function("reparse nested", |b|b.iter(||black_box({
+    p4.reparse()
+  })));
+
+  let mut p5 = Parser::new(format!("{}1", "sum(".repeat(100)));
+  c.bench_function("reparse nested unclosed", |b|b.iter(||black_box({
+    p5.reparse()
+  })));
+
+  let list = (0..1000).map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+  let mut p6 = Parser::new(list);
+  c.bench_function("reparse long list", |b|b.iter(||black_box({
+    p6.reparse()
+  })));
 }
 
 criterion_group!(benches, bench_parse);
@@ -0,0 +1,749 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use rust_decimal::Decimal;
+
+use crate::cell::{ErrorKind, Val};
+use crate::closure::Closure;
+use crate::complex::Complex;
+use crate::err::Err;
+use crate::eval::{Env, Node, ObjectContext};
+use crate::parser::{binop_prec, Assoc, ElemRange, NodeId, ValueId, MAX_OP_PREC};
+
+/// Leads the binary form of an `Ast`.
+const MAGIC: &[u8; 4] = b"VAST";
+/// Version of the binary form, bumped whenever the layout changes.
+pub const VERSION: u16 = 5;
+/// Deepest nesting of values and closures accepted when decoding.
+const MAX_DEPTH: u32 = 64;
+
+/// A formula detached from its parser: the root node, and the nodes, values
+/// and elements reachable from it in arenas of their own.
+///
+/// Nodes are copied children first, so every `NodeId` points to an earlier
+/// node and the tree cannot loop. `NodeId(0)` is the `Zero` node, marking an
+/// absent operand as in the parser.
+#[derive(Debug, Clone)]
+pub struct Ast {
+  root: Node,
+  nodes: Vec<Node>,
+  values: Vec<Val>,
+  elems: Vec<NodeId>,
+}
+
+impl Ast {
+  /// Copies the tree at `root` out of `ctx`, leaving behind any nodes the
+  /// parser built while backtracking.
+  pub fn new(ctx: &impl ObjectContext, root: &Node) -> Ast {
+    let mut ast = Ast {
+      root: Node::default(),
+      nodes: vec![Node::default()],
+      values: vec![Val::default()],
+      elems: vec![],
+    };
+    ast.root = ast.copy(ctx, root);
+    ast
+  }
+
+  pub fn root(&self) -> &Node {
+    &self.root
+  }
+
+  /// All nodes of the tree, including the root.
+  pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+    self.nodes.iter().chain([&self.root])
+  }
+
+  fn push_node(&mut self, node: Node) -> NodeId {
+    self.nodes.push(node);
+    NodeId(self.nodes.len() as u32 - 1)
+  }
+
+  fn copy_value(&mut self, ctx: &impl ObjectContext, value: &ValueId) -> ValueId {
+    self.values.push(ctx.get_value(value).clone());
+    ValueId(self.values.len() as u32 - 1)
+  }
+
+  fn copy_elems(&mut self, ctx: &impl ObjectContext, elems: &ElemRange) -> ElemRange {
+    let ids: Vec<NodeId> = ctx.get_elems(elems).iter().map(|id|self.copy_node(ctx, id)).collect();
+    let start = self.elems.len() as u32;
+    self.elems.extend(ids);
+    ElemRange{ start, len: elems.len }
+  }
+
+  /// Copies the subtree at `id`, returning its id in this tree.
+  fn copy_node(&mut self, ctx: &impl ObjectContext, id: &NodeId) -> NodeId {
+    // NodeId(0) marks an absent operand, e.g. the column of `[1]`
+    if *id == NodeId(0) {
+      return NodeId(0);
+    }
+    let node = self.copy(ctx, ctx.get_node(id));
+    self.push_node(node)
+  }
+
+  /// Copies the children of `node`, returning it with their ids in this tree.
+  fn copy(&mut self, ctx: &impl ObjectContext, node: &Node) -> Node {
+    match *node {
+      Node::Zero{} => Node::Zero{},
+      Node::Leaf{value} => Node::Leaf{ value: self.copy_value(ctx, &value) },
+      Node::Sym{value} => Node::Sym{ value: self.copy_value(ctx, &value) },
+      Node::BinOp{op, lhs, rhs} => Node::BinOp{
+        op,
+        lhs: self.copy_node(ctx, &lhs),
+        rhs: self.copy_node(ctx, &rhs),
+      },
+      Node::CustomOp{op, prec, assoc, lhs, rhs} => Node::CustomOp{
+        op: self.copy_value(ctx, &op),
+        prec,
+        assoc,
+        lhs: self.copy_node(ctx, &lhs),
+        rhs: self.copy_node(ctx, &rhs),
+      },
+      Node::UniOp{op, rhs} => Node::UniOp{ op, rhs: self.copy_node(ctx, &rhs) },
+      Node::Index{row, col} => Node::Index{
+        row: self.copy_node(ctx, &row),
+        col: self.copy_node(ctx, &col),
+      },
+      Node::Addr{row, col} => Node::Addr{
+        row: self.copy_node(ctx, &row),
+        col: self.copy_node(ctx, &col),
+      },
+      Node::Call{func, args} => Node::Call{
+        func: self.copy_node(ctx, &func),
+        args: self.copy_node(ctx, &args),
+      },
+      Node::List{elems} => Node::List{ elems: self.copy_elems(ctx, &elems) },
+      Node::Let{binds, body} => Node::Let{
+        binds: self.copy_elems(ctx, &binds),
+        body: self.copy_node(ctx, &body),
+      },
+      Node::Field{key, value} => Node::Field{
+        key: self.copy_node(ctx, &key),
+        value: self.copy_node(ctx, &value),
+      },
+      Node::Lambda{params, body} => Node::Lambda{
+        params: self.copy_elems(ctx, &params),
+        body: self.copy_node(ctx, &body),
+      },
+      Node::Slice{start, end} => Node::Slice{
+        start: self.copy_node(ctx, &start),
+        end: self.copy_node(ctx, &end),
+      },
+      Node::TileRef{tile, cell} => Node::TileRef{
+        tile: self.copy_node(ctx, &tile),
+        cell: self.copy_node(ctx, &cell),
+      },
+      Node::If{cond, then, other} => Node::If{
+        cond: self.copy_node(ctx, &cond),
+        then: self.copy_node(ctx, &then),
+        other: self.copy_node(ctx, &other),
+      },
+      Node::Match{subject, arms, other} => Node::Match{
+        subject: self.copy_node(ctx, &subject),
+        arms: self.copy_elems(ctx, &arms),
+        other: self.copy_node(ctx, &other),
+      },
+    }
+  }
+
+  /// Encodes the tree: `MAGIC`, `VERSION` as two little endian bytes, then
+  /// the values, elements and nodes arenas and the root.
+  ///
+  /// Integers are LEB128 varints, so small ids take a single byte. Nodes are
+  /// a tag followed by their fields, values a tag followed by their contents.
+  pub fn encode(&self) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend(VERSION.to_le_bytes());
+    put_ast(&mut out, self);
+    out
+  }
+
+  /// Decodes a tree written by `encode`.
+  ///
+  /// Input from a different version is rejected with `Err::Version`, and
+  /// truncated or corrupt input with `Err::Corrupt`, including ids outside
+  /// their arena and links that do not point to an earlier node.
+  pub fn decode(bytes: &[u8]) -> Result<Ast, Err> {
+    let mut r = Reader{ bytes, pos: 0, depth: 0 };
+    if r.take(MAGIC.len())? != MAGIC {
+      return Err(Err::Corrupt{ pos: 0 });
+    }
+    let version = u16::from_le_bytes([r.byte()?, r.byte()?]);
+    if version != VERSION {
+      return Err(Err::Version{ found: version });
+    }
+    let ast = r.ast()?;
+    if r.pos < bytes.len() {
+      return r.corrupt();
+    }
+    Ok(ast)
+  }
+}
+
+impl ObjectContext for Ast {
+  fn get_value(&self, value: &ValueId) -> &Val {
+    &self.values[value.0 as usize]
+  }
+  fn get_node(&self, node: &NodeId) -> &Node {
+    &self.nodes[node.0 as usize]
+  }
+  fn get_elems(&self, elems: &ElemRange) -> &[NodeId] {
+    &self.elems[elems.start as usize..(elems.start + elems.len) as usize]
+  }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut n: u128) {
+  while n >= 0x80 {
+    out.push(n as u8 | 0x80);
+    n >>= 7;
+  }
+  out.push(n as u8);
+}
+
+fn put_u32(out: &mut Vec<u8>, n: u32) {
+  put_varint(out, n as u128);
+}
+
+fn put_len(out: &mut Vec<u8>, len: usize) {
+  put_u32(out, len as u32);
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+  put_len(out, s.len());
+  out.extend(s.as_bytes());
+}
+
+fn put_ast(out: &mut Vec<u8>, ast: &Ast) {
+  put_len(out, ast.values.len());
+  for value in &ast.values {
+    put_val(out, value);
+  }
+  put_len(out, ast.elems.len());
+  for id in &ast.elems {
+    put_u32(out, id.0);
+  }
+  put_len(out, ast.nodes.len());
+  for node in &ast.nodes {
+    put_node(out, node);
+  }
+  put_node(out, &ast.root);
+}
+
+fn put_node(out: &mut Vec<u8>, node: &Node) {
+  let mut ids = |tag: u8, ids: &[u32]| {
+    out.push(tag);
+    for id in ids {
+      put_u32(out, *id);
+    }
+  };
+  match *node {
+    Node::Zero{} => ids(0, &[]),
+    Node::Leaf{value} => ids(1, &[value.0]),
+    Node::Sym{value} => ids(2, &[value.0]),
+    Node::BinOp{op, lhs, rhs} => ids(3, &[op as u32, lhs.0, rhs.0]),
+    Node::CustomOp{op, prec, assoc, lhs, rhs} => ids(4, &[op.0, prec as u32, assoc as u32, lhs.0, rhs.0]),
+    Node::UniOp{op, rhs} => ids(5, &[op as u32, rhs.0]),
+    Node::Index{row, col} => ids(6, &[row.0, col.0]),
+    Node::Addr{row, col} => ids(7, &[row.0, col.0]),
+    Node::Call{func, args} => ids(8, &[func.0, args.0]),
+    Node::List{elems} => ids(9, &[elems.start, elems.len]),
+    Node::Let{binds, body} => ids(10, &[binds.start, binds.len, body.0]),
+    Node::Lambda{params, body} => ids(11, &[params.start, params.len, body.0]),
+    Node::Field{key, value} => ids(12, &[key.0, value.0]),
+    Node::Slice{start, end} => ids(13, &[start.0, end.0]),
+    Node::TileRef{tile, cell} => ids(14, &[tile.0, cell.0]),
+    Node::If{cond, then, other} => ids(15, &[cond.0, then.0, other.0]),
+    Node::Match{subject, arms, other} => ids(16, &[subject.0, arms.start, arms.len, other.0]),
+  }
+}
+
+fn put_val(out: &mut Vec<u8>, value: &Val) {
+  let put_vals = |out: &mut Vec<u8>, vals: &[Val]| {
+    put_len(out, vals.len());
+    for val in vals {
+      put_val(out, val);
+    }
+  };
+  let put_axes = |out: &mut Vec<u8>, axes: &[u32]| {
+    put_len(out, axes.len());
+    for axis in axes {
+      put_u32(out, *axis);
+    }
+  };
+  match value {
+    Val::Num(d) => {
+      // the scale, then the mantissa zigzag encoded so small negatives stay short
+      let m = d.mantissa();
+      out.extend([0, d.scale() as u8]);
+      put_varint(out, ((m << 1) ^ (m >> 127)) as u128);
+    },
+    Val::Bool(b) => out.extend([1, *b as u8]),
+    Val::Float(f) => {
+      out.push(2);
+      out.extend(f.to_le_bytes());
+    },
+    Val::Int(i) => {
+      out.push(3);
+      out.extend(i.to_le_bytes());
+    },
+    Val::Str(s) => {
+      out.push(4);
+      put_str(out, s);
+    },
+    Val::List(vals) => {
+      out.push(5);
+      put_vals(out, vals);
+    },
+    Val::Array{elems, axes} => {
+      out.push(6);
+      put_axes(out, axes);
+      put_vals(out, elems);
+    },
+    Val::Record{value, fields} => {
+      out.push(7);
+      put_u32(out, *fields);
+      put_vals(out, value);
+    },
+    Val::Lambda(closure) => {
+      out.push(8);
+      put_len(out, closure.params().len());
+      for param in closure.params() {
+        put_str(out, param);
+      }
+      put_ast(out, closure.body());
+      let bindings = closure.env().bindings();
+      put_len(out, bindings.len());
+      for (name, val) in bindings.iter().rev() {
+        put_str(out, name);
+        put_val(out, val);
+      }
+    },
+    Val::OrderedMap(map) => {
+      out.push(9);
+      put_len(out, map.len());
+      for (key, val) in map {
+        put_str(out, key);
+        put_val(out, val);
+      }
+    },
+    Val::SparseArray{fill, axes, elems} => {
+      out.push(10);
+      put_val(out, fill);
+      put_axes(out, axes);
+      put_len(out, elems.len());
+      for (index, val) in elems {
+        put_u32(out, *index);
+        put_val(out, val);
+      }
+    },
+    Val::Error{kind, origin} => {
+      out.extend([11, *kind as u8]);
+      put_str(out, origin);
+    },
+    Val::Complex(z) => {
+      out.push(12);
+      out.extend(z.re.to_le_bytes());
+      out.extend(z.im.to_le_bytes());
+    },
+  }
+}
+
+struct Reader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+  depth: u32,
+}
+
+impl<'a> Reader<'a> {
+  fn corrupt<T>(&self) -> Result<T, Err> {
+    Err(Err::Corrupt{ pos: self.pos })
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], Err> {
+    match self.bytes.get(self.pos..self.pos.saturating_add(len)) {
+      Some(bytes) => {
+        self.pos += len;
+        Ok(bytes)
+      },
+      None => self.corrupt(),
+    }
+  }
+
+  fn byte(&mut self) -> Result<u8, Err> {
+    Ok(self.take(1)?[0])
+  }
+
+  /// Reads a varint of at most `bits` bits.
+  fn varint(&mut self, bits: u32) -> Result<u128, Err> {
+    let start = self.pos;
+    let mut n: u128 = 0;
+    for shift in (0..bits).step_by(7) {
+      let b = self.byte()?;
+      let part = (b & 0x7f) as u128;
+      if shift + 7 > bits && part >> (bits - shift) != 0 {
+        return Err(Err::Corrupt{ pos: start });
+      }
+      n |= part << shift;
+      if b & 0x80 == 0 {
+        return Ok(n);
+      }
+    }
+    Err(Err::Corrupt{ pos: start })
+  }
+
+  fn u32(&mut self) -> Result<u32, Err> {
+    Ok(self.varint(32)? as u32)
+  }
+
+  /// Reads a count of items, each taking at least one byte, so corrupt
+  /// counts cannot reserve more memory than the input justifies.
+  fn len(&mut self) -> Result<usize, Err> {
+    let len = self.u32()? as usize;
+    match len <= self.bytes.len() - self.pos {
+      true => Ok(len),
+      false => self.corrupt(),
+    }
+  }
+
+  fn str(&mut self) -> Result<String, Err> {
+    let len = self.len()?;
+    let start = self.pos;
+    match std::str::from_utf8(self.take(len)?) {
+      Ok(s) => Ok(s.to_owned()),
+      Err(_) => Err(Err::Corrupt{ pos: start }),
+    }
+  }
+
+  fn char(&mut self) -> Result<char, Err> {
+    let start = self.pos;
+    char::from_u32(self.u32()?).ok_or(Err::Corrupt{ pos: start })
+  }
+
+  /// Runs `read` one level deeper, rejecting input nested past `MAX_DEPTH`.
+  fn nested<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T, Err>) -> Result<T, Err> {
+    if self.depth == MAX_DEPTH {
+      return self.corrupt();
+    }
+    self.depth += 1;
+    let res = read(self);
+    self.depth -= 1;
+    res
+  }
+
+  fn ast(&mut self) -> Result<Ast, Err> {
+    let len = self.len()?;
+    let values = (0..len).map(|_|self.val()).collect::<Result<Vec<Val>, Err>>()?;
+    let len = self.len()?;
+    let elems = (0..len).map(|_|self.u32().map(NodeId)).collect::<Result<Vec<NodeId>, Err>>()?;
+
+    let mut ast = Ast{ root: Node::default(), nodes: vec![], values, elems };
+    let len = self.len()?;
+    for _ in 0..len {
+      let start = self.pos;
+      let node = self.node()?;
+      // the Zero node comes first, and every other node links backwards
+      let valid = match ast.nodes.len() {
+        0 => node == Node::Zero{},
+        at => valid_links(&ast, &node, at as u32),
+      };
+      if !valid {
+        return Err(Err::Corrupt{ pos: start });
+      }
+      ast.nodes.push(node);
+    }
+    if ast.elems.iter().any(|e|e.0 as usize >= ast.nodes.len()) {
+      return self.corrupt();
+    }
+    let start = self.pos;
+    ast.root = self.node()?;
+    if ast.nodes.is_empty() || ast.values.is_empty() || !valid_links(&ast, &ast.root, ast.nodes.len() as u32) {
+      return Err(Err::Corrupt{ pos: start });
+    }
+    Ok(ast)
+  }
+
+  fn node(&mut self) -> Result<Node, Err> {
+    let start = self.pos;
+    let node = match self.byte()? {
+      0 => Node::Zero{},
+      1 => Node::Leaf{ value: ValueId(self.u32()?) },
+      2 => Node::Sym{ value: ValueId(self.u32()?) },
+      3 => match self.char()? {
+        op if binop_prec(op).is_some() => Node::BinOp{ op, lhs: NodeId(self.u32()?), rhs: NodeId(self.u32()?) },
+        _ => return Err(Err::Corrupt{ pos: start }),
+      },
+      4 => {
+        let op = ValueId(self.u32()?);
+        let prec = match u8::try_from(self.u32()?) {
+          Ok(prec) if (1..=MAX_OP_PREC).contains(&prec) => prec,
+          _ => return Err(Err::Corrupt{ pos: start }),
+        };
+        let assoc = match self.u32()? {
+          0 => Assoc::Left,
+          1 => Assoc::Right,
+          _ => return Err(Err::Corrupt{ pos: start }),
+        };
+        Node::CustomOp{ op, prec, assoc, lhs: NodeId(self.u32()?), rhs: NodeId(self.u32()?) }
+      },
+      5 => Node::UniOp{ op: self.char()?, rhs: NodeId(self.u32()?) },
+      6 => Node::Index{ row: NodeId(self.u32()?), col: NodeId(self.u32()?) },
+      7 => Node::Addr{ row: NodeId(self.u32()?), col: NodeId(self.u32()?) },
+      8 => Node::Call{ func: NodeId(self.u32()?), args: NodeId(self.u32()?) },
+      9 => Node::List{ elems: self.elem_range()? },
+      10 => Node::Let{ binds: self.elem_range()?, body: NodeId(self.u32()?) },
+      11 => Node::Lambda{ params: self.elem_range()?, body: NodeId(self.u32()?) },
+      12 => Node::Field{ key: NodeId(self.u32()?), value: NodeId(self.u32()?) },
+      13 => Node::Slice{ start: NodeId(self.u32()?), end: NodeId(self.u32()?) },
+      14 => Node::TileRef{ tile: NodeId(self.u32()?), cell: NodeId(self.u32()?) },
+      15 => Node::If{ cond: NodeId(self.u32()?), then: NodeId(self.u32()?), other: NodeId(self.u32()?) },
+      16 => Node::Match{ subject: NodeId(self.u32()?), arms: self.elem_range()?, other: NodeId(self.u32()?) },
+      _ => return Err(Err::Corrupt{ pos: start }),
+    };
+    Ok(node)
+  }
+
+  fn elem_range(&mut self) -> Result<ElemRange, Err> {
+    Ok(ElemRange{ start: self.u32()?, len: self.u32()? })
+  }
+
+  fn vals(&mut self) -> Result<Vec<Val>, Err> {
+    let len = self.len()?;
+    (0..len).map(|_|self.val()).collect()
+  }
+
+  fn axes(&mut self) -> Result<Vec<u32>, Err> {
+    let len = self.len()?;
+    (0..len).map(|_|self.u32()).collect()
+  }
+
+  fn val(&mut self) -> Result<Val, Err> {
+    let start = self.pos;
+    let val = match self.byte()? {
+      0 => {
+        let scale = self.byte()? as u32;
+        let zigzag = self.varint(128)?;
+        let mantissa = (zigzag >> 1) as i128 ^ -((zigzag & 1) as i128);
+        match Decimal::try_from_i128_with_scale(mantissa, scale) {
+          Ok(d) => Val::Num(d),
+          Err(_) => return Err(Err::Corrupt{ pos: start }),
+        }
+      },
+      1 => match self.byte()? {
+        0 => Val::Bool(false),
+        1 => Val::Bool(true),
+        _ => return Err(Err::Corrupt{ pos: start }),
+      },
+      2 => Val::Float(f64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+      3 => Val::Int(i64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+      4 => Val::Str(self.str()?),
+      5 => Val::List(self.nested(|r|r.vals())?),
+      6 => {
+        let axes = self.axes()?;
+        Val::Array{ axes, elems: self.nested(|r|r.vals())? }
+      },
+      7 => {
+        let fields = self.u32()?;
+        Val::Record{ fields, value: self.nested(|r|r.vals())? }
+      },
+      8 => self.nested(|r|r.lambda())?,
+      9 => {
+        let len = self.len()?;
+        let map = self.nested(|r|(0..len).map(|_|Ok((r.str()?, r.val()?))).collect::<Result<Vec<(String, Val)>, Err>>())?;
+        Val::OrderedMap(map)
+      },
+      10 => {
+        let fill = Box::new(self.nested(|r|r.val())?);
+        let axes = self.axes()?;
+        let len = self.len()?;
+        let elems = self.nested(|r|(0..len).map(|_|Ok((r.u32()?, r.val()?))).collect::<Result<BTreeMap<u32, Val>, Err>>())?;
+        Val::SparseArray{ fill, axes, elems }
+      },
+      11 => {
+        let kind = match self.byte()? {
+          0 => ErrorKind::Type,
+          1 => ErrorKind::Ref,
+          2 => ErrorKind::Div0,
+          3 => ErrorKind::Parse,
+          4 => ErrorKind::Cycle,
+          5 => ErrorKind::Num,
+          6 => ErrorKind::Shape,
+          7 => ErrorKind::Singular,
+          8 => ErrorKind::NoMatch,
+          _ => return Err(Err::Corrupt{ pos: start }),
+        };
+        Val::Error{ kind, origin: self.str()? }
+      },
+      12 => {
+        let re = f64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        Val::Complex(Complex::new(re, f64::from_le_bytes(self.take(8)?.try_into().unwrap())))
+      },
+      _ => return Err(Err::Corrupt{ pos: start }),
+    };
+    Ok(val)
+  }
+
+  fn lambda(&mut self) -> Result<Val, Err> {
+    let len = self.len()?;
+    let params = (0..len).map(|_|self.str()).collect::<Result<Vec<String>, Err>>()?;
+    let body = self.ast()?;
+    let len = self.len()?;
+    let mut env = Env::default();
+    for _ in 0..len {
+      let name = self.str()?;
+      env = env.bind(name, self.val()?);
+    }
+    Ok(Val::Lambda(Arc::new(Closure::from_ast(params, body, env))))
+  }
+}
+
+/// Checks that the links of `node` stay inside the arenas of `ast`, with
+/// node ids below `limit`.
+fn valid_links(ast: &Ast, node: &Node, limit: u32) -> bool {
+  let id = |id: &NodeId|id.0 < limit;
+  let value = |value: &ValueId|(value.0 as usize) < ast.values.len();
+  let elems = |elems: &ElemRange| {
+    let end = elems.start as usize + elems.len as usize;
+    end <= ast.elems.len() && ast.elems[elems.start as usize..end].iter().all(|e|e.0 != 0 && e.0 < limit)
+  };
+  match node {
+    Node::Zero{} => true,
+    Node::Leaf{value: v} | Node::Sym{value: v} => value(v),
+    Node::BinOp{lhs, rhs, ..} => id(lhs) && id(rhs),
+    Node::CustomOp{op, lhs, rhs, ..} => value(op) && id(lhs) && id(rhs),
+    Node::UniOp{rhs, ..} => id(rhs),
+    Node::Index{row, col} | Node::Addr{row, col} => id(row) && id(col),
+    Node::Call{func, args} => id(func) && id(args),
+    Node::List{elems: e} => elems(e),
+    Node::Let{binds: e, body} | Node::Lambda{params: e, body} => elems(e) && id(body),
+    Node::Field{key, value} => id(key) && id(value),
+    Node::Slice{start, end} => id(start) && id(end),
+    Node::TileRef{tile, cell} => id(tile) && id(cell),
+    Node::If{cond, then, other} => id(cond) && id(then) && id(other),
+    Node::Match{subject, arms, other} => id(subject) && elems(arms) && id(other),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_decimal_macros::dec;
+  use crate::parser::Parser;
+
+  fn parse(src: &str) -> Ast {
+    let mut p = Parser::new(src);
+    let root = p.parse().unwrap();
+    Ast::new(&p, &root)
+  }
+
+  #[test]
+  fn test_ast_round_trip() {
+    for src in [
+      "1 + 2 * 3",
+      "sum(1, [0, 1], {2, $B}) - 'total'",
+      "let x = 2 in map((1, 2), y -> x * y)",
+      "OrderedMap(b: 5; a: 10)",
+      "[1]",
+      "&Units{[:], Rent} * &2[0:3, $1:]",
+      "let b = (x in xs) in b ++ (1, 2) union y inter z ** 2",
+      "if a and not b 1 else match c as 1 'one' as 2 'two'",
+    ] {
+      let ast = parse(src);
+      let bytes = ast.encode();
+      let decoded = Ast::decode(&bytes).unwrap();
+      assert_eq!(ast.root().print(&ast), decoded.root().print(&decoded), "{src}");
+      assert_eq!(bytes, decoded.encode(), "{src}");
+    }
+
+    // backtracking leaves unreachable nodes in the parser, not the tree
+    let mut p = Parser::new("sum(sum(sum(1)))");
+    let root = p.parse().unwrap();
+    let ast = Ast::new(&p, &root);
+    assert_eq!(11, ast.nodes().count());
+    assert!(ast.encode().len() <= 64);
+  }
+
+  #[test]
+  fn test_ast_values() {
+    let mut p = Parser::new("(a, b) -> a + b + c");
+    let closure = match p.parse().unwrap().eval(&mut p) {
+      Val::Lambda(closure) => closure,
+      val => panic!("expected lambda, got {val:?}"),
+    };
+    let env = Env::default().bind("c".to_owned(), Val::Num(dec!(1)));
+    let lambda = Val::Lambda(Arc::new(Closure::from_ast(closure.params().to_vec(), closure.body().clone(), env)));
+
+    let values = vec![
+      Val::Num(dec!(-12.50)),
+      Val::Num(Decimal::MAX),
+      Val::Num(Decimal::MIN),
+      Val::Bool(true),
+      Val::Float(0.25),
+      Val::Int(-7),
+      Val::Str("héllo".to_owned()),
+      Val::List(vec![Val::Num(dec!(1)), Val::List(vec![])]),
+      Val::Array{ elems: vec![Val::Int(1), Val::Int(2)], axes: vec![2] },
+      Val::Record{ value: vec![Val::Str("a".to_owned()), Val::Int(1)], fields: 1 },
+      Val::OrderedMap(vec![("b".to_owned(), Val::Int(1)), ("a".to_owned(), Val::Int(2))]),
+      Val::SparseArray{ fill: Box::new(Val::Int(0)), axes: vec![10, 10], elems: BTreeMap::from([(42, Val::Int(5))]) },
+      Val::error(ErrorKind::Div0, "1 / 0"),
+      Val::Complex(Complex::new(-1.5, 2.0)),
+      lambda,
+    ];
+    let ast = Ast{ root: Node::Zero{}, nodes: vec![Node::Zero{}], values: values.clone(), elems: vec![] };
+    let decoded = Ast::decode(&ast.encode()).unwrap();
+    assert_eq!(values[..14], decoded.values[..14]);
+
+    let closure = match &decoded.values[14] {
+      Val::Lambda(closure) => closure.clone(),
+      val => panic!("expected lambda, got {val:?}"),
+    };
+    assert_eq!("(a, b) -> a + b + c", closure.to_string());
+    assert_eq!(Val::Num(dec!(4)), closure.call(&mut Parser::new(""), vec![Val::Num(dec!(1)), Val::Num(dec!(2))]));
+  }
+
+  #[test]
+  fn test_ast_decode_rejects() {
+    let bytes = parse("1 + [0, 1]").encode();
+
+    let mut version = bytes.clone();
+    version[4] = 9;
+    assert!(matches!(Ast::decode(&version), Err(Err::Version{found: 9})));
+    // trees saved before the last layout change are rejected too
+    version[4] = (VERSION - 1) as u8;
+    assert!(matches!(Ast::decode(&version), Err(Err::Version{found}) if found == VERSION - 1));
+    assert!(matches!(Ast::decode(b"nope"), Err(Err::Corrupt{pos: 0})));
+    assert!(matches!(Ast::decode(&[bytes.as_slice(), &[0]].concat()), Err(Err::Corrupt{..})));
+    for len in 0..bytes.len() {
+      assert!(matches!(Ast::decode(&bytes[..len]), Err(Err::Corrupt{..})), "truncated to {len}");
+    }
+    // no input decodes to a tree with dangling links, whatever byte is corrupted
+    for at in 6..bytes.len() {
+      for b in [0, 1, 2, 5, 9, 0x7f, 0x80, 0xff] {
+        let mut corrupt = bytes.clone();
+        corrupt[at] = b;
+        if let Ok(ast) = Ast::decode(&corrupt) {
+          ast.root().print(&ast);
+        }
+      }
+    }
+
+    let ast = |nodes: Vec<Node>, root: Node| Ast{ root, nodes, values: vec![Val::default()], elems: vec![NodeId(1)] };
+    let leaf = Node::Leaf{ value: ValueId(0) };
+    let valid = ast(vec![Node::Zero{}, leaf], Node::UniOp{ op: '$', rhs: NodeId(1) });
+    assert!(Ast::decode(&valid.encode()).is_ok());
+    for invalid in [
+      // out of range
+      ast(vec![Node::Zero{}, leaf], Node::UniOp{ op: '$', rhs: NodeId(2) }),
+      ast(vec![Node::Zero{}, Node::Leaf{ value: ValueId(1) }], Node::Zero{}),
+      ast(vec![Node::Zero{}, leaf], Node::List{ elems: ElemRange{ start: 0, len: 2 } }),
+      // loops
+      ast(vec![Node::Zero{}, Node::UniOp{ op: '$', rhs: NodeId(1) }], Node::Zero{}),
+      ast(vec![Node::Zero{}, Node::List{ elems: ElemRange{ start: 0, len: 1 } }], Node::Zero{}),
+      // missing Zero node
+      ast(vec![leaf], Node::Zero{}),
+      ast(vec![], Node::Zero{}),
+    ] {
+      assert!(matches!(Ast::decode(&invalid.encode()), Err(Err::Corrupt{..})), "{invalid:?}");
+    }
+  }
+}
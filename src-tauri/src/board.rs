@@ -1,19 +1,26 @@
 use std::collections::BTreeMap;
 use std::fmt::Debug;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
+use crate::err::Err;
+use crate::funcs::{FuncDef, Funcs, OpDef};
+use crate::parser::{Assoc, Parser};
+use crate::rounding::Rounding;
 use crate::rpc::TileUi;
 use crate::tile::Tile;
 use crate::tile::TileId;
-use crate::cell::{CellOps, Cell, CRef};
+use crate::cell::{CellOps, Val, Cell, CellId, CRef, ErrorKind};
 
-type TileMap<V> = BTreeMap<TileId, Tile<V>>;
+pub type TileMap<V> = BTreeMap<TileId, Tile<V>>;
 
 
 #[derive(Debug)]
 pub struct Board<V: CellOps = Cell> {
   next_tag: TileId,
   tiles: TileMap<V>,
+  funcs: Funcs,
+  rounding: Rounding,
 }
 
 impl Board {
@@ -37,6 +44,8 @@ impl<V: CellOps> Default for Board<V> {
     Board {
       next_tag: TileId::default(),
       tiles: TileMap::new(),
+      funcs: Funcs::default(),
+      rounding: Rounding::default(),
     }
   }
 }
@@ -65,12 +74,16 @@ impl<V: CellOps> Board<V> {
     self.tiles.get_mut(&tile).and_then(|tile|tile.update_cell(cref, f))
   }
 
+  pub fn tiles(&self) -> impl Iterator<Item = &Tile<V>> {
+    self.tiles.values()
+  }
+
   pub fn tile(&self, tag: TileId) -> &Tile<V> {
     return self.tiles.get(&tag).unwrap()
   }
 
   pub fn render_tile(&self, tag: TileId) -> TileUi {
-    return self.tiles.get(&tag).unwrap().render()
+    return self.tiles.get(&tag).unwrap().render(&self.rounding)
   }
 
   pub fn get_pos<const CARD: usize>(&self, tag: TileId, pos: [usize; CARD]) -> V {
@@ -101,21 +114,112 @@ impl<V: CellOps> Board<V> {
     };
   }
 
+  pub fn funcs(&self) -> &Funcs {
+    &self.funcs
+  }
+
+  /// Adds or replaces a board function, see `Funcs::define`.
+  pub fn define(&mut self, source: &str) -> Result<&FuncDef, Err> {
+    self.funcs.define(source)
+  }
+
+  pub fn undefine(&mut self, name: &str) -> Option<FuncDef> {
+    self.funcs.remove(name)
+  }
+
+  /// Adds or replaces a board operator, see `Funcs::define_op`.
+  pub fn define_op(&mut self, symbol: &str, prec: u8, assoc: Assoc, func: &str) -> Result<&OpDef, Err> {
+    self.funcs.define_op(symbol, prec, assoc, func)
+  }
+
+  pub fn undefine_op(&mut self, symbol: &str) -> Option<OpDef> {
+    self.funcs.remove_op(symbol)
+  }
+
+  pub fn rounding(&self) -> Rounding {
+    self.rounding
+  }
+
+  /// Sets how numbers are rounded. Cells keep their values, so formulas
+  /// must be evaluated again for a change to intermediate rounding to apply.
+  pub fn set_rounding(&mut self, rounding: Rounding) {
+    self.rounding = rounding;
+  }
+
   pub fn len(&self) -> usize {
     self.tiles.len()
   }
 
   pub fn render(&self) -> BoardUi {
     return BoardUi {
-      tiles: self.tiles.values().map(|t| { t.render() } ).collect(),
+      tiles: self.tiles.values().map(|t| { t.render(&self.rounding) } ).collect(),
     }
   }
 
 }
 
 impl Board<Cell> {
+  /// Rewrites formulas written when operators grouped from the right, see
+  /// `Parser::right_grouped`, with the parentheses that keep their meaning.
+  /// Values are kept, as they are the same. Formulas that don't parse are
+  /// left as they are.
+  pub fn migrate_right_grouped(&mut self) {
+    let ops = self.funcs.ops();
+    for tile in self.tiles.values_mut() {
+      let cells = tile.iter().filter(|(_, cell)| !cell.formula.is_empty()).map(|(id, _)| id).collect_vec();
+      for id in cells {
+        let cell = tile.get_cell_by_id(id);
+        let mut p = Parser::new(cell.formula.as_str()).with_ops(ops).right_grouped();
+        if let Some(node) = p.parse() {
+          let formula = node.print(&p);
+          tile.set_cell_by_id(id, Cell{ formula, ..cell });
+        }
+      }
+    }
+  }
+
+  /// Evaluates the cell's formula, then the formulas reading it, in its own
+  /// tile and in the others.
   pub fn eval_cell<const CARD: usize, R: CRef<CARD>>(&mut self, tileid: TileId, cref: R) -> Option<Cell> {
-    self.tiles.get_mut(&tileid).and_then(|tile|tile.eval_cell(tileid, cref))
+    let cellid = self.tiles.get(&tileid)?.resolve(cref);
+    self.eval_tile_cell(tileid, cellid)?;
+    self.eval_readers(&mut vec![(tileid, cellid)]);
+    Some(self.tile(tileid).get_cell_by_id(cellid))
+  }
+
+  fn eval_tile_cell(&mut self, tileid: TileId, cellid: CellId) -> Option<Cell> {
+    // the tile is taken out while evaluating, so its formulas can read the others
+    let mut tile = self.tiles.remove(&tileid)?;
+    let res = tile.eval_cell(tileid, cellid, &self.tiles, &self.funcs, self.rounding);
+    self.tiles.insert(tileid, tile);
+    res
+  }
+
+  /// Evaluates the cells of other tiles reading the last cell of `path`, or
+  /// the cells depending on it, and in turn the cells reading those.
+  fn eval_readers(&mut self, path: &mut Vec<(TileId, CellId)>) {
+    let (source, cell) = *path.last().unwrap();
+    let cells = self.tile(source).downstream(cell);
+    let readers = self.tiles.values()
+      .flat_map(|tile| tile.readers(source, &cells).into_iter().map(|id| (tile.tag, id)))
+      .collect_vec();
+    for reader in readers {
+      match path.iter().position(|cell| *cell == reader) {
+        // a formula reading its own value through other tiles
+        Some(start) => {
+          let origin = path[start..].iter().map(|(tag, id)| self.tile(*tag).label(*id)).join(", ");
+          for (tag, id) in &path[start..] {
+            self.update_cell(*tag, *id, |cell| Cell{ value: Val::error(ErrorKind::Cycle, origin.clone()), ..cell });
+          }
+        },
+        None => {
+          self.eval_tile_cell(reader.0, reader.1);
+          path.push(reader);
+          self.eval_readers(path);
+          path.pop();
+        },
+      }
+    }
   }
 }
 
@@ -125,3 +229,65 @@ impl Board<Cell> {
 pub struct BoardUi {
   tiles: Vec<TileUi>,
 }
+
+
+#[cfg(test)]
+mod tests {
+  use rust_decimal_macros::dec;
+  use super::*;
+
+  #[test]
+  fn test_board_migrate_right_grouped() {
+    let (mut board, tag) = Board::example();
+    for (col, formula) in ["1 - 2 - 3", "[0, 1] * [0, 0] + 1", "1 +"].into_iter().enumerate() {
+      board.update_cell(tag, [col, 3], |cell| Cell{ formula: formula.to_owned(), ..cell });
+    }
+    board.migrate_right_grouped();
+    let formula = |board: &Board, col: usize| board.get_pos(tag, [col, 3]).formula;
+    assert_eq!(formula(&board, 0), "1 - (2 - 3)");
+    assert_eq!(formula(&board, 1), "[0, 1] * ([0, 0] + 1)");
+    assert_eq!(formula(&board, 2), "1 +");
+    // and evaluate as they did before
+    assert_eq!(board.eval_cell(tag, [0, 3]).unwrap().value, Val::Num(dec!(2)));
+    assert_eq!(board.eval_cell(tag, [1, 3]).unwrap().value, Val::Num(dec!(52.5)));
+  }
+
+  #[test]
+  fn test_board_eval_other_tiles() {
+    let mut board = Board::<Cell>::default();
+    let model = board.add_tile();
+    let rates = board.add_tile();
+    let report = board.add_tile();
+    board.mut_tile(rates).unwrap().name = "Rates".to_owned();
+
+    let eval = |board: &mut Board<Cell>, tag: TileId, pos: [usize; 2], formula: &str| {
+      board.update_cell(tag, pos, |cell| Cell{ formula: formula.to_owned(), ..cell });
+      board.eval_cell(tag, pos).unwrap().value
+    };
+    let get = |board: &Board<Cell>, tag: TileId, pos: [usize; 2]| board.get_pos(tag, pos).value;
+
+    eval(&mut board, rates, [0, 0], "2");
+    assert_eq!(eval(&mut board, model, [0, 0], "&Rates[0, 0] * 3"), Val::Num(dec!(6)));
+    eval(&mut board, model, [1, 0], "[0, 0] + 1");
+    assert_eq!(eval(&mut board, report, [0, 0], "&0[1, 0] * 10"), Val::Num(dec!(70)));
+
+    // editing the source updates the cells reading it, the cells depending
+    // on those in their tile, and the cells of other tiles reading those
+    eval(&mut board, rates, [0, 0], "5");
+    assert_eq!(get(&board, model, [0, 0]), Val::Num(dec!(15)));
+    assert_eq!(get(&board, model, [1, 0]), Val::Num(dec!(16)));
+    assert_eq!(get(&board, report, [0, 0]), Val::Num(dec!(160)));
+
+    // a formula no longer reading the other tile isn't updated by it
+    eval(&mut board, model, [0, 0], "1");
+    eval(&mut board, rates, [0, 0], "7");
+    assert_eq!(get(&board, model, [0, 0]), Val::Num(dec!(1)));
+    assert_eq!(get(&board, report, [0, 0]), Val::Num(dec!(20)));
+
+    eval(&mut board, rates, [1, 0], "&0[2, 0]");
+    assert_eq!(eval(&mut board, model, [2, 0], "&Rates[1, 0] + 1"), Val::error(ErrorKind::Cycle, "C1, B1"));
+    assert_eq!(get(&board, rates, [1, 0]), Val::error(ErrorKind::Cycle, "C1, B1"));
+    assert_eq!(eval(&mut board, model, [2, 0], "1"), Val::Num(dec!(1)));
+    assert_eq!(get(&board, rates, [1, 0]), Val::Num(dec!(1)));
+  }
+}
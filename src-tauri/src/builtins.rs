@@ -0,0 +1,766 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+
+use crate::cell::{ErrorKind, Val};
+use crate::eval::condition;
+use crate::linalg::{self, Operand};
+use crate::rounding::{self, Rounding};
+
+/// Calls a function value with arguments, see `closure::apply`.
+pub type Apply<'a> = dyn FnMut(&Val, Vec<Val>) -> Val + 'a;
+
+#[derive(Clone, Copy)]
+pub enum Func {
+  Plain(fn(Vec<Val>) -> Val),
+  /// Takes function arguments, called back through `Apply`.
+  Higher(fn(Vec<Val>, &mut Apply) -> Val),
+  /// Rounds numbers by the board's policy.
+  Rounding(fn(Vec<Val>, Rounding) -> Val),
+}
+
+/// A function callable from formulas, e.g. `sum(1, 2, 3)`.
+pub struct Builtin {
+  pub name: &'static str,
+  pub sig: &'static str,
+  pub doc: &'static str,
+  pub func: Func,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+  Builtin {
+    name: "abs",
+    sig: "abs(x)",
+    doc: "Absolute value of a number, or magnitude of a complex one",
+    func: Func::Plain(abs),
+  },
+  Builtin {
+    name: "Array",
+    sig: "Array(values...)",
+    doc: "One dimensional array of values, or the empty array",
+    func: Func::Plain(array),
+  },
+  Builtin {
+    name: "avg",
+    sig: "avg(values...)",
+    doc: "Mean of the numbers in values",
+    func: Func::Plain(avg),
+  },
+  Builtin {
+    name: "det",
+    sig: "det(matrix)",
+    doc: "Determinant of a square matrix",
+    func: Func::Plain(det),
+  },
+  Builtin {
+    name: "filter",
+    sig: "filter(values, f)",
+    doc: "Elements of values for which f(x) is true",
+    func: Func::Higher(filter),
+  },
+  Builtin {
+    name: "inverse",
+    sig: "inverse(matrix)",
+    doc: "Inverse of a square matrix",
+    func: Func::Plain(inverse),
+  },
+  Builtin {
+    name: "len",
+    sig: "len(collection)",
+    doc: "Number of elements in a collection, or characters in a string",
+    func: Func::Plain(len),
+  },
+  Builtin {
+    name: "List",
+    sig: "List(values...)",
+    doc: "List of values, or the empty list",
+    func: Func::Plain(list),
+  },
+  Builtin {
+    name: "magnitude",
+    sig: "magnitude(z)",
+    doc: "Distance of a complex number from zero, the absolute value of a real one",
+    func: Func::Plain(magnitude),
+  },
+  Builtin {
+    name: "map",
+    sig: "map(values, f)",
+    doc: "Applies f to each element of values",
+    func: Func::Higher(map),
+  },
+  Builtin {
+    name: "matmul",
+    sig: "matmul(a, b)",
+    doc: "Matrix product of a and b, the same as a * b",
+    func: Func::Plain(matmul),
+  },
+  Builtin {
+    name: "max",
+    sig: "max(values...)",
+    doc: "Largest of the numbers in values",
+    func: Func::Plain(max),
+  },
+  Builtin {
+    name: "min",
+    sig: "min(values...)",
+    doc: "Smallest of the numbers in values",
+    func: Func::Plain(min),
+  },
+  Builtin {
+    name: "OrderedMap",
+    sig: "OrderedMap(key: value...)",
+    doc: "Map of values ordered by key",
+    func: Func::Plain(ordered_map),
+  },
+  Builtin {
+    name: "phase",
+    sig: "phase(z)",
+    doc: "Angle of a complex number from the positive real axis, in radians",
+    func: Func::Plain(phase),
+  },
+  Builtin {
+    name: "mround",
+    sig: "mround(x, multiple)",
+    doc: "x rounded to the nearest multiple, halves rounded as the board rounds them",
+    func: Func::Rounding(mround),
+  },
+  Builtin {
+    name: "reduce",
+    sig: "reduce(values, init, f)",
+    doc: "Combines the elements of values with f(acc, x), starting from init",
+    func: Func::Higher(reduce),
+  },
+  Builtin {
+    name: "round",
+    sig: "round(x, places)",
+    doc: "x rounded to a number of decimal places, by default the board's, halves rounded as the board rounds them",
+    func: Func::Rounding(round),
+  },
+  Builtin {
+    name: "rounddown",
+    sig: "rounddown(x, places)",
+    doc: "x rounded toward zero to a number of decimal places, by default the board's",
+    func: Func::Rounding(rounddown),
+  },
+  Builtin {
+    name: "roundup",
+    sig: "roundup(x, places)",
+    doc: "x rounded away from zero to a number of decimal places, by default the board's",
+    func: Func::Rounding(roundup),
+  },
+  Builtin {
+    name: "solve",
+    sig: "solve(a, b)",
+    doc: "Solution x of the linear system a * x = b",
+    func: Func::Plain(solve),
+  },
+  Builtin {
+    name: "SparseArray.fill",
+    sig: "SparseArray.fill(value, shape)",
+    doc: "Array of the given shape with every element set to value, storing only the elements that differ",
+    func: Func::Plain(sparse_fill),
+  },
+  Builtin {
+    name: "SparseArray.get",
+    sig: "SparseArray.get(array, index)",
+    doc: "Element of a sparse array at index, one position per axis",
+    func: Func::Plain(sparse_get),
+  },
+  Builtin {
+    name: "SparseArray.set",
+    sig: "SparseArray.set(array, index, value)",
+    doc: "Sparse array with the element at index replaced by value",
+    func: Func::Plain(sparse_set),
+  },
+  Builtin {
+    name: "sum",
+    sig: "sum(values...)",
+    doc: "Sum of the numbers in values",
+    func: Func::Plain(sum),
+  },
+  Builtin {
+    name: "transpose",
+    sig: "transpose(matrix)",
+    doc: "Rows of the matrix as columns. A vector becomes a single column",
+    func: Func::Plain(transpose),
+  },
+];
+
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+  BUILTINS.iter().find(|b| b.name == name)
+}
+
+/// Flattens list arguments into their numeric elements, or the first error
+/// among them. Floats without a decimal value, `NaN`, are a `Num` error.
+///
+/// Each number comes with how many elements it stands for, so that the
+/// fill of a sparse array counts once for all its unset elements rather
+/// than being expanded.
+fn numbers(args: Vec<Val>) -> Result<Vec<(Decimal, u64)>, Val> {
+  let mut res = vec![];
+  for arg in args {
+    match arg {
+      Val::List(elems) | Val::Array { elems, axes: _ } => res.extend(numbers(elems)?),
+      Val::OrderedMap(map) => res.extend(numbers(map.into_iter().map(|(_, v)| v).collect())?),
+      Val::SparseArray { fill, axes, elems } => {
+        let unset = axes.iter().map(|&axis| axis as u64).product::<u64>() - elems.len() as u64;
+        res.extend(numbers(elems.into_values().collect())?);
+        if unset > 0 {
+          res.extend(numbers(vec![*fill])?.into_iter().map(|(n, count)| (n, count * unset)));
+        }
+      },
+      err @ Val::Error { .. } => return Err(err),
+      Val::Float(f) => res.push((Decimal::from_f64(f).ok_or_else(|| Val::error(ErrorKind::Num, Val::Float(f).to_string()))?, 1)),
+      Val::Complex(z) => return Err(Val::error(ErrorKind::Type, z.to_string())),
+      v => res.push((Decimal::from(&v), 1)),
+    }
+  }
+  Ok(res)
+}
+
+fn list(args: Vec<Val>) -> Val {
+  Val::List(args)
+}
+
+fn array(args: Vec<Val>) -> Val {
+  Val::Array { axes: vec![args.len() as u32], elems: args }
+}
+
+/// Collects record fields and maps into one map, keys in the order they
+/// are first given. Later values win, keeping the key's place.
+fn ordered_map(args: Vec<Val>) -> Val {
+  let mut map: Vec<(String, Val)> = vec![];
+  let mut insert = |key: String, val: Val| match map.iter_mut().find(|(k, _)| *k == key) {
+    Some(entry) => entry.1 = val,
+    None => map.push((key, val)),
+  };
+  for arg in args {
+    match arg {
+      Val::Record { value, fields: _ } => {
+        for pair in value.chunks_exact(2) {
+          insert(pair[0].to_string(), pair[1].clone());
+        }
+      },
+      Val::OrderedMap(other) => other.into_iter().for_each(|(key, val)| insert(key, val)),
+      _ => (),
+    }
+  }
+  Val::OrderedMap(map)
+}
+
+/// Row-major position of `index` in an array of shape `axes`.
+fn flat_index(axes: &[u32], index: Val) -> Option<u32> {
+  let index: Vec<Val> = elements(index).collect();
+  if index.len() != axes.len() {
+    return None;
+  }
+  let mut flat: u32 = 0;
+  for (i, axis) in index.into_iter().zip(axes) {
+    let i = u32::try_from(i64::from(i)).ok().filter(|i| i < axis)?;
+    flat = flat * axis + i;
+  }
+  Some(flat)
+}
+
+fn sparse_fill(args: Vec<Val>) -> Val {
+  let mut args = args.into_iter();
+  let fill = args.next().unwrap_or_default();
+  let axes: Option<Vec<u32>> = elements(args.next().unwrap_or_default())
+    .map(|axis| u32::try_from(i64::from(axis)).ok())
+    .collect();
+  // every element must have a u32 index
+  match axes {
+    Some(axes) if axes.iter().try_fold(1u32, |n, &axis| n.checked_mul(axis)).is_some() =>
+      Val::SparseArray { fill: Box::new(fill), axes, elems: BTreeMap::new() },
+    _ => Val::error(ErrorKind::Num, "SparseArray.fill"),
+  }
+}
+
+/// The element at an index of a sparse array, `Ref` outside it.
+fn sparse_get(args: Vec<Val>) -> Val {
+  let mut args = args.into_iter();
+  match (args.next(), args.next()) {
+    (Some(err @ Val::Error { .. }), _) | (_, Some(err @ Val::Error { .. })) => err,
+    (Some(Val::SparseArray { fill, axes, elems }), Some(index)) => match flat_index(&axes, index) {
+      Some(i) => elems.get(&i).cloned().unwrap_or(*fill),
+      None => Val::error(ErrorKind::Ref, "SparseArray.get"),
+    },
+    _ => Val::error(ErrorKind::Type, "SparseArray.get"),
+  }
+}
+
+/// A sparse array with the element at an index replaced, `Ref` outside it.
+fn sparse_set(args: Vec<Val>) -> Val {
+  let mut args = args.into_iter();
+  let (array, index, value) = (args.next(), args.next(), args.next().unwrap_or_default());
+  match (array, index) {
+    (Some(err @ Val::Error { .. }), _) | (_, Some(err @ Val::Error { .. })) => err,
+    (Some(Val::SparseArray { fill, axes, mut elems }), Some(index)) => {
+      let Some(i) = flat_index(&axes, index) else {
+        return Val::error(ErrorKind::Ref, "SparseArray.set");
+      };
+      if value == *fill {
+        elems.remove(&i);
+      } else {
+        elems.insert(i, value);
+      }
+      Val::SparseArray { fill, axes, elems }
+    },
+    _ => Val::error(ErrorKind::Type, "SparseArray.set"),
+  }
+}
+
+fn abs(args: Vec<Val>) -> Val {
+  match args.first() {
+    Some(Val::Complex(z)) => Val::Float(z.magnitude()),
+    _ => numbers(args).map_or_else(|err| err, |nums| Val::Num(nums.first().map_or_else(Decimal::default, |(n, _)| n.abs()))),
+  }
+}
+
+fn magnitude(args: Vec<Val>) -> Val {
+  abs(args)
+}
+
+fn phase(args: Vec<Val>) -> Val {
+  match args.first() {
+    Some(Val::Complex(z)) => Val::Float(z.phase()),
+    _ => match numbers(args) {
+      Ok(nums) if nums.first().is_some_and(|(n, _)| n.is_sign_negative() && !n.is_zero()) => Val::Float(std::f64::consts::PI),
+      Ok(_) => Val::Float(0.0),
+      Err(err) => err,
+    },
+  }
+}
+
+fn avg(args: Vec<Val>) -> Val {
+  let nums = match numbers(args) {
+    Ok(nums) => nums,
+    Err(err) => return err,
+  };
+  let count: u64 = nums.iter().map(|(_, count)| count).sum();
+  if count == 0 {
+    return Val::error(ErrorKind::Div0, "avg");
+  }
+  match total(&nums) {
+    Some(total) => Val::Num(total / Decimal::from(count)),
+    None => Val::error(ErrorKind::Num, "avg"),
+  }
+}
+
+fn len(args: Vec<Val>) -> Val {
+  let n = match args.first() {
+    Some(Val::List(elems)) | Some(Val::Array { elems, axes: _ }) => elems.len(),
+    Some(Val::OrderedMap(map)) => map.len(),
+    Some(Val::SparseArray { axes, .. }) => axes.iter().product::<u32>() as usize,
+    Some(Val::Str(s)) => s.chars().count(),
+    Some(_) => 1,
+    None => 0,
+  };
+  Val::Int(n as i64)
+}
+
+/// The largest number, `Num` if there are none.
+fn max(args: Vec<Val>) -> Val {
+  match numbers(args).map(|nums| nums.into_iter().map(|(n, _)| n).max()) {
+    Ok(Some(n)) => Val::Num(n),
+    Ok(None) => Val::error(ErrorKind::Num, "max"),
+    Err(err) => err,
+  }
+}
+
+/// The smallest number, `Num` if there are none.
+fn min(args: Vec<Val>) -> Val {
+  match numbers(args).map(|nums| nums.into_iter().map(|(n, _)| n).min()) {
+    Ok(Some(n)) => Val::Num(n),
+    Ok(None) => Val::error(ErrorKind::Num, "min"),
+    Err(err) => err,
+  }
+}
+
+fn sum(args: Vec<Val>) -> Val {
+  match numbers(args) {
+    Ok(nums) => total(&nums).map_or_else(|| Val::error(ErrorKind::Num, "sum"), Val::Num),
+    Err(err) => err,
+  }
+}
+
+fn round(args: Vec<Val>, rounding: Rounding) -> Val {
+  round_places("round", args, rounding, rounding.mode.strategy())
+}
+
+fn roundup(args: Vec<Val>, rounding: Rounding) -> Val {
+  round_places("roundup", args, rounding, RoundingStrategy::AwayFromZero)
+}
+
+fn rounddown(args: Vec<Val>, rounding: Rounding) -> Val {
+  round_places("rounddown", args, rounding, RoundingStrategy::ToZero)
+}
+
+/// Rounds a number, or each number of a list or array, to the places given
+/// by the second argument, or the board's places, or none. Negative places
+/// round to tens, hundreds and so on.
+fn round_places(name: &str, args: Vec<Val>, rounding: Rounding, strategy: RoundingStrategy) -> Val {
+  let mut args = args.into_iter();
+  let (x, places) = (args.next().unwrap_or_default(), args.next());
+  let places = match places.map(|places| number(&places)) {
+    Some(Ok(n)) if n.fract().is_zero() => n.to_i64().unwrap_or_default(),
+    Some(Ok(_)) => return Val::error(ErrorKind::Num, name),
+    Some(Err(err)) => return err,
+    None => rounding.places.unwrap_or(0) as i64,
+  };
+  round_numbers(name, x, &|d| rounding::round(d, places, strategy))
+}
+
+/// `x` rounded to the nearest multiple of `multiple`, halves as the board
+/// rounds them. A multiple of the other sign is a `Num` error.
+fn mround(args: Vec<Val>, rounding: Rounding) -> Val {
+  let mut args = args.into_iter();
+  let (x, multiple) = (args.next().unwrap_or_default(), args.next().unwrap_or_default());
+  let multiple = match number(&multiple) {
+    Ok(multiple) => multiple,
+    Err(err) => return err,
+  };
+  if let Ok(xs) = numbers(vec![x.clone()]) {
+    if xs.iter().any(|(x, _)| !x.is_zero() && !multiple.is_zero() && x.is_sign_negative() != multiple.is_sign_negative()) {
+      return Val::error(ErrorKind::Num, "mround");
+    }
+  }
+  let strategy = rounding.mode.strategy();
+  round_numbers("mround", x, &|d| match multiple.is_zero() {
+    true => Some(Decimal::ZERO),
+    false => d.checked_div(multiple)?.round_dp_with_strategy(0, strategy).checked_mul(multiple),
+  })
+}
+
+/// Applies `f` to a number, or each number of a list or array, giving
+/// `Number`s, or a `Num` error from `name` where `f` overflows.
+fn round_numbers(name: &str, x: Val, f: &dyn Fn(Decimal) -> Option<Decimal>) -> Val {
+  match x {
+    Val::List(elems) => Val::List(elems.into_iter().map(|e| round_numbers(name, e, f)).collect()),
+    Val::Array{elems, axes} => Val::Array{ elems: elems.into_iter().map(|e| round_numbers(name, e, f)).collect(), axes },
+    x => match number(&x) {
+      Ok(n) => f(n).map_or_else(|| Val::error(ErrorKind::Num, name), Val::Num),
+      Err(err) => err,
+    },
+  }
+}
+
+/// A single number argument, or the error it is. Floats without a decimal
+/// value are a `Num` error, as in `numbers`.
+fn number(val: &Val) -> Result<Decimal, Val> {
+  match val {
+    err @ Val::Error{..} => Err(err.clone()),
+    val => crate::eval::number(val).map_err(|kind| Val::error(kind, val.to_string())),
+  }
+}
+
+/// Calls a linear algebra function with `args` as matrices and vectors.
+/// Errors in their elements are passed on, while errors raised by `f` have
+/// the function's name as their origin.
+fn matrices(name: &str, args: Vec<Val>, f: impl FnOnce(Vec<Operand>) -> Result<Val, ErrorKind>) -> Val {
+  if let Some(err) = linalg::first_error(&args) {
+    return err.clone();
+  }
+  args.iter().map(Operand::new).collect::<Result<Vec<_>, _>>().and_then(f).unwrap_or_else(|kind| Val::error(kind, name))
+}
+
+fn det(args: Vec<Val>) -> Val {
+  matrices("det", args, |ops| match ops.into_iter().next() {
+    Some(m) => m.matrix().det().map(Val::Num),
+    None => Err(ErrorKind::Type),
+  })
+}
+
+fn inverse(args: Vec<Val>) -> Val {
+  matrices("inverse", args, |ops| match ops.into_iter().next() {
+    Some(m) => m.matrix().inverse().map(linalg::Matrix::into_val),
+    None => Err(ErrorKind::Type),
+  })
+}
+
+fn matmul(args: Vec<Val>) -> Val {
+  if let Some(err) = linalg::first_error(&args) {
+    return err.clone();
+  }
+  match &args[..] {
+    [a, b] => linalg::dot(a, b),
+    _ => Err(ErrorKind::Type),
+  }.unwrap_or_else(|kind| Val::error(kind, "matmul"))
+}
+
+fn solve(args: Vec<Val>) -> Val {
+  matrices("solve", args, |ops| {
+    let mut ops = ops.into_iter();
+    match (ops.next(), ops.next()) {
+      (Some(a), Some(b @ Operand::Vector(_))) => a.matrix().solve(&b.matrix()).map(linalg::Matrix::into_vector),
+      (Some(a), Some(b)) => a.matrix().solve(&b.matrix()).map(linalg::Matrix::into_val),
+      _ => Err(ErrorKind::Type),
+    }
+  })
+}
+
+fn transpose(args: Vec<Val>) -> Val {
+  matrices("transpose", args, |ops| match ops.into_iter().next() {
+    Some(Operand::Matrix(m)) => Ok(m.transpose().into_val()),
+    Some(v) => Ok(v.matrix().into_val()),
+    None => Err(ErrorKind::Type),
+  })
+}
+
+/// Sum of `nums`, each as many times as it's counted, or `None` if it
+/// overflows.
+fn total(nums: &[(Decimal, u64)]) -> Option<Decimal> {
+  nums.iter().try_fold(Decimal::ZERO, |acc, &(n, count)| acc.checked_add(n.checked_mul(Decimal::from(count))?))
+}
+
+/// Elements of a collection, or a scalar as a single element. Sparse arrays
+/// give every element in turn without expanding them, maps give their values.
+fn elements(val: Val) -> Box<dyn Iterator<Item = Val>> {
+  match val {
+    Val::List(elems) | Val::Array { elems, axes: _ } => Box::new(elems.into_iter()),
+    Val::OrderedMap(map) => Box::new(map.into_iter().map(|(_, v)| v)),
+    Val::SparseArray { fill, axes, elems } => {
+      let n = axes.iter().product::<u32>();
+      Box::new((0..n).map(move |i| elems.get(&i).cloned().unwrap_or_else(|| (*fill).clone())))
+    },
+    v => Box::new(std::iter::once(v)),
+  }
+}
+
+fn map(args: Vec<Val>, apply: &mut Apply) -> Val {
+  let mut args = args.into_iter();
+  let (values, f) = (args.next().unwrap_or_default(), args.next().unwrap_or_default());
+  match values {
+    Val::Array { elems, axes } => Val::Array {
+      elems: elems.into_iter().map(|x| apply(&f, vec![x])).collect(),
+      axes,
+    },
+    Val::OrderedMap(map) => Val::OrderedMap(
+      map.into_iter().map(|(k, x)| (k, apply(&f, vec![x]))).collect()
+    ),
+    // f is applied once to the fill rather than to every unset element
+    Val::SparseArray { fill, axes, elems } => Val::SparseArray {
+      fill: Box::new(apply(&f, vec![*fill])),
+      axes,
+      elems: elems.into_iter().map(|(i, x)| (i, apply(&f, vec![x]))).collect(),
+    },
+    values => Val::List(elements(values).map(|x| apply(&f, vec![x])).collect()),
+  }
+}
+
+/// The elements for which `f` holds, as `if` tests a condition. An error
+/// from `f` is the result, and a value that isn't a condition is `Type`.
+fn filter(args: Vec<Val>, apply: &mut Apply) -> Val {
+  let mut args = args.into_iter();
+  let (values, f) = (args.next().unwrap_or_default(), args.next().unwrap_or_default());
+  let mut kept = vec![];
+  for x in elements(values) {
+    match condition(apply(&f, vec![x.clone()])) {
+      Ok(true) => kept.push(x),
+      Ok(false) => (),
+      Err(err) => return err.unwrap_or_else(|| Val::error(ErrorKind::Type, "filter")),
+    }
+  }
+  Val::List(kept)
+}
+
+fn reduce(args: Vec<Val>, apply: &mut Apply) -> Val {
+  let mut args = args.into_iter();
+  let values = args.next().unwrap_or_default();
+  let init = args.next().unwrap_or_default();
+  let f = args.next().unwrap_or_default();
+  elements(values).fold(init, |acc, x| apply(&f, vec![acc, x]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  #[test]
+  fn test_builtins_basics() {
+    use Val::*;
+
+    let call = |name: &str, args: Vec<Val>| match lookup(name).unwrap().func {
+      Func::Plain(f) => f(args),
+      Func::Higher(_) => panic!("{name} takes functions"),
+      Func::Rounding(f) => f(args, Rounding::default()),
+    };
+
+    assert_eq!(call("sum", vec![Num(dec!(1)), List(vec![Int(2), Float(3.0)])]), Num(dec!(6)));
+    assert_eq!(call("avg", vec![Num(dec!(1)), Num(dec!(2))]), Num(dec!(1.5)));
+    // nothing to aggregate is an error rather than a plausible zero
+    assert_eq!(call("avg", vec![]), Val::error(ErrorKind::Div0, "avg"));
+    assert_eq!(call("max", vec![]), Val::error(ErrorKind::Num, "max"));
+    assert_eq!(call("min", vec![List(vec![])]), Val::error(ErrorKind::Num, "min"));
+    let empty = call("SparseArray.fill", vec![Int(1), List(vec![Int(0), Int(3)])]);
+    assert_eq!(call("avg", vec![empty.clone()]), Val::error(ErrorKind::Div0, "avg"));
+    assert_eq!(call("max", vec![empty.clone()]), Val::error(ErrorKind::Num, "max"));
+    assert_eq!(call("sum", vec![empty]), Num(dec!(0)));
+    assert_eq!(call("min", vec![List(vec![Int(3), Int(-2)])]), Num(dec!(-2)));
+    assert_eq!(call("max", vec![Int(3), Int(-2)]), Num(dec!(3)));
+    assert_eq!(call("abs", vec![Num(dec!(-4.5))]), Num(dec!(4.5)));
+    assert_eq!(call("len", vec![List(vec![Int(1), Int(2)])]), Int(2));
+    assert_eq!(call("len", vec![Str("abc".to_owned())]), Int(3));
+    assert!(lookup("nope").is_none());
+  }
+
+  #[test]
+  fn test_builtins_constructors() {
+    use Val::*;
+
+    let call = |name: &str, args: Vec<Val>| match lookup(name).unwrap().func {
+      Func::Plain(f) => f(args),
+      Func::Higher(_) => panic!("{name} takes functions"),
+      Func::Rounding(f) => f(args, Rounding::default()),
+    };
+    let field = |k: &str, v: Val| Record{ value: vec![Str(k.to_owned()), v], fields: 1 };
+
+    assert_eq!(call("List", vec![]), List(vec![]));
+    assert_eq!(call("Array", vec![]), Array{ elems: vec![], axes: vec![0] });
+    assert_eq!(call("Array", vec![Int(1), Int(2)]), Array{ elems: vec![Int(1), Int(2)], axes: vec![2] });
+
+    let map = call("OrderedMap", vec![field("b", Int(5)), field("a", Int(10)), field("b", Int(6))]);
+    assert_eq!(map, OrderedMap(vec![("b".to_owned(), Int(6)), ("a".to_owned(), Int(10))]));
+    assert_eq!(call("len", vec![map.clone()]), Int(2));
+    assert_eq!(call("sum", vec![map]), Num(dec!(16)));
+
+    let shape = List(vec![Int(100), Int(300)]);
+    let sparse = call("SparseArray.fill", vec![Int(0), shape]);
+    assert_eq!(call("len", vec![sparse.clone()]), Int(30000));
+    let sparse = call("SparseArray.set", vec![sparse, List(vec![Int(1), Int(2)]), Int(7)]);
+    match &sparse {
+      SparseArray{ elems, .. } => assert_eq!(elems, &BTreeMap::from([(302, Int(7))])),
+      v => panic!("expected sparse array, got {v:?}"),
+    }
+    assert_eq!(call("SparseArray.get", vec![sparse.clone(), List(vec![Int(1), Int(2)])]), Int(7));
+    assert_eq!(call("SparseArray.get", vec![sparse.clone(), List(vec![Int(0), Int(2)])]), Int(0));
+    assert_eq!(call("SparseArray.get", vec![sparse.clone(), List(vec![Int(100), Int(0)])]), Val::error(ErrorKind::Ref, "SparseArray.get"));
+    assert_eq!(call("SparseArray.get", vec![sparse.clone(), List(vec![Int(1)])]), Val::error(ErrorKind::Ref, "SparseArray.get"));
+    assert_eq!(call("SparseArray.set", vec![sparse.clone(), List(vec![Int(0), Int(300)]), Int(1)]), Val::error(ErrorKind::Ref, "SparseArray.set"));
+    assert_eq!(call("SparseArray.get", vec![Int(1), List(vec![Int(0), Int(0)])]), Val::error(ErrorKind::Type, "SparseArray.get"));
+    assert_eq!(call("sum", vec![sparse.clone()]), Num(dec!(7)));
+    assert_eq!(call("max", vec![sparse.clone()]), Num(dec!(7)));
+    assert_eq!(call("min", vec![sparse.clone()]), Num(dec!(0)));
+
+    // the fill counts for every unset element without expanding them
+    let big = call("SparseArray.fill", vec![Int(1), List(vec![Int(20000), Int(20000)])]);
+    let big = call("SparseArray.set", vec![big, List(vec![Int(0), Int(0)]), Int(400_000_001)]);
+    assert_eq!(call("sum", vec![big.clone()]), Num(dec!(800000000)));
+    assert_eq!(call("avg", vec![big.clone()]), Num(dec!(2)));
+    assert_eq!(call("max", vec![big.clone()]), Num(dec!(400000001)));
+    assert_eq!(call("min", vec![big]), Num(dec!(1)));
+
+    // setting the fill value drops the element
+    let sparse = call("SparseArray.set", vec![sparse, List(vec![Int(1), Int(2)]), Int(0)]);
+    assert!(matches!(sparse, SparseArray{ elems, .. } if elems.is_empty()));
+
+    // shapes with more elements than a u32 can index are errors
+    assert_eq!(call("SparseArray.fill", vec![Int(0), List(vec![Int(100_000), Int(100_000)])]), Val::error(ErrorKind::Num, "SparseArray.fill"));
+    assert_eq!(call("SparseArray.fill", vec![Int(0), List(vec![Int(-1)])]), Val::error(ErrorKind::Num, "SparseArray.fill"));
+    let huge = call("SparseArray.fill", vec![Int(1), List(vec![Int(100_000), Int(300_000)])]);
+    assert_eq!(call("sum", vec![huge]), Val::error(ErrorKind::Num, "SparseArray.fill"));
+  }
+
+  #[test]
+  fn test_builtins_checked() {
+    use Val::*;
+
+    let call = |name: &str, args: Vec<Val>| match lookup(name).unwrap().func {
+      Func::Plain(f) => f(args),
+      Func::Higher(_) => panic!("{name} takes functions"),
+      Func::Rounding(f) => f(args, Rounding::default()),
+    };
+
+    assert_eq!(call("sum", vec![Num(Decimal::MAX), Int(1)]), Val::error(ErrorKind::Num, "sum"));
+    assert_eq!(call("avg", vec![List(vec![Num(Decimal::MAX), Num(Decimal::MAX)])]), Val::error(ErrorKind::Num, "avg"));
+    assert_eq!(call("sum", vec![Float(f64::NAN)]), Val::error(ErrorKind::Num, "NaN"));
+    assert_eq!(call("max", vec![List(vec![Float(f64::INFINITY)])]), Val::error(ErrorKind::Num, "∞"));
+    assert_eq!(call("sum", vec![Num(Decimal::MAX), Num(Decimal::MIN)]), Num(dec!(0)));
+  }
+
+  #[test]
+  fn test_builtins_rounding() {
+    use Val::*;
+    use crate::rounding::Mode;
+
+    let call = |name: &str, args: Vec<Val>, rounding: Rounding| match lookup(name).unwrap().func {
+      Func::Rounding(f) => f(args, rounding),
+      _ => panic!("{name} doesn't round"),
+    };
+    let half_up = Rounding::default();
+    let half_even = Rounding{ places: Some(2), mode: Mode::HalfEven, ..half_up };
+
+    assert_eq!(call("round", vec![Num(dec!(2.5))], half_up), Num(dec!(3)));
+    assert_eq!(call("round", vec![Num(dec!(-2.5))], half_up), Num(dec!(-3)));
+    assert_eq!(call("round", vec![Num(dec!(1.005)), Int(2)], half_up), Num(dec!(1.01)));
+    assert_eq!(call("round", vec![Num(dec!(1234)), Int(-2)], half_up), Num(dec!(1200)));
+    assert_eq!(call("round", vec![Float(2.675), Int(1)], half_up), Num(dec!(2.7)));
+    // the board's places and mode apply when not given
+    assert_eq!(call("round", vec![Num(dec!(0.125))], half_even), Num(dec!(0.12)));
+    assert_eq!(call("round", vec![Num(dec!(2.5)), Int(0)], half_even), Num(dec!(2)));
+    assert_eq!(call("round", vec![List(vec![Num(dec!(0.5)), Int(3)])], half_up), List(vec![Num(dec!(1)), Num(dec!(3))]));
+
+    assert_eq!(call("roundup", vec![Num(dec!(1.201)), Int(1)], half_up), Num(dec!(1.3)));
+    assert_eq!(call("roundup", vec![Num(dec!(-1.201)), Int(1)], half_up), Num(dec!(-1.3)));
+    assert_eq!(call("rounddown", vec![Num(dec!(1.299)), Int(1)], half_up), Num(dec!(1.2)));
+    assert_eq!(call("rounddown", vec![Num(dec!(0.129))], half_even), Num(dec!(0.12)));
+
+    assert_eq!(call("mround", vec![Num(dec!(17)), Int(5)], half_up), Num(dec!(15)));
+    assert_eq!(call("mround", vec![Num(dec!(1.3)), Num(dec!(0.25))], half_up), Num(dec!(1.25)));
+    assert_eq!(call("mround", vec![Num(dec!(7.5)), Int(5)], half_up), Num(dec!(10)));
+    assert_eq!(call("mround", vec![Num(dec!(7.5)), Int(5)], half_even), Num(dec!(10)));
+    assert_eq!(call("mround", vec![Num(dec!(12.5)), Int(5)], half_even), Num(dec!(10)));
+    assert_eq!(call("mround", vec![Num(dec!(-7)), Int(5)], half_up), Val::error(ErrorKind::Num, "mround"));
+
+    assert_eq!(call("round", vec![Num(dec!(1)), Num(dec!(0.5))], half_up), Val::error(ErrorKind::Num, "round"));
+    assert_eq!(call("round", vec![Str("a".to_owned())], half_up), Val::error(ErrorKind::Type, "a"));
+    assert_eq!(call("round", vec![Float(f64::INFINITY)], half_up), Val::error(ErrorKind::Num, "∞"));
+    // results past the largest number
+    let max = Num(Decimal::MAX);
+    assert_eq!(call("round", vec![max.clone(), Int(-1)], half_up), Val::error(ErrorKind::Num, "round"));
+    assert_eq!(call("roundup", vec![max.clone(), Int(-1)], half_up), Val::error(ErrorKind::Num, "roundup"));
+    assert_eq!(call("rounddown", vec![max.clone(), Int(-1)], half_up), Num(dec!(79228162514264337593543950330)));
+    assert_eq!(call("mround", vec![max.clone(), Int(10)], half_up), Val::error(ErrorKind::Num, "mround"));
+    assert_eq!(call("mround", vec![max, Num(dec!(0.1))], half_up), Val::error(ErrorKind::Num, "mround"));
+    assert_eq!(call("round", vec![List(vec![Num(Decimal::MIN), Int(15)]), Int(-1)], half_up), List(vec![Val::error(ErrorKind::Num, "round"), Num(dec!(20))]));
+  }
+
+  #[test]
+  fn test_builtins_linalg() {
+    use Val::*;
+
+    let call = |name: &str, args: Vec<Val>| match lookup(name).unwrap().func {
+      Func::Plain(f) => f(args),
+      Func::Higher(_) => panic!("{name} takes functions"),
+      Func::Rounding(f) => f(args, Rounding::default()),
+    };
+    let nums = |vals: &[i64]| vals.iter().map(|v| Num(Decimal::from(*v))).collect::<Vec<_>>();
+    let matrix = |rows: u32, cols: u32, vals: &[i64]| Array{ elems: nums(vals), axes: vec![rows, cols] };
+    let a = matrix(2, 2, &[4, 7, 2, 6]);
+
+    assert_eq!(call("det", vec![a.clone()]), Num(dec!(10)));
+    assert_eq!(call("transpose", vec![a.clone()]), matrix(2, 2, &[4, 2, 7, 6]));
+    assert_eq!(call("transpose", vec![List(nums(&[1, 2]))]), matrix(2, 1, &[1, 2]));
+    assert_eq!(call("matmul", vec![a.clone(), matrix(2, 1, &[1, 1])]), matrix(2, 1, &[11, 8]));
+    assert_eq!(call("inverse", vec![a.clone()]), Array{
+      elems: [dec!(0.6), dec!(-0.7), dec!(-0.2), dec!(0.4)].into_iter().map(Num).collect(),
+      axes: vec![2, 2],
+    });
+    // vectors give vectors, matrices matrices
+    assert_eq!(call("solve", vec![a.clone(), List(nums(&[11, 8]))]), Array{ elems: nums(&[1, 1]), axes: vec![2] });
+    assert_eq!(call("solve", vec![a.clone(), matrix(2, 1, &[11, 8])]), matrix(2, 1, &[1, 1]));
+
+    let singular = matrix(2, 2, &[1, 2, 2, 4]);
+    assert_eq!(call("det", vec![singular.clone()]), Num(dec!(0)));
+    assert_eq!(call("inverse", vec![singular.clone()]), Val::error(ErrorKind::Singular, "inverse"));
+    assert_eq!(call("solve", vec![singular, List(nums(&[1, 2]))]), Val::error(ErrorKind::Singular, "solve"));
+    assert_eq!(call("det", vec![matrix(1, 2, &[1, 2])]), Val::error(ErrorKind::Shape, "det"));
+    assert_eq!(call("solve", vec![a.clone(), List(nums(&[1, 2, 3]))]), Val::error(ErrorKind::Shape, "solve"));
+    assert_eq!(call("matmul", vec![a.clone(), matrix(1, 2, &[1, 2])]), Val::error(ErrorKind::Shape, "matmul"));
+    assert_eq!(call("inverse", vec![List(vec![Str("a".to_owned())])]), Val::error(ErrorKind::Type, "inverse"));
+    let err = Val::error(ErrorKind::Div0, "1 / 0");
+    assert_eq!(call("det", vec![Array{ elems: vec![Int(1), err.clone()], axes: vec![1, 2] }]), err);
+  }
+}
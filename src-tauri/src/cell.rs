@@ -1,15 +1,25 @@
+use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use rust_decimal::{Decimal, prelude::{FromPrimitive, ToPrimitive}};
 use rust_decimal_macros::dec;
 use itertools::Itertools;
 
+use crate::closure::Closure;
+use crate::complex::Complex;
+use crate::rounding::Rounding;
 use crate::rpc::*;
 
 
 
 pub trait RenderCell {
   fn render(&self) -> CellUi;
+
+  /// Renders the cell with its value rounded for display.
+  fn render_rounded(&self, _rounding: &Rounding) -> CellUi {
+    self.render()
+  }
 }
 
 pub trait RenderValue {
@@ -95,6 +105,45 @@ impl<T, const CARD: usize> CRef<CARD> for T where T:
     }
   }
 
+/// Why a formula has no value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+  /// An operand or argument of the wrong type, `'a' * 2`.
+  Type,
+  /// A reference to a cell, function or operator that doesn't exist.
+  Ref,
+  /// Division by zero.
+  Div0,
+  /// The formula doesn't parse.
+  Parse,
+  /// The cell depends on its own value.
+  Cycle,
+  /// A number too large to represent, or a float that isn't a number.
+  Num,
+  /// Lists or arrays whose shapes can't be broadcast together.
+  Shape,
+  /// A singular matrix, which has no inverse.
+  Singular,
+  /// A `match` without a case for the value.
+  NoMatch,
+}
+
+impl ErrorKind {
+  pub fn code(&self) -> &'static str {
+    match self {
+      ErrorKind::Type => "#TYPE",
+      ErrorKind::Ref => "#REF",
+      ErrorKind::Div0 => "#DIV/0",
+      ErrorKind::Parse => "#PARSE",
+      ErrorKind::Cycle => "#CYCLE",
+      ErrorKind::Num => "#NUM",
+      ErrorKind::Shape => "#SHAPE",
+      ErrorKind::Singular => "#SINGULAR",
+      ErrorKind::NoMatch => "#NOMATCH",
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 #[allow(unused)]
 pub enum Val {
@@ -102,10 +151,22 @@ pub enum Val {
   Bool(bool),
   Float(f64),
   Int(i64),
+  /// A number with an imaginary part, see `complex`.
+  Complex(Complex),
   Str(String),
   List(Vec<Val>),
   Array{elems: Vec<Val>, axes: Vec<u32>},
   Record{value: Vec<Val>, fields: u32},
+  Lambda(Arc<Closure>),
+  /// Values by key, in the order the keys were first added. Created with
+  /// `OrderedMap(b: 5, a: 10)`.
+  OrderedMap(Vec<(String, Val)>),
+  /// An array where every element is `fill` except those in `elems`, keyed
+  /// by row-major index. Created with `SparseArray.fill(0, (100, 300))`.
+  SparseArray{fill: Box<Val>, axes: Vec<u32>, elems: BTreeMap<u32, Val>},
+  /// A failed evaluation. `origin` is the formula text where the error
+  /// arose, passed along unchanged by everything computed from it.
+  Error{kind: ErrorKind, origin: String},
 }
 
 impl From<&Val> for Decimal {
@@ -116,10 +177,15 @@ impl From<&Val> for Decimal {
       &Bool(b) => if b {Decimal::new(1, 0)} else {Decimal::new(0, 0)},
       &Float(f) => Decimal::from_f64(f).unwrap_or_default(),
       &Int(i) => Decimal::from_i64(i).unwrap_or_default(),
+      Complex(_) => Decimal::default(),
       Str(s) => Decimal::from_str_radix(s, 10).unwrap_or_default(),
       List(_) => Decimal::default(),
       Array{elems: _, axes: _} => Decimal::default(),
       Record{value: _, fields: _} => Decimal::default(),
+      Lambda(_) => Decimal::default(),
+      OrderedMap(_) => Decimal::default(),
+      SparseArray{..} => Decimal::default(),
+      Error{..} => Decimal::default(),
     }
   }
 }
@@ -145,9 +211,12 @@ impl From<Val> for String {
     match value {
       Num(d) => d.to_string(),
       Bool(b) => (if b {"true"} else {"false"}).to_owned(),
-      Float(f) => f.to_string(),
+      Float(f) => float_text(f),
       Int(i) => i.to_string(),
+      Complex(z) => z.to_string(),
       Str(s) => s,
+      Lambda(closure) => closure.to_string(),
+      OrderedMap(_) | SparseArray{..} | Error{..} => value.to_string(),
       List(elems) => {
         let res: String = elems.into_iter()
                                .map(|e|Self::from(e))
@@ -160,6 +229,16 @@ impl From<Val> for String {
   }
 }
 
+impl Val {
+  pub fn error(kind: ErrorKind, origin: impl Into<String>) -> Val {
+    Val::Error{ kind, origin: origin.into() }
+  }
+
+  pub fn is_error(&self) -> bool {
+    matches!(self, Val::Error{..})
+  }
+}
+
 #[allow(unused)]
 impl Val {
   fn is_scalar(self) -> bool {
@@ -169,6 +248,7 @@ impl Val {
       Bool(_) => true,
       Float(_) => true,
       Int(_) => true,
+      Complex(_) => true,
       Str(_) => true,
       _ => false
     }
@@ -217,6 +297,15 @@ fn join_cell_values<'a>(iter: core::slice::Iter<'_, Val>, sep: &str) -> String {
       .join(sep)
 }
 
+/// Floats as text, with infinities written as they're parsed, `∞`.
+fn float_text(f: f64) -> String {
+  match f {
+    f64::INFINITY => "∞".to_owned(),
+    f64::NEG_INFINITY => "-∞".to_owned(),
+    f => f.to_string(),
+  }
+}
+
 impl ToString for Val {
   fn to_string(&self) -> String {
     use Val::*;
@@ -224,8 +313,9 @@ impl ToString for Val {
     match &self {
       Num(value) => value.to_string(),
       Bool(value) => value.to_string(),
-      Float(value) => value.to_string(),
+      Float(value) => float_text(*value),
       Int(value) => value.to_string(),
+      Complex(value) => value.to_string(),
       Str(value) => value.clone(),
       List(value) =>
         value.into_iter()
@@ -242,6 +332,16 @@ impl ToString for Val {
                .collect();
         kvs.join(",")
       }
+      Lambda(closure) => closure.to_string(),
+      OrderedMap(map) =>
+        map.iter()
+           .map(|(k, v)| format!("{}:{}", k, v.to_string()))
+           .collect::<Vec<String>>().join(","),
+      SparseArray{fill, axes, elems} => {
+        let dims: Vec<String> = axes.iter().map(ToString::to_string).collect();
+        format!("SparseArray({} of {}, {} set)", dims.join("x"), fill.to_string(), elems.len())
+      },
+      Error{kind, origin: _} => kind.code().to_owned(),
     }
   }
 }
@@ -263,13 +363,18 @@ impl RenderValue for Val {
       Float(value) =>
         ValueUi::V(ScalarValueUi{
           typ: TypeUi::Number,
-          value: value.to_string(),
+          value: float_text(*value),
         }),
       Int(value) =>
         ValueUi::V(ScalarValueUi{
           typ: TypeUi::Number,
           value: value.to_string(),
         }),
+      Complex(value) =>
+        ValueUi::V(ScalarValueUi{
+          typ: TypeUi::Number,
+          value: value.to_string(),
+        }),
       Str(value) =>
         ValueUi::V(ScalarValueUi {
           typ: TypeUi::String,
@@ -292,6 +397,31 @@ impl RenderValue for Val {
           value: value.into_iter().map(|cell| cell.to_string()).collect(),
           fields: *fields,
         }),
+      Lambda(closure) =>
+        ValueUi::V(ScalarValueUi {
+          typ: TypeUi::Lambda,
+          value: closure.to_string(),
+        }),
+      OrderedMap(map) =>
+        ValueUi::R(RecordValueUi {
+          typ: TypeUi::OrderedMap,
+          value: map.iter().flat_map(|(k, v)| [k.clone(), v.to_string()]).collect(),
+          fields: map.len() as u32,
+        }),
+      SparseArray{fill, axes, elems} =>
+        ValueUi::S(SparseArrayValueUi {
+          typ: TypeUi::SparseArray,
+          fill: fill.to_string(),
+          dims: axes.clone(),
+          index: elems.keys().copied().collect(),
+          value: elems.values().map(|cell| cell.to_string()).collect(),
+        }),
+      Error{kind, origin} =>
+        ValueUi::E(ErrorValueUi {
+          typ: TypeUi::Error,
+          value: kind.code().to_owned(),
+          origin: origin.clone(),
+        }),
     }
   }
 }
@@ -368,6 +498,13 @@ impl RenderCell for Cell {
       style: String::new(),
     }
   }
+
+  fn render_rounded(&self, rounding: &Rounding) -> CellUi {
+    CellUi{
+      value: RenderValue::render(&rounding.apply(self.value.clone())),
+      ..self.render()
+    }
+  }
 }
 
 // impl ToString for Cell {
@@ -407,4 +544,35 @@ mod tests {
     assert_eq!("1,2,3", s)
   }
 
+  #[test]
+  fn test_val_render_special() {
+    use Val::*;
+
+    let map = OrderedMap(vec![("b".to_owned(), Int(5)), ("a".to_owned(), Int(10))]);
+    assert_eq!("b:5,a:10", map.to_string());
+    assert_eq!(map.render(), ValueUi::R(RecordValueUi {
+      typ: TypeUi::OrderedMap,
+      value: vec!["b".to_owned(), "5".to_owned(), "a".to_owned(), "10".to_owned()],
+      fields: 2,
+    }));
+
+    let sparse = SparseArray{ fill: Box::new(Int(0)), axes: vec![100, 300], elems: BTreeMap::from([(301, Int(7))]) };
+    assert_eq!("SparseArray(100x300 of 0, 1 set)", sparse.to_string());
+    assert_eq!(sparse.render(), ValueUi::S(SparseArrayValueUi {
+      typ: TypeUi::SparseArray,
+      fill: "0".to_owned(),
+      dims: vec![100, 300],
+      index: vec![301],
+      value: vec!["7".to_owned()],
+    }));
+
+    let err = Val::error(ErrorKind::Div0, "1 / 0");
+    assert_eq!("#DIV/0", err.to_string());
+    assert_eq!(err.render(), ValueUi::E(ErrorValueUi {
+      typ: TypeUi::Error,
+      value: "#DIV/0".to_owned(),
+      origin: "1 / 0".to_owned(),
+    }));
+  }
+
 }
\ No newline at end of file
@@ -0,0 +1,220 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::ast::Ast;
+use crate::cell::{ErrorKind, Val};
+use crate::eval::{Env, FuncContext, Node, ObjectContext};
+use crate::parser::{ElemRange, NodeId, ValueId};
+use crate::rounding::Rounding;
+use crate::tile::{Axis, TileContext};
+
+/// Closure calls nested deeper than this give `#NUM` rather than overflow
+/// the stack, as a lambda applied to itself would, `g -> g(g)`.
+pub const MAX_DEPTH: u32 = 256;
+
+/// A lambda value, e.g. `(acc, x) -> acc + x`.
+///
+/// The body is copied out of the parser into an `Ast` of its own, so a
+/// closure stays callable after the formula that created it is gone.
+#[derive(Debug)]
+pub struct Closure {
+  params: Vec<String>,
+  body: Ast,
+  env: Env,
+}
+
+impl Closure {
+  /// Captures `env` and copies `body` out of `ctx`.
+  pub fn new(ctx: &impl ObjectContext, params: Vec<String>, body: &NodeId, env: Env) -> Closure {
+    Closure::from_ast(params, Ast::new(ctx, ctx.get_node(body)), env)
+  }
+
+  pub fn from_ast(params: Vec<String>, body: Ast, env: Env) -> Closure {
+    Closure{ params, body, env }
+  }
+
+  pub fn params(&self) -> &[String] {
+    &self.params
+  }
+
+  pub fn body(&self) -> &Ast {
+    &self.body
+  }
+
+  /// The scope the closure was created in.
+  pub fn env(&self) -> &Env {
+    &self.env
+  }
+
+  /// Names of the functions the body refers to, whether called or passed
+  /// on as in `let g = f in g(x)`. Names bound inside the closure itself
+  /// (parameters, `let` and nested lambdas) are excluded, as are names that
+  /// aren't variables: record keys, labels and tile names.
+  pub fn calls(&self) -> Vec<String> {
+    let name = |id: &NodeId| match self.get_node(id) {
+      Node::Sym{value} => Some(self.get_value(value).to_string()),
+      _ => None,
+    };
+    let mut bound: BTreeSet<String> = self.params.iter().cloned().collect();
+    let mut labels = BTreeSet::new();
+    for node in self.body.nodes() {
+      match *node {
+        Node::Let{binds, ..} => bound.extend(self.get_elems(&binds).iter().step_by(2).filter_map(name)),
+        Node::Lambda{params, ..} => bound.extend(self.get_elems(&params).iter().filter_map(name)),
+        Node::Field{key, ..} => labels.extend([key]),
+        Node::Addr{row, col} => labels.extend([row, col]),
+        Node::TileRef{tile, ..} => labels.extend([tile]),
+        _ => (),
+      }
+    }
+    // ids index the nodes in order, with the root last
+    let calls: BTreeSet<String> = self.body.nodes().enumerate()
+      .filter(|(i, _)| !labels.contains(&NodeId(*i as u32)))
+      .filter_map(|(_, node)| match node {
+        Node::Sym{value} => Some(self.get_value(value).to_string()),
+        _ => None,
+      })
+      .collect();
+    calls.difference(&bound).cloned().collect()
+  }
+
+  /// Symbols of the board operators used by the body.
+  pub fn ops(&self) -> Vec<String> {
+    let ops: BTreeSet<String> = self.body.nodes().filter_map(|node| match node {
+      Node::CustomOp{op, ..} => Some(self.get_value(op).to_string()),
+      _ => None,
+    }).collect();
+    ops.into_iter().collect()
+  }
+
+  /// Calls the closure, binding `args` to its parameters in the captured
+  /// scope. Missing arguments are left unbound, extra ones are ignored.
+  pub fn call(&self, tiles: &mut dyn Caller, args: Vec<Val>) -> Val {
+    let depth = tiles.depth() + 1;
+    if depth > MAX_DEPTH {
+      return Val::error(ErrorKind::Num, self.to_string());
+    }
+    let mut env = self.env.clone();
+    for (name, arg) in self.params.iter().zip(args) {
+      env = env.bind(name.clone(), arg);
+    }
+    let body = *self.body.root();
+    body.eval_env(&mut ClosureContext{ closure: self, tiles, depth }, &env)
+  }
+}
+
+impl ObjectContext for Closure {
+  fn get_value(&self, value: &ValueId) -> &Val {
+    self.body.get_value(value)
+  }
+  fn get_node(&self, node: &NodeId) -> &Node {
+    self.body.get_node(node)
+  }
+  fn get_elems(&self, elems: &ElemRange) -> &[NodeId] {
+    self.body.get_elems(elems)
+  }
+}
+
+impl fmt::Display for Closure {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.params[..] {
+      [param] => f.write_str(param)?,
+      params => write!(f, "({})", params.join(", "))?,
+    }
+    write!(f, " -> {}", self.body.root().print(self))
+  }
+}
+
+/// Closures are compared by identity.
+impl PartialEq for Closure {
+  fn eq(&self, other: &Self) -> bool {
+    std::ptr::eq(self, other)
+  }
+}
+
+impl PartialOrd for Closure {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    (self == other).then_some(std::cmp::Ordering::Equal)
+  }
+}
+
+/// What a closure body sees of its caller: cells, board functions and operators.
+///
+/// Unlike `TileContext` it is object safe, so closures called from closures
+/// share one context type rather than nesting them.
+pub trait Caller {
+  fn range(&mut self, tile: Option<&Val>, axes: [Axis; 2]) -> Result<Val, ErrorKind>;
+  fn func(&self, name: &str) -> Option<Arc<Closure>>;
+  fn op(&self, symbol: &str) -> Option<String>;
+  fn rounding(&self) -> Rounding;
+  fn depth(&self) -> u32;
+}
+
+impl<T: TileContext + FuncContext> Caller for T {
+  fn range(&mut self, tile: Option<&Val>, axes: [Axis; 2]) -> Result<Val, ErrorKind> {
+    self.get_range(tile, axes)
+  }
+  fn func(&self, name: &str) -> Option<Arc<Closure>> {
+    self.get_func(name)
+  }
+  fn op(&self, symbol: &str) -> Option<String> {
+    self.get_op(symbol)
+  }
+  fn rounding(&self) -> Rounding {
+    FuncContext::rounding(self)
+  }
+  fn depth(&self) -> u32 {
+    FuncContext::depth(self)
+  }
+}
+
+/// Evaluates a closure body: nodes from the closure, cells from the caller.
+struct ClosureContext<'a> {
+  closure: &'a Closure,
+  tiles: &'a mut dyn Caller,
+  /// Calls enclosing this one, this one included.
+  depth: u32,
+}
+
+impl ObjectContext for ClosureContext<'_> {
+  fn get_value(&self, value: &ValueId) -> &Val {
+    self.closure.get_value(value)
+  }
+  fn get_node(&self, node: &NodeId) -> &Node {
+    self.closure.get_node(node)
+  }
+  fn get_elems(&self, elems: &ElemRange) -> &[NodeId] {
+    self.closure.get_elems(elems)
+  }
+}
+
+impl TileContext for ClosureContext<'_> {
+  fn get_range(&mut self, tile: Option<&Val>, axes: [Axis; 2]) -> Result<Val, ErrorKind> {
+    self.tiles.range(tile, axes)
+  }
+}
+
+impl FuncContext for ClosureContext<'_> {
+  fn get_func(&self, name: &str) -> Option<Arc<Closure>> {
+    self.tiles.func(name)
+  }
+  fn get_op(&self, symbol: &str) -> Option<String> {
+    self.tiles.op(symbol)
+  }
+  fn rounding(&self) -> Rounding {
+    self.tiles.rounding()
+  }
+  fn depth(&self) -> u32 {
+    self.depth
+  }
+}
+
+/// Calls `func` with `args`, for builtins that take functions.
+pub fn apply(tiles: &mut dyn Caller, func: &Val, args: Vec<Val>) -> Val {
+  match func {
+    Val::Lambda(closure) => closure.call(tiles, args),
+    err @ Val::Error{..} => err.clone(),
+    func => Val::error(ErrorKind::Type, func.to_string()),
+  }
+}
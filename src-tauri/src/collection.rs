@@ -0,0 +1,286 @@
+use rust_decimal::prelude::ToPrimitive;
+
+use crate::cell::{ErrorKind, Val};
+use crate::eval::{is_number, number};
+use crate::linalg::first_error;
+
+/// A list or array of one or two axes, by rows.
+struct Grid {
+  elems: Vec<Val>,
+  rows: usize,
+  cols: usize,
+}
+
+impl Grid {
+  /// Two dimensional arrays, and vectors as a single column of `rows` or a
+  /// single row of `cols`, whichever matches.
+  fn new(val: Val, rows: usize, cols: usize) -> Result<Grid, ErrorKind> {
+    match val {
+      Val::Array{elems, axes} => match axes[..] {
+        [r, c] if (r * c) as usize == elems.len() => Ok(Grid{ elems, rows: r as usize, cols: c as usize }),
+        [n] if n as usize == elems.len() => Grid::vector(elems, rows, cols),
+        _ => Err(ErrorKind::Shape),
+      },
+      Val::List(elems) => Grid::vector(elems, rows, cols),
+      _ => Err(ErrorKind::Type),
+    }
+  }
+
+  fn vector(elems: Vec<Val>, rows: usize, cols: usize) -> Result<Grid, ErrorKind> {
+    match elems.len() {
+      n if n == rows => Ok(Grid{ elems, rows: n, cols: 1 }),
+      n if n == cols => Ok(Grid{ elems, rows: 1, cols: n }),
+      _ => Err(ErrorKind::Shape),
+    }
+  }
+
+  fn row(&self, row: usize) -> &[Val] {
+    &self.elems[row * self.cols..(row + 1) * self.cols]
+  }
+
+  fn into_val(self) -> Val {
+    Val::Array{ elems: self.elems, axes: vec![self.rows as u32, self.cols as u32] }
+  }
+}
+
+/// Axes of a two dimensional array.
+fn matrix_axes(val: &Val) -> Option<(usize, usize)> {
+  match val {
+    Val::Array{axes, ..} => match axes[..] {
+      [rows, cols] => Some((rows as usize, cols as usize)),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+/// Concatenates strings, lists or arrays, `++`. A value that isn't a list
+/// is added as one element. Arrays with the same number of rows are joined
+/// side by side, otherwise ones with the same number of columns are stacked.
+pub fn concat(left: Val, right: Val) -> Result<Val, ErrorKind> {
+  use Val::*;
+  match (left, right) {
+    (Str(l), Str(r)) => Ok(Str(l + &r)),
+    (l, r) if matrix_axes(&l).is_some() || matrix_axes(&r).is_some() => {
+      let (rows, cols) = matrix_axes(&l).or(matrix_axes(&r)).unwrap_or_default();
+      let (l, r) = (Grid::new(l, rows, cols)?, Grid::new(r, rows, cols)?);
+      let grid = if l.rows == r.rows {
+        let elems = (0..l.rows).flat_map(|row| l.row(row).iter().chain(r.row(row)).cloned()).collect();
+        Grid{ elems, rows: l.rows, cols: l.cols + r.cols }
+      } else if l.cols == r.cols {
+        Grid{ elems: l.elems.into_iter().chain(r.elems).collect(), rows: l.rows + r.rows, cols: l.cols }
+      } else {
+        return Err(ErrorKind::Shape);
+      };
+      Ok(grid.into_val())
+    },
+    (l, r) if is_sequence(&l) || is_sequence(&r) => {
+      let array = matches!(l, Array{..}) || matches!(r, Array{..});
+      Ok(sequence(elements(l)?.into_iter().chain(elements(r)?).collect(), array))
+    },
+    _ => Err(ErrorKind::Type),
+  }
+}
+
+/// Most elements, or bytes of a string, that `**` builds.
+pub const MAX_LEN: usize = 10_000_000;
+
+/// Repeats a string, list or array `count` times, `**`. Two dimensional
+/// arrays repeat each row. Results longer than `MAX_LEN` are `#NUM`.
+pub fn extend(val: Val, count: &Val) -> Result<Val, ErrorKind> {
+  let n = number(count)?;
+  let n = match n.fract().is_zero() {
+    true => n.to_usize().ok_or(ErrorKind::Num)?,
+    false => return Err(ErrorKind::Num),
+  };
+  let len = match &val {
+    Val::Str(s) => s.len(),
+    Val::List(elems) | Val::Array{elems, ..} => elems.len(),
+    _ => 0,
+  };
+  // repeating nothing gives nothing, however many times
+  let n = if len == 0 { 0 } else { n };
+  if len.checked_mul(n).map_or(true, |total| total > MAX_LEN) {
+    return Err(ErrorKind::Num);
+  }
+  let times = u32::try_from(n).map_err(|_| ErrorKind::Num)?;
+  match val {
+    Val::Str(s) => Ok(Val::Str(s.repeat(n))),
+    Val::List(elems) => Ok(Val::List(repeat(&elems, n))),
+    Val::Array{elems, axes} => match axes[..] {
+      [len] if len as usize == elems.len() => Ok(Val::Array{ elems: repeat(&elems, n), axes: vec![len * times] }),
+      [rows, cols] if (rows * cols) as usize == elems.len() => Ok(Val::Array{
+        elems: elems.chunks(cols.max(1) as usize).flat_map(|row| repeat(row, n)).collect(),
+        axes: vec![rows, cols * times],
+      }),
+      _ => Err(ErrorKind::Shape),
+    },
+    _ => Err(ErrorKind::Type),
+  }
+}
+
+/// Whether `elem` is in `coll`, `in`: an element of a list or array, or a
+/// part of a string.
+pub fn contains(elem: &Val, coll: &Val) -> Result<Val, ErrorKind> {
+  match (elem, coll) {
+    (Val::Str(part), Val::Str(s)) => Ok(Val::Bool(s.contains(part.as_str()))),
+    (_, Val::List(elems) | Val::Array{elems, ..}) => match first_error(elems) {
+      Some(err) => Ok(err.clone()),
+      None => Ok(Val::Bool(elems.iter().any(|e| same(e, elem)))),
+    },
+    _ => Err(ErrorKind::Type),
+  }
+}
+
+/// Elements of either operand, `union`.
+pub fn union(left: Val, right: Val) -> Result<Val, ErrorKind> {
+  set(left, right, |_, _| true)
+}
+
+/// Elements of `left` that are also in `right`, `inter`.
+pub fn inter(left: Val, right: Val) -> Result<Val, ErrorKind> {
+  set(left, right, |in_left, in_right| in_left && in_right)
+}
+
+/// Elements of `left` that aren't in `right`, set `-`.
+pub fn difference(left: Val, right: Val) -> Result<Val, ErrorKind> {
+  set(left, right, |in_left, in_right| in_left && !in_right)
+}
+
+/// The elements of `left` and then `right` for which `keep` holds, given
+/// whether the element is in each, without duplicates. The result is an
+/// array if either operand is one.
+fn set(left: Val, right: Val, keep: fn(bool, bool) -> bool) -> Result<Val, ErrorKind> {
+  if let Some(err) = first_error(&[left.clone(), right.clone()]) {
+    return Ok(err.clone());
+  }
+  let array = matches!(left, Val::Array{..}) || matches!(right, Val::Array{..});
+  let (l, r) = (elements(left)?, elements(right)?);
+  let mut out: Vec<Val> = vec![];
+  for elem in l.iter().chain(&r) {
+    let in_left = l.iter().any(|e| same(e, elem));
+    let in_right = r.iter().any(|e| same(e, elem));
+    if keep(in_left, in_right) && !out.iter().any(|e| same(e, elem)) {
+      out.push(elem.clone());
+    }
+  }
+  Ok(sequence(out, array))
+}
+
+fn repeat(elems: &[Val], n: usize) -> Vec<Val> {
+  (0..n).flat_map(|_| elems.iter().cloned()).collect()
+}
+
+/// Whether `-` removes elements rather than subtracting: a list or array is
+/// involved, and either operand holds more than numbers. Errors count as
+/// numbers, so they are passed on either way.
+pub fn is_difference(left: &Val, right: &Val) -> bool {
+  let numeric = |val: &Val| match val {
+    Val::List(elems) | Val::Array{elems, ..} => elems.iter().all(|e| is_number(e) || matches!(e, Val::Error{..})),
+    val => is_number(val) || matches!(val, Val::Error{..}),
+  };
+  (is_sequence(left) || is_sequence(right)) && !(numeric(left) && numeric(right))
+}
+
+fn is_sequence(val: &Val) -> bool {
+  matches!(val, Val::List(_) | Val::Array{..})
+}
+
+/// Elements of a list or one dimensional array. Other values are a single
+/// element.
+fn elements(val: Val) -> Result<Vec<Val>, ErrorKind> {
+  match val {
+    Val::List(elems) => Ok(elems),
+    Val::Array{elems, axes} if axes.len() == 1 && axes[0] as usize == elems.len() => Ok(elems),
+    Val::Array{..} => Err(ErrorKind::Shape),
+    val => Ok(vec![val]),
+  }
+}
+
+fn sequence(elems: Vec<Val>, array: bool) -> Val {
+  match array {
+    true => Val::Array{ axes: vec![elems.len() as u32], elems },
+    false => Val::List(elems),
+  }
+}
+
+/// Equality of elements, with numbers of any type compared by value.
+pub fn same(a: &Val, b: &Val) -> bool {
+  match (number(a), number(b)) {
+    (Ok(a), Ok(b)) => a == b,
+    _ => a == b,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::Parser;
+  use rust_decimal::Decimal;
+
+  fn eval(formula: &str) -> Val {
+    let mut p = Parser::new(formula);
+    p.parse().unwrap().eval(&mut p)
+  }
+
+  fn nums(vals: &[i64]) -> Vec<Val> {
+    vals.iter().map(|v| Val::Num(Decimal::from(*v))).collect()
+  }
+
+  fn strs(vals: &[&str]) -> Vec<Val> {
+    vals.iter().map(|v| Val::Str(v.to_string())).collect()
+  }
+
+  #[test]
+  fn test_collection_ops() {
+    use Val::*;
+
+    assert_eq!(eval("(1, 2, 3) ++ (4, 5)"), List(nums(&[1, 2, 3, 4, 5])));
+    assert_eq!(eval("'hello ' ++ 'world'"), Str("hello world".to_owned()));
+    assert_eq!(eval("(1, 2) ++ 3"), List(nums(&[1, 2, 3])));
+    assert_eq!(eval("Array(1, 2) ++ (3, 4)"), Array{ elems: nums(&[1, 2, 3, 4]), axes: vec![4] });
+    // columns are joined side by side, and rows stacked
+    assert_eq!(eval("transpose((1, 4)) ++ transpose((2, 5)) ++ (3, 6)"), Array{ elems: nums(&[1, 2, 3, 4, 5, 6]), axes: vec![2, 3] });
+    assert_eq!(eval("transpose((1, 3)) ++ transpose((2, 4)) ++ transpose(transpose((5, 6)))"), Array{ elems: nums(&[1, 2, 3, 4, 5, 6]), axes: vec![3, 2] });
+    assert_eq!(eval("transpose((1, 3)) ++ (1, 2, 3)"), Val::error(ErrorKind::Shape, "transpose((1, 3)) ++ (1, 2, 3)"));
+    assert_eq!(eval("1 ++ 2"), Val::error(ErrorKind::Type, "1 ++ 2"));
+
+    assert_eq!(eval("'ab' ** 3"), Str("ababab".to_owned()));
+    assert_eq!(eval("('a', 'b') ** 2"), List(strs(&["a", "b", "a", "b"])));
+    assert_eq!(eval("transpose((1, 2)) ** 2"), Array{ elems: nums(&[1, 1, 2, 2]), axes: vec![2, 2] });
+    assert_eq!(eval("'a' ** 1.5"), Val::error(ErrorKind::Num, "'a' ** 1.5"));
+    assert_eq!(eval("'a' ** -1"), Val::error(ErrorKind::Num, "'a' ** -1"));
+    // results too long to build are errors rather than an allocation failure
+    assert_eq!(eval("'ab' ** 10000000000000000000"), Val::error(ErrorKind::Num, "'ab' ** 10000000000000000000"));
+    assert_eq!(eval("(1, 2) ** 10000000000"), Val::error(ErrorKind::Num, "(1, 2) ** 10000000000"));
+    assert_eq!(eval(&format!("Array(1) ** {}", MAX_LEN + 1)), Val::error(ErrorKind::Num, format!("Array(1) ** {}", MAX_LEN + 1)));
+    assert_eq!(eval("'' ** 10000000000000000000"), Str(String::new()));
+
+    assert_eq!(eval("(1, 2, 3) union (2, 3, 3, 7)"), List(nums(&[1, 2, 3, 7])));
+    assert_eq!(eval("(1, 1, 2) union 3"), List(nums(&[1, 2, 3])));
+    assert_eq!(eval("(3, 1, 2, 3) inter (3, 4, 2)"), List(nums(&[3, 2])));
+    assert_eq!(eval("Array(1, 2) inter (2, 3)"), Array{ elems: nums(&[2]), axes: vec![1] });
+    assert_eq!(eval("(1, 1 / 0) union 2"), Val::error(ErrorKind::Div0, "1 / 0"));
+
+    assert_eq!(eval("1 in (1, 2, 3)"), Bool(true));
+    assert_eq!(eval("'f' in 'foo'"), Bool(true));
+    assert_eq!(eval("4 in Array(1, 2)"), Bool(false));
+    assert_eq!(eval("1 + 1 in (1, 2)"), Bool(true));
+    assert_eq!(eval("1 in 2"), Val::error(ErrorKind::Type, "1 in 2"));
+
+    // `-` removes elements where a side holds more than numbers, whatever
+    // the lengths
+    assert_eq!(eval("('a', 'b') - 'b'"), List(strs(&["a"])));
+    assert_eq!(eval("('a', 'b', 'c', 'b') - ('b', 'c')"), List(strs(&["a"])));
+    assert_eq!(eval("('a', 'b') - ('b', 'c')"), List(strs(&["a"])));
+    assert_eq!(eval("('a', 1, 2, 1) - 1"), List(vec![Str("a".to_owned()), Val::Num(2.into())]));
+    assert_eq!(eval("(1, 2, 3) - ('x', 2)"), List(nums(&[1, 3])));
+    assert_eq!(eval("Array('a', 'b') - 'a'"), Array{ elems: strs(&["b"]), axes: vec![1] });
+    assert_eq!(eval("('a', 1 / 0) - 'a'"), Val::error(ErrorKind::Div0, "1 / 0"));
+    // and subtracts numbers, element by element or broadcast
+    assert_eq!(eval("(1, 2) - (1, 1)"), List(nums(&[0, 1])));
+    assert_eq!(eval("(1, 2) - 2"), List(nums(&[-1, 0])));
+    assert_eq!(eval("(1, 2, 3) - (2, 3)"), Val::error(ErrorKind::Shape, "(1, 2, 3) - (2, 3)"));
+    assert_eq!(eval("'a' - 'b'"), Val::error(ErrorKind::Type, "'a' - 'b'"));
+  }
+}
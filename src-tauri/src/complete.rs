@@ -0,0 +1,211 @@
+use crate::board::Board;
+use crate::builtins::BUILTINS;
+use crate::cell::CellOps;
+use crate::parser::KEYWORDS;
+use crate::rpc::{CompletionKindUi, CompletionUi};
+use crate::tile::TileId;
+
+/// What kind of name is expected at the cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+  /// Anywhere an expression can start.
+  Expr,
+  /// Inside an address, `{...}`, or after `@`.
+  Label,
+  /// After `&`.
+  Tile,
+}
+
+impl Context {
+  /// Ranks candidate kinds for this context, lower is better.
+  /// Kinds that are invalid in the context are excluded.
+  fn rank(&self, kind: CompletionKindUi) -> Option<u8> {
+    use CompletionKindUi::*;
+    match (self, kind) {
+      (Context::Tile, Tile) => Some(0),
+      (Context::Tile, _) => None,
+      (Context::Label, Label) => Some(0),
+      (Context::Label, Function) => Some(1),
+      (Context::Label, Keyword) => Some(2),
+      (Context::Expr, Function) => Some(0),
+      (Context::Expr, Keyword) => Some(1),
+      _ => None,
+    }
+  }
+}
+
+fn is_word(ch: char) -> bool {
+  ch.is_alphanumeric() || ch == '_'
+}
+
+/// Finds the start of the word ending at `cursor` and the context it is typed
+/// in. Returns `None` inside string literals.
+fn context(chars: &[char], cursor: usize) -> Option<(Context, usize)> {
+  let mut start = cursor;
+  while start > 0 && is_word(chars[start - 1]) {
+    start -= 1;
+  }
+  let mut before = start;
+  if before > 0 && chars[before - 1] == '$' {
+    before -= 1;
+  }
+
+  let mut open = vec![];
+  let mut quote = None;
+  for &ch in &chars[..before] {
+    match (quote, ch) {
+      (Some(q), c) if c == q => quote = None,
+      (Some(_), _) => (),
+      (None, '\'' | '"') => quote = Some(ch),
+      (None, '(' | '[' | '{') => open.push(ch),
+      (None, ')' | ']' | '}') => {
+        open.pop();
+      },
+      _ => (),
+    }
+  }
+  if quote.is_some() {
+    return None;
+  }
+
+  let ctx = match (before.checked_sub(1).map(|i| chars[i]), open.last()) {
+    (Some('&'), _) => Context::Tile,
+    (Some('@'), _) => Context::Label,
+    (_, Some('{')) => Context::Label,
+    _ => Context::Expr,
+  };
+  Some((ctx, start))
+}
+
+/// Ranks how well `label` matches the typed `prefix`, lower is better.
+fn match_rank(label: &str, prefix: &str) -> Option<u8> {
+  let lower = label.to_lowercase();
+  let prefix_lower = prefix.to_lowercase();
+  if label.starts_with(prefix) {
+    Some(0)
+  } else if lower.starts_with(&prefix_lower) {
+    Some(1)
+  } else if lower.contains(&prefix_lower) {
+    Some(2)
+  } else {
+    None
+  }
+}
+
+/// Quotes names that are not valid symbols, e.g. `'price target'`.
+fn quoted(name: &str) -> String {
+  if !name.is_empty() && name.chars().all(is_word) {
+    name.to_owned()
+  } else {
+    format!("'{name}'")
+  }
+}
+
+/// Completion candidates for `formula`, being edited in tile `tag` with the
+/// cursor at UTF-16 offset `cursor`.
+pub fn complete<V: CellOps>(board: &Board<V>, tag: TileId, formula: &str, cursor: u32) -> Vec<CompletionUi> {
+  let chars: Vec<char> = formula.chars().collect();
+  let mut utf16 = vec![0];
+  for ch in chars.iter() {
+    utf16.push(utf16[utf16.len() - 1] + ch.len_utf16() as u32);
+  }
+  let cursor = utf16.iter().position(|&u| u >= cursor).unwrap_or(chars.len());
+
+  let (ctx, start) = match context(&chars, cursor) {
+    Some(found) => found,
+    None => return vec![],
+  };
+  let prefix: String = chars[start..cursor].iter().collect();
+
+  use CompletionKindUi::*;
+  let mut candidates: Vec<(CompletionKindUi, String, String)> = vec![];
+  if let Some(tile) = board.get_tile(tag) {
+    for lbl in tile.col_labels() {
+      candidates.push((Label, quoted(lbl), "column".to_owned()));
+    }
+    for lbl in tile.row_labels() {
+      candidates.push((Label, quoted(lbl), "row".to_owned()));
+    }
+  }
+  for tile in board.tiles() {
+    candidates.push((Tile, quoted(&tile.name), format!("tile {}", tile.tag.0)));
+  }
+  for def in board.funcs().iter() {
+    candidates.push((Function, def.name.clone(), def.sig()));
+  }
+  for builtin in BUILTINS {
+    candidates.push((Function, builtin.name.to_owned(), builtin.sig.to_owned()));
+  }
+  for kw in KEYWORDS {
+    candidates.push((Keyword, kw.to_owned(), "keyword".to_owned()));
+  }
+
+  let mut ranked: Vec<((u8, u8), CompletionUi)> = candidates.into_iter().filter_map(|(kind, label, detail)| {
+    let rank = (ctx.rank(kind)?, match_rank(&label, &prefix)?);
+    Some((rank, CompletionUi {
+      kind,
+      label,
+      detail,
+      start: utf16[start],
+      end: utf16[cursor],
+    }))
+  }).collect();
+
+  // stable, so candidates of equal rank keep tile and table order
+  ranked.sort_by_key(|(rank, _)| *rank);
+  ranked.into_iter().map(|(_, c)| c).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cell::Cell;
+
+  fn labels(formula: &str, cursor: usize) -> Vec<String> {
+    let (mut board, tile) = Board::<Cell>::example();
+    let other = board.add_tile();
+    board.mut_tile(other).unwrap().name = "Food Prices".to_owned();
+    complete(&board, tile, formula, cursor as u32).into_iter().map(|c| c.label).collect()
+  }
+
+  #[test]
+  fn test_complete_context() {
+    let lbls = labels("{", 1);
+    assert_eq!(lbls[..5], ["A", "B", "1", "2", "3"]);
+    assert!(lbls.contains(&"sum".to_owned()));
+
+    assert_eq!(labels("{b", 2)[0], "B");
+    assert_eq!(labels("@", 1)[..2], ["A", "B"]);
+    assert_eq!(labels("1 + {A, $", 9)[0], "A");
+
+    assert_eq!(labels("2 * &", 5), ["Tile0", "'Food Prices'"]);
+    assert_eq!(labels("2 * &fo", 7), ["'Food Prices'"]);
+
+    let lbls = labels("1 + ", 4);
+    assert_eq!(lbls[0], "abs");
+    assert!(!lbls.contains(&"A".to_owned()));
+    assert_eq!(labels("1 + su", 6), ["sum"]);
+    assert_eq!(labels("tr", 2), ["transpose", "true"]);
+  }
+
+  #[test]
+  fn test_complete_edges() {
+    assert_eq!(labels("'su", 3), Vec::<String>::new());
+    assert_eq!(labels("{A, B} + su", 11), ["sum"]);
+
+    let (board, tile) = Board::<Cell>::example();
+    let res = complete(&board, tile, "'😀' + su", 9);
+    assert_eq!(res[0].label, "sum");
+    assert_eq!((res[0].start, res[0].end), (7, 9));
+    assert_eq!(res[0].detail, "sum(values...)");
+  }
+
+  #[test]
+  fn test_complete_funcs() {
+    let (mut board, tile) = Board::<Cell>::example();
+    board.define("def noi(rent, vacancy, opex) = rent * (1 - vacancy) - opex").unwrap();
+    let res = complete(&board, tile, "1 + no", 6);
+    assert_eq!(res[0].label, "noi");
+    assert_eq!(res[0].detail, "noi(rent, vacancy, opex)");
+  }
+}
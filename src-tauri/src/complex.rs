@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::cell::{ErrorKind, Val};
+
+/// A complex number, in floating point like `Float`. Written with the
+/// constant `i`, `3 + 4 * i`.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Complex {
+  pub re: f64,
+  pub im: f64,
+}
+
+/// The imaginary unit.
+pub const I: Complex = Complex{ re: 0.0, im: 1.0 };
+
+impl Complex {
+  pub fn new(re: f64, im: f64) -> Complex {
+    Complex{ re, im }
+  }
+
+  /// The result of a computation, a `Float` when the imaginary part is zero
+  /// so that `i * i` is `-1`. Infinite or `NaN` parts are a `Num` error.
+  pub fn into_val(self) -> Result<Val, ErrorKind> {
+    if !self.re.is_finite() || !self.im.is_finite() {
+      Err(ErrorKind::Num)
+    } else if self.im == 0.0 {
+      Ok(Val::Float(self.re))
+    } else {
+      Ok(Val::Complex(self))
+    }
+  }
+
+  pub fn magnitude(self) -> f64 {
+    self.re.hypot(self.im)
+  }
+
+  /// Angle from the positive real axis, between `-π` and `π`.
+  pub fn phase(self) -> f64 {
+    self.im.atan2(self.re)
+  }
+
+  pub fn arith(op: char, a: Complex, b: Complex) -> Result<Complex, ErrorKind> {
+    Ok(match op {
+      '+' => Complex::new(a.re + b.re, a.im + b.im),
+      '-' => Complex::new(a.re - b.re, a.im - b.im),
+      '*' => a.mul(b),
+      '/' => a.div(b)?,
+      _ => return Err(ErrorKind::Type),
+    })
+  }
+
+  fn mul(self, b: Complex) -> Complex {
+    Complex::new(self.re * b.re - self.im * b.im, self.re * b.im + self.im * b.re)
+  }
+
+  fn div(self, b: Complex) -> Result<Complex, ErrorKind> {
+    let norm = b.re * b.re + b.im * b.im;
+    if norm == 0.0 {
+      return Err(ErrorKind::Div0);
+    }
+    Ok(Complex::new((self.re * b.re + self.im * b.im) / norm, (self.im * b.re - self.re * b.im) / norm))
+  }
+
+  /// Integer powers by repeated multiplication, so `i ^ 2` is exactly `-1`.
+  pub fn powi(self, n: i64) -> Result<Complex, ErrorKind> {
+    let (mut res, mut base, mut k) = (Complex::new(1.0, 0.0), self, n.unsigned_abs());
+    while k > 0 {
+      if k & 1 == 1 {
+        res = res.mul(base);
+      }
+      base = base.mul(base);
+      k >>= 1;
+    }
+    match n < 0 {
+      true => Complex::new(1.0, 0.0).div(res),
+      false => Ok(res),
+    }
+  }
+
+  /// Principal value of `self ^ w`, `exp(w * ln(self))`. Parts that are
+  /// only rounding error are dropped, so `(-1) ^ 0.5` is `i`.
+  pub fn pow(self, w: Complex) -> Result<Complex, ErrorKind> {
+    if self.re == 0.0 && self.im == 0.0 {
+      return match w.re > 0.0 {
+        true => Ok(self),
+        false => Err(ErrorKind::Div0),
+      };
+    }
+    let ln = Complex::new(self.magnitude().ln(), self.phase());
+    let exp = ln.mul(w);
+    let (len, angle) = (exp.re.exp(), exp.im);
+    let snap = |part: f64| if part.abs() < len * 1e-15 { 0.0 } else { part };
+    Ok(Complex::new(snap(len * angle.cos()), snap(len * angle.sin())))
+  }
+}
+
+impl fmt::Display for Complex {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let im = if self.im == 1.0 {
+      String::new()
+    } else if self.im == -1.0 {
+      "-".to_owned()
+    } else {
+      self.im.to_string()
+    };
+    if self.re == 0.0 {
+      write!(f, "{im}i")
+    } else if self.im < 0.0 {
+      write!(f, "{}{im}i", self.re)
+    } else {
+      write!(f, "{}+{im}i", self.re)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_complex_ops() {
+    let z = Complex::new(3.0, 4.0);
+    assert_eq!(z.magnitude(), 5.0);
+    assert_eq!(Complex::arith('*', z, I), Ok(Complex::new(-4.0, 3.0)));
+    assert_eq!(Complex::arith('/', z, Complex::new(0.0, 2.0)), Ok(Complex::new(2.0, -1.5)));
+    assert_eq!(Complex::arith('/', z, Complex::new(0.0, 0.0)), Err(ErrorKind::Div0));
+
+    assert_eq!(I.powi(2), Ok(Complex::new(-1.0, 0.0)));
+    assert_eq!(I.powi(-1), Ok(Complex::new(0.0, -1.0)));
+    assert_eq!(z.powi(0), Ok(Complex::new(1.0, 0.0)));
+    assert_eq!(Complex::new(-1.0, 0.0).pow(Complex::new(0.5, 0.0)), Ok(I));
+    let root = Complex::new(-8.0, 0.0).pow(Complex::new(1.0 / 3.0, 0.0)).unwrap();
+    assert!((root.magnitude() - 2.0).abs() < 1e-12 && (root.phase() - std::f64::consts::FRAC_PI_3).abs() < 1e-12);
+    assert_eq!(Complex::new(0.0, 0.0).pow(Complex::new(-1.0, 0.0)), Err(ErrorKind::Div0));
+
+    assert_eq!(I.powi(2).and_then(Complex::into_val), Ok(Val::Float(-1.0)));
+    assert_eq!(Complex::new(f64::INFINITY, 1.0).into_val(), Err(ErrorKind::Num));
+
+    let printed = ["3+4i", "3-4i", "i", "-i", "2.5i", "-1+i"];
+    let zs = [z, Complex::new(3.0, -4.0), I, Complex::new(0.0, -1.0), Complex::new(0.0, 2.5), Complex::new(-1.0, 1.0)];
+    for (z, printed) in zs.iter().zip(printed) {
+      assert_eq!(z.to_string(), printed);
+    }
+  }
+}
@@ -6,6 +6,12 @@ pub enum Err {
   Parse{pos: usize},
   Eval(),
   Num(),
+  Cycle{path: Vec<String>},
+  Op{symbol: String},
+  Undefined{name: String},
+  Untranslatable{names: Vec<String>},
+  Corrupt{pos: usize},
+  Version{found: u16},
 }
 
 impl Display for Err {
@@ -18,6 +24,36 @@ impl Display for Err {
       },
       Err::Eval() => f.write_str("Err::Eval")?,
       Err::Num() => f.write_str("Err::Num")?,
+      Err::Cycle{path} => {
+        f.write_str("Err::Cycle{path: ")?;
+        f.write_str(&path.join(" -> "))?;
+        f.write_str("}")?;
+      },
+      Err::Op{symbol} => {
+        f.write_str("Err::Op{symbol: ")?;
+        f.write_str(symbol)?;
+        f.write_str("}")?;
+      },
+      Err::Undefined{name} => {
+        f.write_str("Err::Undefined{name: ")?;
+        f.write_str(name)?;
+        f.write_str("}")?;
+      },
+      Err::Untranslatable{names} => {
+        f.write_str("Err::Untranslatable{names: ")?;
+        f.write_str(&names.join(", "))?;
+        f.write_str("}")?;
+      },
+      Err::Corrupt{pos} => {
+        f.write_str("Err::Corrupt{pos: ")?;
+        pos.fmt(f)?;
+        f.write_str("}")?;
+      },
+      Err::Version{found} => {
+        f.write_str("Err::Version{found: ")?;
+        found.fmt(f)?;
+        f.write_str("}")?;
+      },
     };
     Ok(())
   }
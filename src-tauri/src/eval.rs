@@ -1,22 +1,31 @@
-use std::cmp::min;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use crate::board::Board;
-use crate::parser::{ValueId, NodeId};
-use crate::cell::{Val, Cell, CellId, CellRef};
-use crate::tile::{TileId, TileState};
+use crate::builtins::{self, Func};
+use crate::closure::{apply, Closure};
+use crate::collection;
+use crate::complex::{self, Complex};
+use crate::funcs::Funcs;
+use crate::linalg;
+use crate::parser::{Assoc, ValueId, NodeId, ElemRange};
+use crate::rounding::Rounding;
+use crate::cell::{Val, Cell, CellId, ErrorKind};
+use crate::tile::{Axis, TileId, TileState};
 use crate::tile::TileContext;
 
 use log_derive::{logfn, logfn_inputs};
 use rust_decimal::Decimal;
-use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
 #[allow(unused)]
 use rust_decimal_macros::dec;
 
 pub struct MainContext<'a> {
   pub parser: &'a dyn ObjectContext,
   pub state: &'a mut TileState<'a>,
+  pub funcs: &'a Funcs,
+  pub rounding: Rounding,
 }
 
 impl Debug for MainContext<'_> {
@@ -29,6 +38,9 @@ impl<'a> ObjectContext for MainContext<'a> {
   fn get_node(&self, node: &NodeId) -> &Node {
     self.parser.get_node(node)
   }
+  fn get_elems(&self, elems: &ElemRange) -> &[NodeId] {
+    self.parser.get_elems(elems)
+  }
   fn get_value(&self, node: &ValueId) -> &Val {
     self.parser.get_value(node)
   }
@@ -38,26 +50,53 @@ impl<'a> ObjectContext for MainContext<'a> {
 impl<'a> TileContext for MainContext<'a> {
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
-  fn get_cell<const CARD: usize, R: Into<CellRef<CARD>>+std::fmt::Debug>(&mut self, cellref: R) -> (CellId, Cell) {
-    self.state.get_cell(cellref)
+  fn get_range(&mut self, tile: Option<&Val>, axes: [Axis; 2]) -> Result<Val, ErrorKind> {
+    self.state.get_range(tile, axes)
+  }
+}
+
+impl FuncContext for MainContext<'_> {
+  fn get_func(&self, name: &str) -> Option<Arc<Closure>> {
+    self.funcs.get(name).map(|def|def.closure.clone())
+  }
+  fn get_op(&self, symbol: &str) -> Option<String> {
+    self.funcs.ops().get(symbol).map(|op|op.func.clone())
+  }
+  fn rounding(&self) -> Rounding {
+    self.rounding
   }
 }
 
 pub trait ObjectContext {
   fn get_value(&self, value: &ValueId) -> &Val;
   fn get_node(&self, node: &NodeId) -> &Node;
+  fn get_elems(&self, elems: &ElemRange) -> &[NodeId];
+}
+
+/// Functions and operators defined on the board, usable from any formula,
+/// and the board's rounding policy.
+pub trait FuncContext {
+  fn get_func(&self, name: &str) -> Option<Arc<Closure>>;
+  /// Name of the function called by the operator `symbol`.
+  fn get_op(&self, symbol: &str) -> Option<String>;
+  fn rounding(&self) -> Rounding;
+  /// Closure calls the evaluation is nested in, none outside a closure.
+  fn depth(&self) -> u32 {
+    0
+  }
 }
 
 pub trait EvalContext:
-  ObjectContext + TileContext {}
+  ObjectContext + TileContext + FuncContext {}
 
 impl<T> EvalContext for T where T:
-  ObjectContext + TileContext {}
+  ObjectContext + TileContext + FuncContext {}
 
 #[derive(Debug)]
 pub struct EvalState<'a> {
   nodes: HashMap<NodeId, Node>,
   values: HashMap<ValueId, Val>,
+  elems: Vec<NodeId>,
   pub board: &'a mut Board<Cell>,
   cell: CellId,
   tile: TileId,
@@ -69,6 +108,7 @@ impl EvalState<'_> {
     EvalState{
       nodes: HashMap::new(),
       values: HashMap::new(),
+      elems: vec![],
       board: board,
       cell: cell_id,
       tile: tile_id,
@@ -86,6 +126,12 @@ impl EvalState<'_> {
     }
   }
 
+  pub fn push_elems(&mut self, elems: &[NodeId]) -> ElemRange {
+    let start = self.elems.len() as u32;
+    self.elems.extend_from_slice(elems);
+    ElemRange{ start, len: elems.len() as u32 }
+  }
+
   pub fn push_value(&mut self, value: Val) -> ValueId{
     let id = ValueId(self.values.len() as u32);
     self.values.insert(id, value);
@@ -100,33 +146,114 @@ impl ObjectContext for EvalState<'_> {
   fn get_node(&self, node: &NodeId) -> &Node {
     self.nodes.get(node).unwrap()
   }
+  fn get_elems(&self, elems: &ElemRange) -> &[NodeId] {
+    &self.elems[elems.start as usize..(elems.start + elems.len) as usize]
+  }
 }
 
 impl TileContext for EvalState<'_> {
-  fn get_cell<const CARD: usize, R: Into<CellRef<CARD>>>(&mut self, cellref: R) -> (CellId, Cell) {
-    let cellref: CellRef<CARD> = cellref.into();
-    let tile = self.board.mut_tile(self.tile).unwrap();
-    
-    tile.track_dep(self.cell, cellref.clone());
+  fn get_range(&mut self, tile: Option<&Val>, axes: [Axis; 2]) -> Result<Val, ErrorKind> {
+    let tag = match tile {
+      Some(name) => self.board.tiles().find(|tile| tile.is_named(name)).ok_or(ErrorKind::Ref)?.tag,
+      None => self.tile,
+    };
+    let tile = self.board.mut_tile(tag).unwrap();
+    match tag == self.tile {
+      true => tile.get_range(self.cell, axes),
+      false => tile.range(axes).map(|(_, val)| val).ok_or(ErrorKind::Ref),
+    }
+  }
+}
 
-    (tile.resolve(cellref.clone()), tile.get_cell(cellref))
+impl FuncContext for EvalState<'_> {
+  fn get_func(&self, name: &str) -> Option<Arc<Closure>> {
+    self.board.funcs().get(name).map(|def|def.closure.clone())
+  }
+  fn get_op(&self, symbol: &str) -> Option<String> {
+    self.board.funcs().ops().get(symbol).map(|op|op.func.clone())
+  }
+  fn rounding(&self) -> Rounding {
+    self.board.rounding()
   }
 }
 
 
 
-pub const LIST_ELEMS: usize = 8;
+/// Variables bound by `let`, innermost first.
+///
+/// Binding returns a new scope sharing its parent, so scopes are cheap to
+/// clone and never see bindings made after them.
+#[derive(Clone, Debug, Default)]
+pub struct Env(Option<Arc<Binding>>);
+
+#[derive(Debug)]
+struct Binding {
+  name: String,
+  value: Val,
+  parent: Env,
+}
+
+impl Env {
+  pub fn bind(&self, name: String, value: Val) -> Env {
+    Env(Some(Arc::new(Binding{ name, value, parent: self.clone() })))
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Val> {
+    let mut curr = &self.0;
+    while let Some(binding) = curr {
+      if binding.name == name {
+        return Some(&binding.value);
+      }
+      curr = &binding.parent.0;
+    }
+    None
+  }
+
+  /// Bindings in scope, innermost first, including shadowed ones.
+  pub fn bindings(&self) -> Vec<(&str, &Val)> {
+    let mut bindings = vec![];
+    let mut curr = &self.0;
+    while let Some(binding) = curr {
+      bindings.push((binding.name.as_str(), &binding.value));
+      curr = &binding.parent.0;
+    }
+    bindings
+  }
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 #[allow(unused)]
 pub enum Node {
   Zero{},
   Leaf{value: ValueId},
+  Sym{value: ValueId},
   BinOp{op: char, lhs: NodeId, rhs: NodeId},
+  /// A board operator, `op` holding its symbol. Precedence and
+  /// associativity are kept from parsing so the node prints without the board.
+  CustomOp{op: ValueId, prec: u8, assoc: Assoc, lhs: NodeId, rhs: NodeId},
   UniOp{op: char, rhs: NodeId},
   Index{row: NodeId, col: NodeId},
   Addr{row: NodeId, col: NodeId},
-  List{elems: [NodeId; LIST_ELEMS], len: usize, link: Option<NodeId>},
+  Call{func: NodeId, args: NodeId},
+  List{elems: ElemRange},
+  /// `let name = value, ... in body`, with `binds` holding name and value
+  /// nodes in turn.
+  Let{binds: ElemRange, body: NodeId},
+  /// `(param, ...) -> body`, with `params` holding symbol nodes.
+  Lambda{params: ElemRange, body: NodeId},
+  /// A record field, `key: value`. Lists of fields are records.
+  Field{key: NodeId, value: NodeId},
+  /// Positions from `start` up to `end` in a reference, `[0, 1:3]`. Absent
+  /// bounds are `NodeId(0)`.
+  Slice{start: NodeId, end: NodeId},
+  /// A reference into another tile, `&Units{[:], Rent}`, by name or id.
+  TileRef{tile: NodeId, cell: NodeId},
+  /// `if cond then else other`.
+  If{cond: NodeId, then: NodeId, other: NodeId},
+  /// `match subject as pattern result ... else other`, with `arms` holding
+  /// pattern and result nodes in turn. Without `else`, `other` is
+  /// `NodeId(0)`.
+  Match{subject: NodeId, arms: ElemRange, other: NodeId},
 }
 
   use Node::*;
@@ -139,91 +266,662 @@ impl Default for Node {
 
 impl Node {
   pub fn eval(&self, ctx: &mut impl EvalContext) -> Val {
+    self.eval_env(ctx, &Env::default())
+  }
+
+  /// Evaluates the node with the variables in scope at its position.
+  pub fn eval_env(&self, ctx: &mut impl EvalContext, env: &Env) -> Val {
     match self {
       Leaf{value} => ctx.get_value(value).to_owned(),
-      BinOp{op, lhs, rhs} => {
-        let lnode = *ctx.get_node(lhs);
-        let rnode = *ctx.get_node(rhs);
-        let left = lnode.eval(ctx);
-        let right = rnode.eval(ctx);
-
-        use Val::*;
-
-        let f: fn(Decimal, Decimal) -> Decimal = match *op {
-          '+' => |l,r|l + r,
-          '-' => |l,r|l - r,
-          '/' => |l,r|l / r,
-          '*' => |l,r|l * r,
-          _ => |_l, _r|Decimal::new(0, 0),
-        };
-
-        match (left, right) {
-          (List(l), Num(r)) => List(
-            l.iter().map(|v|{
-              let d = Decimal::from(v);
-              Num(f(d, r))
-            }).collect()
-          ),
-          (Num(l), List(r)) => List(
-            r.iter().map(|v|{
-              let d = Decimal::from(v);
-              Num(f(l, d))
-            }).collect()
-          ),
-          (Num(l), Num(r)) => Num(f(l,r)),
-          (Num(l), Int(r)) => Num(f(l, Decimal::from(r))),
-          (Int(l), Num(r)) => Num(f(Decimal::from(l), r)),
-          (Num(l), Float(r)) => Num(f(l, Decimal::from_f64(r).unwrap())),
-          (Float(l), Num(r)) => Num(f(Decimal::from_f64(l).unwrap(), r)),
-          (Num(l), Bool(r)) => Num(f(l, Decimal::from(&Bool(r)))),
-          (Bool(l), Num(r)) => Num(f(Decimal::from(&Bool(l)), r)),
-          _ => Val::Num(Decimal::from(0)),
+      Sym{value} => {
+        let name = ctx.get_value(value);
+        match env.get(&name.to_string()) {
+          Some(bound) => bound.clone(),
+          None => constant(&name.to_string()).unwrap_or_else(|| name.to_owned()),
         }
       },
+      Let{binds, body} => let_in(ctx, env, binds, body),
+      Lambda{params, body} => {
+        let params = ctx.get_elems(params).iter().filter_map(|p| match ctx.get_node(p) {
+          Sym{value} => Some(ctx.get_value(value).to_string()),
+          _ => None,
+        }).collect();
+        Val::Lambda(Arc::new(Closure::new(ctx, params, body, env.clone())))
+      },
+      UniOp{op: '$', rhs} => {
+        let rnode = *ctx.get_node(rhs);
+        rnode.eval_env(ctx, env)
+      },
+      UniOp{op: '¬', ..} | BinOp{op: '∧' | '∨' | '⊻', ..} => logic(ctx, env, self),
+      If{cond, then, other} => if_else(ctx, env, cond, then, other),
+      Match{subject, arms, other} => match_arms(ctx, env, subject, arms, other),
+      BinOp{..} | CustomOp{..} => operator(ctx, env, self),
 
-      List { elems, len, link } => {
-        let clamped_len = min(*len, LIST_ELEMS);
-        let mut vals: Vec<Val> = elems.iter().take(clamped_len).map(|nid|{
+      List { elems } => {
+        let elems = ctx.get_elems(elems).to_vec();
+        let vals = elems.iter().map(|nid|{
           let node = *ctx.get_node(nid);
-          node.eval(ctx)
+          node.eval_env(ctx, env)
         }).collect();
-
-        if *len > clamped_len {
-          let get_node = *ctx.get_node(&link.unwrap());
-          let rest = get_node.eval(ctx);
-          match rest {
-            Val::List(l) => vals.extend(l),
-            _ => (),
-          }
+        // a list of fields is a record, `name: 'Daniel', id: 17`
+        match !elems.is_empty() && elems.iter().all(|nid|matches!(ctx.get_node(nid), Field{..})) {
+          true => record(vals),
+          false => Val::List(vals),
         }
-        Val::List(vals)
       }
 
-      Index { row, col } => {
-        let row = *ctx.get_node(row);
-        let col = *ctx.get_node(col);
-        let r: i64 = row.eval(ctx).into();
-        let c: i64 = col.eval(ctx).into();
+      Field { key, value } => {
+        let value = *ctx.get_node(value);
+        let value = value.eval_env(ctx, env);
+        field(ctx, key, value)
+      }
+
+      Index{..} | Addr{..} | TileRef{..} => reference(ctx, env, self),
+
+      Call { func, args } => {
+        let func = *ctx.get_node(func);
+        let args = *ctx.get_node(args);
+        let func = func.eval_env(ctx, env);
+        let args = eval_args(ctx, env, &args);
+        let res = call(ctx, func, args);
+        ctx.rounding().intermediate(res)
+      }
 
-        let (_id, cell) = ctx.get_cell([r as usize, c as usize]);
-        cell.value
+      _ => Val::default(),
+    }
+  }
+}
+
+/// `let name = value, ... in body`, each value seeing the names before it.
+fn let_in(ctx: &mut impl EvalContext, env: &Env, binds: &ElemRange, body: &NodeId) -> Val {
+  let binds = ctx.get_elems(binds).to_vec();
+  let mut scope = env.clone();
+  for pair in binds.chunks_exact(2) {
+    let name = ctx.get_value(match ctx.get_node(&pair[0]) {
+      Node::Sym{value} => value,
+      _ => continue,
+    }).to_string();
+    let value = *ctx.get_node(&pair[1]);
+    let value = value.eval_env(ctx, &scope);
+    scope = scope.bind(name, value);
+  }
+  let body = *ctx.get_node(body);
+  body.eval_env(ctx, &scope)
+}
+
+/// `if cond then else other`, evaluating only the branch taken.
+fn if_else(ctx: &mut impl EvalContext, env: &Env, cond: &NodeId, then: &NodeId, other: &NodeId) -> Val {
+  let cond = *ctx.get_node(cond);
+  let branch = match condition(cond.eval_env(ctx, env)) {
+    Ok(true) => then,
+    Ok(false) => other,
+    Err(err) => return err.unwrap_or_else(|| Val::error(ErrorKind::Type, cond.print(&*ctx))),
+  };
+  let branch = *ctx.get_node(branch);
+  branch.eval_env(ctx, env)
+}
+
+/// A builtin or board binary operator, rounding the result if intermediate
+/// results are rounded.
+fn operator(ctx: &mut impl EvalContext, env: &Env, node: &Node) -> Val {
+  let (lhs, rhs) = match *node {
+    Node::BinOp{lhs, rhs, ..} | Node::CustomOp{lhs, rhs, ..} => (*ctx.get_node(&lhs), *ctx.get_node(&rhs)),
+    _ => return Val::default(),
+  };
+  let res = match *node {
+    Node::CustomOp{op, ..} => match ctx.get_op(&ctx.get_value(&op).to_string()) {
+      Some(func) => {
+        let args = vec![lhs.eval_env(ctx, env), rhs.eval_env(ctx, env)];
+        call(ctx, Val::Str(func), args)
       },
+      None => return Val::error(ErrorKind::Ref, node.print(&*ctx)),
+    },
+    Node::BinOp{op, ..} => {
+      let left = lhs.eval_env(ctx, env);
+      let right = rhs.eval_env(ctx, env);
+      binop(op, left, right).unwrap_or_else(|kind| Val::error(kind, node.print(&*ctx)))
+    },
+    _ => Val::default(),
+  };
+  ctx.rounding().intermediate(res)
+}
+
+/// `not`, `and`, `or` and `xor`. The right operand of `and` and `or` is
+/// only evaluated if the left doesn't decide.
+fn logic(ctx: &mut impl EvalContext, env: &Env, node: &Node) -> Val {
+  let res = match *node {
+    Node::UniOp{rhs, ..} => {
+      let rnode = *ctx.get_node(&rhs);
+      condition(rnode.eval_env(ctx, env)).map(|b| !b)
+    },
+    Node::BinOp{op: '⊻', lhs, rhs} => {
+      let lnode = *ctx.get_node(&lhs);
+      let rnode = *ctx.get_node(&rhs);
+      let (left, right) = (lnode.eval_env(ctx, env), rnode.eval_env(ctx, env));
+      match (condition(left), condition(right)) {
+        (Ok(l), Ok(r)) => Ok(l != r),
+        (Err(Some(err)), _) | (_, Err(Some(err))) => Err(Some(err)),
+        _ => Err(None),
+      }
+    },
+    Node::BinOp{op, lhs, rhs} => {
+      let lnode = *ctx.get_node(&lhs);
+      match condition(lnode.eval_env(ctx, env)) {
+        Ok(l) if l == (op == '∨') => Ok(l),
+        Ok(_) => {
+          let rnode = *ctx.get_node(&rhs);
+          condition(rnode.eval_env(ctx, env))
+        },
+        Err(err) => Err(err),
+      }
+    },
+    _ => Err(None),
+  };
+  match res {
+    Ok(b) => Val::Bool(b),
+    Err(err) => err.unwrap_or_else(|| Val::error(ErrorKind::Type, node.print(&*ctx))),
+  }
+}
+
+/// `match subject as pattern result ... else other`. Patterns are tried in
+/// turn, so those after the match aren't evaluated.
+fn match_arms(ctx: &mut impl EvalContext, env: &Env, subject: &NodeId, arms: &ElemRange, other: &NodeId) -> Val {
+  let subject = *ctx.get_node(subject);
+  let value = subject.eval_env(ctx, env);
+  if value.is_error() {
+    return value;
+  }
+  let arms = ctx.get_elems(arms).to_vec();
+  let mut branch = None;
+  for pair in arms.chunks_exact(2) {
+    let pattern = *ctx.get_node(&pair[0]);
+    let pattern = pattern.eval_env(ctx, env);
+    if pattern.is_error() {
+      return pattern;
+    }
+    if collection::same(&pattern, &value) {
+      branch = Some(pair[1]);
+      break;
+    }
+  }
+  match branch.or((*other != NodeId(0)).then_some(*other)) {
+    Some(branch) => {
+      let branch = *ctx.get_node(&branch);
+      branch.eval_env(ctx, env)
+    },
+    None => Val::error(ErrorKind::NoMatch, value.to_string()),
+  }
+}
 
-      Addr { row, col } => {
-        let row = *ctx.get_node(row);
-        let col = *ctx.get_node(col);
-        let r: String = row.eval(ctx).into();
-        let c: String = col.eval(ctx).into();
+/// A value used as a condition: a boolean, or a number that is true unless
+/// zero. Errors in it are passed on, while other values are `Err(None)`,
+/// a `Type` error of the expression using them.
+pub fn condition(val: Val) -> Result<bool, Option<Val>> {
+  match val {
+    Val::Bool(b) => Ok(b),
+    Val::Float(f) => Ok(f != 0.0),
+    err @ Val::Error{..} => Err(Some(err)),
+    val => match number(&val) {
+      Ok(n) => Ok(!n.is_zero()),
+      Err(_) => Err(None),
+    },
+  }
+}
+
+/// Applies a builtin binary operator. Collection operators are in
+/// `collection`. `-` is a set difference where a list or array holds more
+/// than numbers, and `**` by a number extends a collection rather than
+/// crossing vectors.
+fn binop(op: char, left: Val, right: Val) -> Result<Val, ErrorKind> {
+  use Val::*;
+  match (op, left, right) {
+    (_, err @ Error{..}, _) | (_, _, err @ Error{..}) => Ok(err),
+    ('⧺', l, r) => collection::concat(l, r),
+    ('∪', l, r) => collection::union(l, r),
+    ('∩', l, r) => collection::inter(l, r),
+    ('∈', l, r) => collection::contains(&l, &r),
+    ('×', l, r) if is_number(&r) => collection::extend(l, &r),
+    ('-', l, r) if collection::is_difference(&l, &r) => collection::difference(l, r),
+    (op, l, r) => arith(op, l, r),
+  }
+}
 
-        let (_id, cell) = ctx.get_cell([r, c]);
-        cell.value
+/// Applies an arithmetic operator. Errors in the operands are passed on, the
+/// left one first, while `Err` is an error raised by the operator itself.
+///
+/// Lists are computed element by element, against a number or a list of the
+/// same length. Arrays are broadcast against each other, lists and numbers,
+/// except that `*` is the dot product of arrays and `**` the cross product.
+fn arith(op: char, left: Val, right: Val) -> Result<Val, ErrorKind> {
+  use Val::*;
+  match (left, right) {
+    (err @ Error{..}, _) | (_, err @ Error{..}) => Ok(err),
+    (l, r) if op == '×' || op == '*' && is_vectors(&l, &r) => {
+      if let Some(err) = linalg::first_error(&[l.clone(), r.clone()]) {
+        return Ok(err.clone());
+      }
+      match op {
+        '*' => linalg::dot(&l, &r),
+        _ => linalg::cross(&l, &r),
       }
+    },
+    (List(l), List(r)) if l.len() == r.len() =>
+      l.into_iter().zip(r).map(|(l, r)| arith(op, l, r)).collect::<Result<_, _>>().map(List),
+    (List(_), List(_)) => Err(ErrorKind::Shape),
+    (l @ Array{..}, r) | (l, r @ Array{..}) => broadcast(op, l, r),
+    (List(l), r) if is_number(&r) =>
+      l.into_iter().map(|v| arith(op, v, r.clone())).collect::<Result<_, _>>().map(List),
+    (l, List(r)) if is_number(&l) =>
+      r.into_iter().map(|v| arith(op, l.clone(), v)).collect::<Result<_, _>>().map(List),
+    (l, r) if is_comparison(op) => compare(op, &l, &r).map(Bool),
+    (l, r) if is_number(&l) && is_number(&r) => promote(op, l, r),
+    _ => Err(ErrorKind::Type),
+  }
+}
 
-      _ => Val::default(),
+/// Arithmetic on arrays, as in NumPy. Axes are matched from the last, and an
+/// axis of length 1, or a missing one, is stretched to the length of the
+/// other. Lists are arrays with one axis and numbers have none.
+fn broadcast(op: char, left: Val, right: Val) -> Result<Val, ErrorKind> {
+  let (l, laxes) = shape(left)?;
+  let (r, raxes) = shape(right)?;
+  let rank = laxes.len().max(raxes.len());
+  let pad = |axes: &[u32]| std::iter::repeat(1).take(rank - axes.len()).chain(axes.iter().copied()).collect::<Vec<_>>();
+  let (laxes, raxes) = (pad(&laxes), pad(&raxes));
+  let axes = laxes.iter().zip(&raxes).map(|(&l, &r)| match (l, r) {
+    (l, r) if l == r || r == 1 => Ok(l),
+    (1, r) => Ok(r),
+    _ => Err(ErrorKind::Shape),
+  }).collect::<Result<Vec<u32>, _>>()?;
+  let (lstrides, rstrides) = (strides(&laxes), strides(&raxes));
+  let len = axes.iter().product::<u32>() as usize;
+  let mut elems = Vec::with_capacity(len);
+  for i in 0..len {
+    // position of element `i` in each operand, skipping stretched axes
+    let (mut li, mut ri, mut rest) = (0, 0, i);
+    for (k, &n) in axes.iter().enumerate().rev() {
+      let pos = rest % n as usize;
+      rest /= n as usize;
+      li += lstrides[k] * pos;
+      ri += rstrides[k] * pos;
+    }
+    elems.push(arith(op, l[li].clone(), r[ri].clone())?);
+  }
+  Ok(Val::Array{elems, axes})
+}
+
+/// Whether `*` is a dot product: of two arrays, or an array and a list.
+fn is_vectors(l: &Val, r: &Val) -> bool {
+  use Val::*;
+  matches!((l, r), (Array{..}, Array{..} | List(_)) | (List(_), Array{..}))
+}
+
+/// Elements and axes of an array operand.
+fn shape(val: Val) -> Result<(Vec<Val>, Vec<u32>), ErrorKind> {
+  match val {
+    Val::Array{elems, axes} if elems.len() == axes.iter().product::<u32>() as usize => Ok((elems, axes)),
+    Val::Array{..} => Err(ErrorKind::Shape),
+    Val::List(elems) => {
+      let len = elems.len() as u32;
+      Ok((elems, vec![len]))
+    },
+    val if is_number(&val) => Ok((vec![val], vec![])),
+    _ => Err(ErrorKind::Type),
+  }
+}
+
+/// Row-major strides of `axes`, 0 for axes of length 1 so they're stretched.
+fn strides(axes: &[u32]) -> Vec<usize> {
+  let mut stride = 1;
+  let mut strides = vec![0; axes.len()];
+  for (k, &n) in axes.iter().enumerate().rev() {
+    if n != 1 {
+      strides[k] = stride;
     }
+    stride *= n as usize;
   }
+  strides
 }
 
+/// Arithmetic on two numbers, in the wider of their types. `Complex` is
+/// widest, then `Num` so results stay exact, then `Float` then `Int`.
+/// Booleans are `Int`s.
+///
+/// `Int`s that overflow, or divide with a remainder, give a `Num`. `Float`s
+/// that overflow give a `Num` error.
+fn promote(op: char, l: Val, r: Val) -> Result<Val, ErrorKind> {
+  use Val::*;
+  match (&l, &r) {
+    _ if op == '^' => power(&l, &r),
+    (Complex(_), _) | (_, Complex(_)) => complex::Complex::arith(op, as_complex(&l), as_complex(&r))?.into_val(),
+    // infinities have no decimal value, so are computed as floats
+    _ if is_infinite(&l) || is_infinite(&r) => floats(op, &l, &r),
+    (Num(_), _) | (_, Num(_)) => decimal(op, number(&l)?, number(&r)?).map(Num),
+    (Float(_), _) | (_, Float(_)) => floats(op, &l, &r),
+    _ => {
+      let (li, ri) = (i64::from(l.clone()), i64::from(r.clone()));
+      let res = match op {
+        '+' => li.checked_add(ri),
+        '-' => li.checked_sub(ri),
+        '*' => li.checked_mul(ri),
+        '/' if ri == 0 => return Err(ErrorKind::Div0),
+        '/' if li.checked_rem(ri) == Some(0) => li.checked_div(ri),
+        '/' => None,
+        _ => return Err(ErrorKind::Type),
+      };
+      match res {
+        Some(res) => Ok(Int(res)),
+        None => decimal(op, number(&l)?, number(&r)?).map(Num),
+      }
+    },
+  }
+}
+
+fn decimal(op: char, l: Decimal, r: Decimal) -> Result<Decimal, ErrorKind> {
+  match op {
+    '+' => l.checked_add(r),
+    '-' => l.checked_sub(r),
+    '*' => l.checked_mul(r),
+    '/' if r.is_zero() => return Err(ErrorKind::Div0),
+    '/' => l.checked_div(r),
+    _ => return Err(ErrorKind::Type),
+  }.ok_or(ErrorKind::Num)
+}
+
+fn floats(op: char, l: &Val, r: &Val) -> Result<Val, ErrorKind> {
+  let (lf, rf) = (float(l), float(r));
+  let res = match op {
+    '+' => lf + rf,
+    '-' => lf - rf,
+    '*' => lf * rf,
+    '/' if rf == 0.0 => return Err(ErrorKind::Div0),
+    '/' => lf / rf,
+    _ => return Err(ErrorKind::Type),
+  };
+  float_result(res, l, r)
+}
+
+/// A `Float` result of an operation on `l` and `r`. Undefined results,
+/// `∞ - ∞` or `0 * ∞`, are a `Num` error, as are infinite ones unless an
+/// operand was infinite.
+fn float_result(res: f64, l: &Val, r: &Val) -> Result<Val, ErrorKind> {
+  if res.is_nan() || res.is_infinite() && !is_infinite(l) && !is_infinite(r) {
+    return Err(ErrorKind::Num);
+  }
+  Ok(Val::Float(res))
+}
+
+fn is_infinite(val: &Val) -> bool {
+  matches!(val, Val::Float(f) if f.is_infinite())
+}
+
+fn is_comparison(op: char) -> bool {
+  matches!(op, '<' | '>' | '≤' | '≥' | '=' | '≠')
+}
+
+/// Compares two values. Numbers of any type compare by value, infinities
+/// included, and strings alphabetically. Other values, and complex numbers,
+/// can only be tested for equality.
+fn compare(op: char, l: &Val, r: &Val) -> Result<bool, ErrorKind> {
+  let equality = |equal: bool| match op {
+    '=' => Ok(equal),
+    '≠' => Ok(!equal),
+    _ => Err(ErrorKind::Type),
+  };
+  let ord = match (l, r) {
+    (Val::Str(l), Val::Str(r)) => l.cmp(r),
+    (Val::Complex(_), _) | (_, Val::Complex(_)) if is_number(l) && is_number(r) => return equality(as_complex(l) == as_complex(r)),
+    _ if is_number(l) && is_number(r) => match (number(l), number(r)) {
+      (Ok(l), Ok(r)) => l.cmp(&r),
+      _ => float(l).partial_cmp(&float(r)).ok_or(ErrorKind::Num)?,
+    },
+    _ => return equality(l == r),
+  };
+  Ok(match op {
+    '<' => ord.is_lt(),
+    '>' => ord.is_gt(),
+    '≤' => ord.is_le(),
+    '≥' => ord.is_ge(),
+    '=' => ord.is_eq(),
+    _ => ord.is_ne(),
+  })
+}
+
+/// `base ^ exp`. Whole powers are in the wider type of the operands, as for
+/// other arithmetic, and powers of complex numbers are computed by
+/// multiplication. Other powers are `Float`s, or complex for roots of
+/// negative numbers, `(-1) ^ 0.5`.
+fn power(base: &Val, exp: &Val) -> Result<Val, ErrorKind> {
+  use Val::*;
+  let n = match exp {
+    Complex(_) => None,
+    exp => number(exp).ok().filter(|n| n.fract().is_zero()).and_then(|n| n.to_i64()),
+  };
+  if is_infinite(base) || is_infinite(exp) {
+    return float_result(float(base).powf(float(exp)), base, exp);
+  }
+  let Some(n) = n else {
+    return match (base, exp) {
+      (Complex(_), _) | (_, Complex(_)) => as_complex(base).pow(as_complex(exp))?.into_val(),
+      _ if float(base) >= 0.0 => float_result(float(base).powf(float(exp)), base, exp),
+      _ => as_complex(base).pow(as_complex(exp))?.into_val(),
+    };
+  };
+  match (base, exp) {
+    _ if n < 0 && number(base).is_ok_and(|b| b.is_zero()) => Err(ErrorKind::Div0),
+    (Complex(z), _) => z.powi(n)?.into_val(),
+    (Num(_), _) | (_, Num(_)) => decimal_pow(number(base)?, n).map(Num),
+    (Float(_), _) | (_, Float(_)) => float_result(float(base).powf(n as f64), base, exp),
+    _ => {
+      let b = i64::from(base.clone());
+      match u32::try_from(n).ok().and_then(|n| b.checked_pow(n)) {
+        Some(res) => Ok(Int(res)),
+        None => decimal_pow(number(base)?, n).map(Num),
+      }
+    },
+  }
+}
+
+/// `base ^ n` by repeated squaring.
+fn decimal_pow(base: Decimal, n: i64) -> Result<Decimal, ErrorKind> {
+  let (mut res, mut base, mut k) = (Decimal::ONE, base, n.unsigned_abs());
+  while k > 0 {
+    if k & 1 == 1 {
+      res = res.checked_mul(base).ok_or(ErrorKind::Num)?;
+    }
+    k >>= 1;
+    if k > 0 {
+      base = base.checked_mul(base).ok_or(ErrorKind::Num)?;
+    }
+  }
+  match n < 0 {
+    true => Decimal::ONE.checked_div(res).ok_or(ErrorKind::Num),
+    false => Ok(res),
+  }
+}
+
+/// A number as a complex number.
+fn as_complex(val: &Val) -> Complex {
+  match val {
+    Val::Complex(z) => *z,
+    val => Complex::new(float(val), 0.0),
+  }
+}
+
+/// Float value of a number or boolean.
+fn float(val: &Val) -> f64 {
+  match val {
+    Val::Float(f) => *f,
+    Val::Num(d) => d.to_f64().unwrap_or_default(),
+    Val::Int(i) => *i as f64,
+    Val::Bool(b) => *b as u8 as f64,
+    _ => 0.0,
+  }
+}
+
+/// Numbers and booleans, the operands of arithmetic.
+pub fn is_number(val: &Val) -> bool {
+  matches!(val, Val::Num(_) | Val::Int(_) | Val::Float(_) | Val::Complex(_) | Val::Bool(_))
+}
+
+/// The value of a named constant, `i`, where no variable of the name is in
+/// scope.
+fn constant(name: &str) -> Option<Val> {
+  match name {
+    "i" => Some(Val::Complex(complex::I)),
+    _ => None,
+  }
+}
+
+/// Decimal value of a number or boolean. Floats without one, `NaN`, infinite
+/// or past `Decimal::MAX`, give a `Num` error.
+pub fn number(val: &Val) -> Result<Decimal, ErrorKind> {
+  match val {
+    Val::Float(f) => Decimal::from_f64(*f).ok_or(ErrorKind::Num),
+    Val::Num(_) | Val::Int(_) | Val::Bool(_) => Ok(Decimal::from(val)),
+    _ => Err(ErrorKind::Type),
+  }
+}
+
+/// Reads the cells covered by a reference, `[0, 1:3]`, `{B, 3}` or
+/// `&Units{[:], Rent}`.
+fn reference(ctx: &mut impl EvalContext, env: &Env, node: &Node) -> Val {
+  let (tile, cell) = match node {
+    Node::TileRef{tile, cell} => (Some(tile_name(ctx, tile)), *ctx.get_node(cell)),
+    node => (None, *node),
+  };
+  let (first, second, labels) = match cell {
+    Node::Index{row, col} => (row, col, false),
+    Node::Addr{row, col} => (row, col, true),
+    _ => return Val::error(ErrorKind::Type, node.print(&*ctx)),
+  };
+  let axes = match (axis(ctx, env, node, &first, labels), axis(ctx, env, node, &second, labels)) {
+    (Ok(first), Ok(second)) => [first, second],
+    (Err(err), _) | (_, Err(err)) => return err,
+  };
+  ctx.get_range(tile.as_ref(), axes).unwrap_or_else(|kind| Val::error(kind, node.print(&*ctx)))
+}
+
+/// The tile named by a reference, `&Units`, `&'Food Prices'` or `&2`.
+fn tile_name(ctx: &impl ObjectContext, tile: &NodeId) -> Val {
+  match ctx.get_node(tile) {
+    Node::UniOp{op: '$', rhs} => tile_name(ctx, rhs),
+    Node::Sym{value} | Node::Leaf{value} => ctx.get_value(value).clone(),
+    _ => Val::default(),
+  }
+}
+
+/// One axis of `reference`. In addresses names and numbers are labels, while
+/// positions are written as an index, `{[1:3], Price}`.
+fn axis(ctx: &mut impl EvalContext, env: &Env, reference: &Node, id: &NodeId, labels: bool) -> Result<Axis, Val> {
+  match *ctx.get_node(id) {
+    Node::Zero{} => Ok(Axis::Pos(0)),
+    Node::UniOp{op: '$', rhs} => axis(ctx, env, reference, &rhs, labels),
+    Node::Index{row, col: NodeId(0)} if labels => axis(ctx, env, reference, &row, false),
+    // names are labels even where they're also constants, `{i, B}`
+    Node::Sym{value} if labels && env.get(&ctx.get_value(&value).to_string()).is_none() =>
+      Ok(Axis::Label(ctx.get_value(&value).to_string())),
+    Node::Slice{start, end} => {
+      let start = bound(ctx, env, reference, &start)?;
+      let end = bound(ctx, env, reference, &end)?;
+      Ok(Axis::Slice(start, end))
+    },
+    node => match node.eval_env(ctx, env) {
+      err @ Val::Error{..} => Err(err),
+      Val::Str(lbl) if labels => Ok(Axis::Label(lbl)),
+      lbl @ (Val::Num(_) | Val::Int(_)) if labels => Ok(Axis::Label(lbl.to_string())),
+      val => position(ctx, reference, &val).map(Axis::Pos),
+    },
+  }
+}
+
+fn bound(ctx: &mut impl EvalContext, env: &Env, reference: &Node, id: &NodeId) -> Result<Option<usize>, Val> {
+  let node = *ctx.get_node(id);
+  if let Node::Zero{} = node {
+    return Ok(None);
+  }
+  match node.eval_env(ctx, env) {
+    err @ Val::Error{..} => Err(err),
+    val => position(ctx, reference, &val).map(Some),
+  }
+}
+
+/// A position in `reference`, which must be a whole number.
+fn position(ctx: &impl ObjectContext, reference: &Node, val: &Val) -> Result<usize, Val> {
+  match number(val) {
+    Ok(pos) => pos.to_usize().ok_or_else(|| Val::error(ErrorKind::Ref, reference.print(ctx))),
+    Err(kind) => Err(Val::error(kind, reference.print(ctx))),
+  }
+}
+
+/// Evaluates call arguments, parsed as a list node. Fields are passed as a
+/// single record argument, `f(a: 1, b: 2)`.
+///
+/// Each argument is evaluated directly rather than through the list node, so
+/// nested calls take one `eval_env` frame per level.
+fn eval_args(ctx: &mut impl EvalContext, env: &Env, args: &Node) -> Vec<Val> {
+  let elems = match args {
+    Node::List{elems} => ctx.get_elems(elems).to_vec(),
+    arg => return vec![arg.eval_env(ctx, env)],
+  };
+  let fields = !elems.is_empty() && elems.iter().all(|nid|matches!(ctx.get_node(nid), Node::Field{..}));
+  let mut vals = Vec::with_capacity(elems.len());
+  for nid in &elems {
+    let node = *ctx.get_node(nid);
+    vals.push(node.eval_env(ctx, env));
+  }
+  match fields {
+    true => vec![record(vals)],
+    false => vals,
+  }
+}
+
+/// Joins single field records into one record.
+///
+/// Kept out of `eval_env`, whose stack frame bounds how deeply formulas nest.
+fn record(fields: Vec<Val>) -> Val {
+  Val::Record{
+    fields: fields.len() as u32,
+    value: fields.into_iter().flat_map(|field| match field {
+      Val::Record{value, ..} => value,
+      _ => vec![],
+    }).collect(),
+  }
+}
+
+/// A single field record. Keys are names, not variables.
+fn field(ctx: &impl ObjectContext, key: &NodeId, value: Val) -> Val {
+  let key = match ctx.get_node(key) {
+    Node::Sym{value} | Node::Leaf{value} => ctx.get_value(value).to_string(),
+    _ => String::new(),
+  };
+  Val::Record{ value: vec![Val::Str(key), value], fields: 1 }
+}
+
+/// Calls `func`, a closure or the name of a function. Names bound in scope
+/// have already been resolved to their value, so board functions come next,
+/// then builtins. Errors in the function or its arguments are passed on.
+fn call(ctx: &mut impl EvalContext, func: Val, args: Vec<Val>) -> Val {
+  if let Some(err) = std::iter::once(&func).chain(&args).find(|val| val.is_error()) {
+    return err.clone();
+  }
+  let func = match func {
+    Val::Str(name) => ctx.get_func(&name).map(Val::Lambda).unwrap_or(Val::Str(name)),
+    func => func,
+  };
+  if let Val::Lambda(closure) = func {
+    return closure.call(ctx, args);
+  }
+  match builtins::lookup(&func.to_string()).map(|b| b.func) {
+    Some(Func::Plain(f)) => f(args),
+    Some(Func::Higher(f)) => f(args, &mut |func, args| apply(ctx, func, args)),
+    Some(Func::Rounding(f)) => f(args, ctx.rounding()),
+    None => match func {
+      Val::Str(name) => Val::error(ErrorKind::Ref, name),
+      func => Val::error(ErrorKind::Type, func.to_string()),
+    },
+  }
+}
 
 #[cfg(test)]
 mod tests {
@@ -293,4 +991,309 @@ mod tests {
     let res = ast.get(ast.len()-1).unwrap().eval(&mut state);
     assert_eq!(Val::Bool(true), res);
   }
+
+  #[test]
+  fn test_eval_checked() {
+    use crate::parser::Parser;
+
+    let eval = |formula: &str| {
+      let mut p = Parser::new(formula);
+      p.parse().unwrap().eval(&mut p)
+    };
+    let num = |origin: &str| Val::error(ErrorKind::Num, origin);
+    let (max, min) = (Decimal::MAX, Decimal::MIN);
+
+    assert_eq!(eval(&format!("{max} + 1")), num(&format!("{max} + 1")));
+    assert_eq!(eval(&format!("{min} - 1")), num(&format!("{min} - 1")));
+    assert_eq!(eval(&format!("{max} * 2")), num(&format!("{max} * 2")));
+    assert_eq!(eval(&format!("{max} / (1 / 2)")), num(&format!("{max} / (1 / 2)")));
+    assert_eq!(eval("1 / 0"), Val::error(ErrorKind::Div0, "1 / 0"));
+    assert_eq!(eval("(1, 2) / 0"), Val::error(ErrorKind::Div0, "(1, 2) / 0"));
+    assert_eq!(eval(&format!("{max} - 1 + 1")), Val::Num(max));
+    // unbounded recursion stops at the call depth limit rather than overflowing the stack
+    assert_eq!(eval("let y = g -> g(g) in y(y)"), num("g -> g(g)"));
+    assert_eq!(eval("let y = g -> 1 + g(g) in y(y)"), num("g -> 1 + g(g)"));
+
+    // floats without a decimal value
+    let (mut board, tile) = Board::<Cell>::example();
+    let mut state = EvalState::new(&mut board, tile, CellId(0));
+    let ast = vec![
+      Node::Leaf{value: state.push_value(Val::Num(dec!(1)))},
+      Node::Leaf{value: state.push_value(Val::Float(f64::NAN))},
+      Node::Leaf{value: state.push_value(Val::Float(f64::INFINITY))},
+      Node::Leaf{value: state.push_value(Val::Float(1e300))},
+      Node::BinOp{op: '+', lhs: NodeId(0), rhs: NodeId(1)},
+      Node::BinOp{op: '*', lhs: NodeId(2), rhs: NodeId(0)},
+      Node::BinOp{op: '-', lhs: NodeId(0), rhs: NodeId(3)},
+      Node::Index{row: NodeId(0), col: NodeId(1)},
+    ];
+    state.load(&ast);
+
+    assert_eq!(ast[4].eval(&mut state), num("1 + NaN"));
+    // an infinite operand can give an infinite result
+    assert_eq!(ast[5].eval(&mut state), Val::Float(f64::INFINITY));
+    assert_eq!(ast[6].eval(&mut state), num(&format!("1 - {}", 1e300)));
+    assert_eq!(ast[7].eval(&mut state), num("[1, NaN]"));
+  }
+
+  #[test]
+  fn test_eval_promotion() {
+    use Val::*;
+
+    fn typ(val: &Val) -> &'static str {
+      match val {
+        Num(_) => "Num",
+        Int(_) => "Int",
+        Float(_) => "Float",
+        Bool(_) => "Bool",
+        _ => "other",
+      }
+    }
+
+    let lhs = [Num(dec!(6)), Int(6), Float(6.0), Bool(false)];
+    let rhs = [Num(dec!(2)), Int(2), Float(2.0), Bool(true)];
+    // type of `row op col` for every operator
+    let table = [
+      //          Num    Int      Float    Bool
+      /* Num   */ ["Num", "Num",   "Num",   "Num"],
+      /* Int   */ ["Num", "Int",   "Float", "Int"],
+      /* Float */ ["Num", "Float", "Float", "Float"],
+      /* Bool  */ ["Num", "Int",   "Float", "Int"],
+    ];
+    for (i, l) in lhs.iter().enumerate() {
+      for (j, r) in rhs.iter().enumerate() {
+        for op in ['+', '-', '*', '/'] {
+          let res = arith(op, l.clone(), r.clone()).unwrap();
+          let expected = decimal(op, Decimal::from(l), Decimal::from(r)).unwrap();
+          assert_eq!(typ(&res), table[i][j], "{l:?} {op} {r:?}");
+          assert_eq!(Decimal::from(&res), expected, "{l:?} {op} {r:?}");
+        }
+      }
+    }
+
+    // Ints overflowing or dividing with a remainder become Nums
+    assert_eq!(arith('+', Int(i64::MAX), Int(1)), Ok(Num(Decimal::from(i64::MAX) + dec!(1))));
+    assert_eq!(arith('-', Int(i64::MIN), Bool(true)), Ok(Num(Decimal::from(i64::MIN) - dec!(1))));
+    assert_eq!(arith('*', Int(i64::MIN), Int(2)), Ok(Num(Decimal::from(i64::MIN) * dec!(2))));
+    assert_eq!(arith('/', Int(i64::MIN), Int(-1)), Ok(Num(-Decimal::from(i64::MIN))));
+    assert_eq!(arith('/', Int(7), Int(2)), Ok(Num(dec!(3.5))));
+    assert_eq!(arith('/', Int(-8), Int(2)), Ok(Int(-4)));
+    assert_eq!(arith('/', Int(1), Int(0)), Err(ErrorKind::Div0));
+    assert_eq!(arith('/', Bool(true), Bool(false)), Err(ErrorKind::Div0));
+
+    assert_eq!(arith('/', Float(1.0), Float(4.0)), Ok(Float(0.25)));
+    assert_eq!(arith('/', Float(1.0), Int(0)), Err(ErrorKind::Div0));
+    assert_eq!(arith('*', Float(1e308), Float(10.0)), Err(ErrorKind::Num));
+
+    // lists, element by element
+    let list = |vals: &[Val]| List(vals.to_vec());
+    assert_eq!(arith('+', list(&[Int(1), Float(2.0)]), list(&[Int(3), Num(dec!(4))])), Ok(list(&[Int(4), Num(dec!(6))])));
+    assert_eq!(arith('*', list(&[Int(1), Int(2)]), Float(0.5)), Ok(list(&[Float(0.5), Float(1.0)])));
+    assert_eq!(arith('-', Int(1), list(&[Int(1), list(&[Bool(true)])])), Ok(list(&[Int(0), list(&[Int(0)])])));
+    assert_eq!(arith('+', list(&[Int(1)]), list(&[Int(1), Int(2)])), Err(ErrorKind::Shape));
+  }
+
+  #[test]
+  fn test_eval_broadcast() {
+    use Val::*;
+
+    let ints = |vals: &[i64]| vals.iter().map(|v| Int(*v)).collect::<Vec<_>>();
+    let array = |axes: &[u32], vals: &[i64]| Array{ elems: ints(vals), axes: axes.to_vec() };
+
+    // same shape, element by element
+    assert_eq!(arith('+', array(&[2, 2], &[1, 2, 3, 4]), array(&[2, 2], &[10, 20, 30, 40])), Ok(array(&[2, 2], &[11, 22, 33, 44])));
+    // numbers and lists are stretched over the leading axes
+    assert_eq!(arith('*', array(&[2, 3], &[1, 2, 3, 4, 5, 6]), Int(2)), Ok(array(&[2, 3], &[2, 4, 6, 8, 10, 12])));
+    assert_eq!(arith('-', Int(10), array(&[3], &[1, 2, 3])), Ok(array(&[3], &[9, 8, 7])));
+    assert_eq!(arith('-', array(&[2, 3], &[1, 2, 3, 4, 5, 6]), List(ints(&[1, 0, -1]))), Ok(array(&[2, 3], &[0, 2, 4, 3, 5, 7])));
+    // and axes of length 1 against the other operand
+    assert_eq!(arith('+', array(&[2, 1], &[10, 20]), array(&[1, 3], &[1, 2, 3])), Ok(array(&[2, 3], &[11, 12, 13, 21, 22, 23])));
+    assert_eq!(arith('+', array(&[2, 1, 2], &[1, 2, 3, 4]), array(&[2, 1], &[10, 20])), Ok(array(&[2, 2, 2], &[11, 12, 21, 22, 13, 14, 23, 24])));
+
+    // shapes that don't line up
+    assert_eq!(arith('+', array(&[2, 3], &[1, 2, 3, 4, 5, 6]), List(ints(&[1, 2]))), Err(ErrorKind::Shape));
+    assert_eq!(arith('+', array(&[2, 3], &[1, 2, 3, 4, 5, 6]), array(&[3, 2], &[1, 2, 3, 4, 5, 6])), Err(ErrorKind::Shape));
+    assert_eq!(arith('+', array(&[2, 2], &[1, 2, 3]), Int(1)), Err(ErrorKind::Shape));
+    assert_eq!(arith('+', array(&[2], &[1, 2]), Str("a".to_owned())), Err(ErrorKind::Type));
+
+    // element errors are kept, operator errors fail the whole array
+    let err = Val::error(ErrorKind::Div0, "1 / 0");
+    assert_eq!(arith('+', Array{ elems: vec![Int(1), err.clone()], axes: vec![2] }, Int(1)), Ok(Array{ elems: vec![Int(2), err], axes: vec![2] }));
+    assert_eq!(arith('/', array(&[2], &[1, 2]), array(&[2], &[1, 0])), Err(ErrorKind::Div0));
+  }
+
+  #[test]
+  fn test_eval_complex() {
+    use Val::*;
+    use crate::parser::Parser;
+    use std::f64::consts::FRAC_PI_2;
+
+    let eval = |formula: &str| {
+      let mut p = Parser::new(formula);
+      p.parse().unwrap().eval(&mut p)
+    };
+    assert_eq!(eval("i ^ 2"), Float(-1.0));
+    assert_eq!(eval("i * i"), Float(-1.0));
+    assert_eq!(eval("(-1) ^ (1 / 2)"), Complex(complex::I));
+    assert_eq!(eval("3 + 4 * i"), Complex(complex::Complex::new(3.0, 4.0)));
+    assert_eq!(eval("(1 + i) / (1 - i)"), Complex(complex::I));
+    // the identities in the docs
+    assert_eq!(eval("(-1)^(1/2) == i"), Bool(true));
+    assert_eq!(eval("i^2 == -1"), Bool(true));
+    assert_eq!(eval("(1, 2) * i"), List(vec![Complex(complex::I), Complex(complex::Complex::new(0.0, 2.0))]));
+    assert_eq!(eval("magnitude(3 + 4 * i)"), Float(5.0));
+    assert_eq!(eval("abs(-3 + 4 * i)"), Float(5.0));
+    assert_eq!(eval("phase(i)"), Float(FRAC_PI_2));
+    assert_eq!(eval("phase(-2)"), Float(std::f64::consts::PI));
+    // variables shadow the constant
+    assert_eq!(eval("let i = 3 in i ^ 2"), Num(dec!(9)));
+    assert_eq!(eval("i / 0"), Val::error(ErrorKind::Div0, "i / 0"));
+    assert_eq!(eval("i + 'a'"), Val::error(ErrorKind::Type, "i + 'a'"));
+    assert_eq!(eval("sum(1, i)"), Val::error(ErrorKind::Type, "i"));
+
+    // powers are exact where they can be
+    assert_eq!(eval("2 ^ 10"), Num(dec!(1024)));
+    assert_eq!(eval("2 ^ -2"), Num(dec!(0.25)));
+    assert_eq!(eval("2 ^ 3 ^ 2"), Num(dec!(512)));
+    assert_eq!(eval("4 ^ (1 / 2)"), Float(2.0));
+    assert_eq!(eval("0 ^ -1"), Val::error(ErrorKind::Div0, "0 ^ -1"));
+    assert_eq!(arith('^', Int(2), Int(62)), Ok(Int(1 << 62)));
+    assert_eq!(arith('^', Int(2), Int(64)), Ok(Num(dec!(18446744073709551616))));
+    assert_eq!(arith('^', Float(2.0), Int(3)), Ok(Float(8.0)));
+    assert_eq!(arith('^', Float(10.0), Int(400)), Err(ErrorKind::Num));
+  }
+
+  #[test]
+  fn test_eval_comparisons() {
+    use Val::*;
+    use crate::parser::Parser;
+
+    let eval = |formula: &str| {
+      let mut p = Parser::new(formula);
+      p.parse().unwrap().eval(&mut p)
+    };
+    assert_eq!(eval("1 < 2"), Bool(true));
+    assert_eq!(eval("2 <= 2 * 1"), Bool(true));
+    assert_eq!(eval("1 / 3 == 2 / 6"), Bool(true));
+    assert_eq!(eval("1 != 1.0"), Bool(false));
+    assert_eq!(eval("'apple' < 'banana'"), Bool(true));
+    assert_eq!(eval("'a' == 'a'"), Bool(true));
+    assert_eq!(eval("i == i"), Bool(true));
+    assert_eq!(eval("i < 1"), Val::error(ErrorKind::Type, "i < 1"));
+    assert_eq!(eval("'a' < 1"), Val::error(ErrorKind::Type, "'a' < 1"));
+    assert_eq!(eval("'a' != 1"), Bool(true));
+    // comparisons broadcast like arithmetic
+    assert_eq!(eval("50000 < (10000, 60000, ∞)"), List(vec![Bool(false), Bool(true), Bool(true)]));
+    assert_eq!(eval("(1, 2) >= (2, 2)"), List(vec![Bool(false), Bool(true)]));
+  }
+
+  #[test]
+  fn test_eval_associativity() {
+    use Val::*;
+    use crate::parser::Parser;
+
+    let eval = |formula: &str| {
+      let mut p = Parser::new(formula);
+      p.parse().unwrap().eval(&mut p)
+    };
+    // operators of the same precedence group from the left, except `^`
+    assert_eq!(eval("1 - 2 - 3"), Num(dec!(-4)));
+    assert_eq!(eval("8 / 4 / 2"), Num(dec!(1)));
+    assert_eq!(eval("1 - 2 + 3"), Num(dec!(2)));
+    assert_eq!(eval("2 ^ 3 ^ 2"), Num(dec!(512)));
+  }
+
+  #[test]
+  fn test_eval_lazy() {
+    use Val::*;
+    use crate::parser::Parser;
+
+    let eval = |formula: &str| {
+      let mut p = Parser::new(formula);
+      p.parse().unwrap().eval(&mut p)
+    };
+    // branches that aren't taken aren't evaluated
+    assert_eq!(eval("false and 1 / 0"), Bool(false));
+    assert_eq!(eval("1 < 2 or 1 / 0"), Bool(true));
+    assert_eq!(eval("if 1 > 0 'yes' else 1 / 0"), Str("yes".to_owned()));
+    assert_eq!(eval("match 2 as (1 / 0) 'a' as 2 'b'"), Val::error(ErrorKind::Div0, "1 / 0"));
+    assert_eq!(eval("match 2 as 2 'b' as (1 / 0) 'c' else 1 / 0"), Str("b".to_owned()));
+    assert_eq!(eval("true and 1 / 0"), Val::error(ErrorKind::Div0, "1 / 0"));
+
+    assert_eq!(eval("true and 0"), Bool(false));
+    assert_eq!(eval("not 0"), Bool(true));
+    assert_eq!(eval("not 1 == 2"), Bool(true));
+    assert_eq!(eval("true xor 1"), Bool(false));
+    assert_eq!(eval("'a' and true"), Val::error(ErrorKind::Type, "'a' and true"));
+    assert_eq!(eval("if (1, 2) 1 else 2"), Val::error(ErrorKind::Type, "1, 2"));
+    assert_eq!(eval("if 1 / 0 1 else 2"), Val::error(ErrorKind::Div0, "1 / 0"));
+    assert_eq!(eval("if 0 1 else if 1 2 else 3"), Num(dec!(2)));
+
+    assert_eq!(eval("match 'b' as 'a' 1 as 'b' 2"), Num(dec!(2)));
+    assert_eq!(eval("match 1 / 2 as (2 / 4) 'half' else 'other'"), Str("half".to_owned()));
+    assert_eq!(eval("match 3 as 1 'one' else 'many'"), Str("many".to_owned()));
+    assert_eq!(eval("match 3 as 1 'one'"), Val::error(ErrorKind::NoMatch, "3"));
+    assert_eq!(eval("let f = x -> match x > 0 as true x as false 0 - x in f(-3)"), Num(dec!(3)));
+  }
+
+  #[test]
+  fn test_eval_infinity() {
+    use Val::*;
+    use crate::parser::Parser;
+
+    let eval = |formula: &str| {
+      let mut p = Parser::new(formula);
+      p.parse().unwrap().eval(&mut p)
+    };
+    assert_eq!(eval("∞"), Float(f64::INFINITY));
+    assert_eq!(eval("-infinity"), Float(f64::NEG_INFINITY));
+    assert_eq!(eval("1 < ∞"), Bool(true));
+    assert_eq!(eval("-∞ < -100000"), Bool(true));
+    assert_eq!(eval("∞ == infinity"), Bool(true));
+    assert_eq!(eval("∞ + 1"), Float(f64::INFINITY));
+    assert_eq!(eval("2 * -∞"), Float(f64::NEG_INFINITY));
+    assert_eq!(eval("1 / ∞"), Float(0.0));
+    assert_eq!(eval("2 ^ ∞"), Float(f64::INFINITY));
+    // undefined results are errors
+    assert_eq!(eval("∞ - ∞"), Val::error(ErrorKind::Num, "∞ - ∞"));
+    assert_eq!(eval("0 * ∞"), Val::error(ErrorKind::Num, "0 * ∞"));
+    assert_eq!(eval("∞ / 0"), Val::error(ErrorKind::Div0, "∞ / 0"));
+    assert_eq!(eval("sum(1, ∞)"), Val::error(ErrorKind::Num, "∞"));
+    // finite operands still can't overflow to infinity
+    assert_eq!(arith('*', Float(f64::MAX), Int(2)), Err(ErrorKind::Num));
+  }
+
+  #[test]
+  fn test_eval_products() {
+    use Val::*;
+    use crate::parser::Parser;
+
+    let nums = |vals: &[i64]| vals.iter().map(|v| Num(Decimal::from(*v))).collect::<Vec<_>>();
+    let array = |axes: &[u32], vals: &[i64]| Array{ elems: nums(vals), axes: axes.to_vec() };
+
+    // `*` of arrays is the dot product
+    assert_eq!(arith('*', array(&[2, 2], &[1, 2, 3, 4]), array(&[2, 2], &[5, 6, 7, 8])), Ok(array(&[2, 2], &[19, 22, 43, 50])));
+    assert_eq!(arith('*', array(&[2, 3], &[1, 2, 3, 4, 5, 6]), List(nums(&[1, 0, -1]))), Ok(array(&[2], &[-2, -2])));
+    assert_eq!(arith('*', List(nums(&[1, 1])), array(&[2, 3], &[1, 2, 3, 4, 5, 6])), Ok(array(&[3], &[5, 7, 9])));
+    assert_eq!(arith('*', array(&[3], &[1, 2, 3]), array(&[3], &[4, 5, 6])), Ok(Num(dec!(32))));
+    assert_eq!(arith('*', array(&[2, 3], &[1, 2, 3, 4, 5, 6]), array(&[2, 3], &[1, 2, 3, 4, 5, 6])), Err(ErrorKind::Shape));
+    // but lists and numbers are still multiplied element by element
+    assert_eq!(arith('*', List(nums(&[1, 2])), List(nums(&[3, 4]))), Ok(List(nums(&[3, 8]))));
+    assert_eq!(arith('*', array(&[2], &[1, 2]), Int(2)), Ok(array(&[2], &[2, 4])));
+
+    // `**` is the cross product
+    assert_eq!(arith('×', List(nums(&[1, 0, 0])), List(nums(&[0, 1, 0]))), Ok(List(nums(&[0, 0, 1]))));
+    assert_eq!(arith('×', array(&[3], &[1, 2, 3]), List(nums(&[4, 5, 6]))), Ok(array(&[3], &[-3, 6, -3])));
+    assert_eq!(arith('×', List(nums(&[1, 2])), List(nums(&[3, 4]))), Ok(Num(dec!(-2))));
+    assert_eq!(arith('×', List(nums(&[1, 2])), List(nums(&[3, 4, 5]))), Err(ErrorKind::Shape));
+
+    let eval = |formula: &str| {
+      let mut p = Parser::new(formula);
+      p.parse().unwrap().eval(&mut p)
+    };
+    assert_eq!(eval("Array(1, 2, 3) * Array(4, 5, 6) + 1"), Num(dec!(33)));
+    assert_eq!(eval("(1, 2, 3) ** (4, 5, 6) * 2"), List(nums(&[-6, 12, -6])));
+    assert_eq!(eval("2 ** 3"), Val::error(ErrorKind::Type, "2 ** 3"));
+    assert_eq!(eval("Array(1, 2) * (1, 1 / 0)"), Val::error(ErrorKind::Div0, "1 / 0"));
+  }
 }
@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use crate::builtins;
+use crate::cell::Val;
+use crate::closure::Closure;
+use crate::err::Err;
+use crate::parser::{binop, Assoc, Parser, KEYWORDS, MAX_OP_PREC};
+
+/// A named function defined on a board, e.g. `def noi(rent, opex) = rent - opex`.
+#[derive(Debug, Clone)]
+pub struct FuncDef {
+  pub name: String,
+  pub source: String,
+  pub closure: Arc<Closure>,
+}
+
+impl FuncDef {
+  /// Signature shown in autocomplete, e.g. `noi(rent, opex)`.
+  pub fn sig(&self) -> String {
+    format!("{}({})", self.name, self.closure.params().join(", "))
+  }
+}
+
+/// A binary operator defined on a board, e.g. `a %of b` calling `percentof(a, b)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpDef {
+  pub symbol: String,
+  /// Binding strength on the scale of the builtin operators, see `BINOPS`.
+  pub prec: u8,
+  pub assoc: Assoc,
+  /// Name of the board function or builtin the operator calls.
+  pub func: String,
+}
+
+/// Operators defined on a board, longest symbol first so that the tokenizer
+/// prefers `+-` over `+`.
+#[derive(Debug, Default)]
+pub struct Ops {
+  defs: Vec<OpDef>,
+}
+
+impl Ops {
+  pub const fn new() -> Ops {
+    Ops{ defs: Vec::new() }
+  }
+
+  pub fn get(&self, symbol: &str) -> Option<&OpDef> {
+    self.defs.iter().find(|op|op.symbol == symbol)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &OpDef> {
+    self.defs.iter()
+  }
+
+  /// Adds `op`, returning the operator it replaces.
+  fn insert(&mut self, op: OpDef) -> Option<OpDef> {
+    let old = self.remove(&op.symbol);
+    let at = self.defs.partition_point(|o|o.symbol.len() >= op.symbol.len());
+    self.defs.insert(at, op);
+    old
+  }
+
+  fn remove(&mut self, symbol: &str) -> Option<OpDef> {
+    let at = self.defs.iter().position(|op|op.symbol == symbol)?;
+    Some(self.defs.remove(at))
+  }
+}
+
+/// Characters that delimit terms and so cannot appear in operators.
+const RESERVED: &str = "(){}[],;'\"$@&";
+
+/// Checks that `symbol` can be tokenized as an operator without shadowing
+/// builtin syntax.
+fn valid_symbol(symbol: &str) -> bool {
+  !symbol.is_empty()
+    && binop(symbol).is_none()
+    && !["=", "->"].contains(&symbol)
+    && !KEYWORDS.contains(&symbol)
+    && !symbol.starts_with(|ch: char|ch.is_ascii_digit())
+    && !symbol.chars().any(|ch|ch.is_whitespace() || RESERVED.contains(ch))
+}
+
+/// The functions and operators defined on a board, by name.
+///
+/// Definitions never form a cycle: a function may call or refer to any
+/// other function, directly or through an operator, but cannot reach itself.
+#[derive(Debug, Default)]
+pub struct Funcs {
+  defs: BTreeMap<String, FuncDef>,
+  ops: Ops,
+}
+
+impl Funcs {
+  /// Parses and adds a definition, replacing any function of the same name.
+  pub fn define(&mut self, source: &str) -> Result<&FuncDef, Err> {
+    let mut p = Parser::new(source).with_ops(&self.ops);
+    let (name, lambda) = p.parse_def().ok_or(Err::Parse{ pos: p.furthest() })?;
+    let closure = match lambda.eval(&mut p) {
+      Val::Lambda(closure) => closure,
+      _ => return Err(Err::Eval()),
+    };
+    let def = FuncDef{ name: name.clone(), source: source.to_owned(), closure };
+
+    let old = self.defs.insert(name.clone(), def);
+    if let Some(path) = self.cycle(&name) {
+      match old {
+        Some(old) => self.defs.insert(name, old),
+        None => self.defs.remove(&name),
+      };
+      return Err(Err::Cycle{ path });
+    }
+    Ok(&self.defs[&name])
+  }
+
+  /// Adds a binary operator calling `func`, replacing any operator with the
+  /// same symbol. Precedence ranges from 1 to `MAX_OP_PREC`.
+  pub fn define_op(&mut self, symbol: &str, prec: u8, assoc: Assoc, func: &str) -> Result<&OpDef, Err> {
+    if !valid_symbol(symbol) || !(1..=MAX_OP_PREC).contains(&prec) {
+      return Err(Err::Op{ symbol: symbol.to_owned() });
+    }
+    if !self.defs.contains_key(func) && builtins::lookup(func).is_none() {
+      return Err(Err::Undefined{ name: func.to_owned() });
+    }
+    let old = self.ops.insert(OpDef{ symbol: symbol.to_owned(), prec, assoc, func: func.to_owned() });
+
+    // functions already using the symbol now call `func` through it
+    if let Some(path) = self.defs.keys().find_map(|name|self.cycle(name)) {
+      self.ops.remove(symbol);
+      if let Some(old) = old {
+        self.ops.insert(old);
+      }
+      return Err(Err::Cycle{ path });
+    }
+    Ok(self.ops.get(symbol).unwrap())
+  }
+
+  pub fn remove_op(&mut self, symbol: &str) -> Option<OpDef> {
+    self.ops.remove(symbol)
+  }
+
+  pub fn ops(&self) -> &Ops {
+    &self.ops
+  }
+
+  pub fn remove(&mut self, name: &str) -> Option<FuncDef> {
+    self.defs.remove(name)
+  }
+
+  pub fn get(&self, name: &str) -> Option<&FuncDef> {
+    self.defs.get(name)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &FuncDef> {
+    self.defs.values()
+  }
+
+  /// Board functions called by `def`, by name or through an operator.
+  fn callees(&self, def: &FuncDef) -> Vec<String> {
+    let mut callees = def.closure.calls();
+    callees.extend(def.closure.ops().iter().filter_map(|symbol|self.ops.get(symbol)).map(|op|op.func.clone()));
+    callees
+  }
+
+  /// Finds a call path from `name` that loops, e.g. `[f, g, f]`.
+  fn cycle(&self, name: &str) -> Option<Vec<String>> {
+    let mut path = vec![];
+    let mut done = BTreeSet::new();
+    self.visit(name, &mut path, &mut done).then_some(path)
+  }
+
+  fn visit(&self, name: &str, path: &mut Vec<String>, done: &mut BTreeSet<String>) -> bool {
+    if let Some(at) = path.iter().position(|n|n == name) {
+      path.drain(..at);
+      path.push(name.to_owned());
+      return true;
+    }
+    let def = match self.defs.get(name) {
+      Some(def) if !done.contains(name) => def,
+      _ => return false,
+    };
+    path.push(name.to_owned());
+    for callee in self.callees(def) {
+      if self.visit(&callee, path, done) {
+        return true;
+      }
+    }
+    path.pop();
+    done.insert(name.to_owned());
+    false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_funcs_define() {
+    let mut funcs = Funcs::default();
+    let def = funcs.define("def noi(rent, vacancy, opex) = rent * (1 - vacancy) - opex").unwrap();
+    assert_eq!("noi(rent, vacancy, opex)", def.sig());
+    assert!(funcs.get("noi").is_some());
+
+    assert!(matches!(funcs.define("def bad(x = x"), Err(Err::Parse{..})));
+    assert!(matches!(funcs.define("noi(x) = x"), Err(Err::Parse{..})));
+
+    funcs.define("def noi(rent) = rent").unwrap();
+    assert_eq!("noi(rent)", funcs.get("noi").unwrap().sig());
+    assert!(funcs.remove("noi").is_some());
+    assert!(funcs.get("noi").is_none());
+  }
+
+  #[test]
+  fn test_funcs_recursion() {
+    let mut funcs = Funcs::default();
+    assert!(matches!(funcs.define("def f(x) = f(x - 1)"), Err(Err::Cycle{..})));
+    assert!(funcs.get("f").is_none());
+
+    funcs.define("def f(x) = g(x) + 1").unwrap();
+    funcs.define("def h(x) = x * 2").unwrap();
+    funcs.define("def g(x) = h(x) + 1").unwrap();
+    match funcs.define("def h(x) = f(x)") {
+      Err(Err::Cycle{path}) => assert_eq!(vec!["h", "f", "g", "h"], path),
+      res => panic!("expected cycle, got {:?}", res.map(|d|d.sig())),
+    }
+    // the rejected definition leaves the old one in place
+    assert_eq!("def h(x) = x * 2", funcs.get("h").unwrap().source);
+
+    // parameters shadow functions, so calling one is not recursion
+    funcs.define("def k(k) = k(1)").unwrap();
+
+    // nor can a function reach itself through a name it isn't called by
+    assert!(matches!(funcs.define("def r(x) = let g = r in g(x)"), Err(Err::Cycle{..})));
+    assert!(matches!(funcs.define("def r(x) = map((1, 2), r)"), Err(Err::Cycle{..})));
+    assert!(matches!(funcs.define("def r(x) = x -> r"), Err(Err::Cycle{..})));
+    // record keys and labels are not references
+    funcs.define("def r(x) = (r: x)").unwrap();
+    funcs.define("def s(x) = {x, r}").unwrap();
+  }
+
+  #[test]
+  fn test_funcs_define_op() {
+    let mut funcs = Funcs::default();
+    funcs.define("def pct(a, b) = a * b / 100").unwrap();
+    let op = funcs.define_op("%of", 15, Assoc::Left, "pct").unwrap();
+    assert_eq!("pct", op.func);
+    funcs.define_op("±", 10, Assoc::Right, "sum").unwrap();
+
+    for symbol in ["", "+", "=", "->", "in", "++", "union", "a b", "(x", "1x", "$$"] {
+      assert!(matches!(funcs.define_op(symbol, 15, Assoc::Left, "pct"), Err(Err::Op{..})), "{symbol}");
+    }
+    assert!(matches!(funcs.define_op("%", 0, Assoc::Left, "pct"), Err(Err::Op{..})));
+    assert!(matches!(funcs.define_op("%", MAX_OP_PREC + 1, Assoc::Left, "pct"), Err(Err::Op{..})));
+    assert!(matches!(funcs.define_op("%", 15, Assoc::Left, "nope"), Err(Err::Undefined{..})));
+
+    // definitions parse board operators
+    funcs.define("def half(x) = 50 %of x").unwrap();
+    match funcs.define_op("%of", 15, Assoc::Left, "half") {
+      Err(Err::Cycle{path}) => assert_eq!(vec!["half", "half"], path),
+      res => panic!("expected cycle, got {:?}", res),
+    }
+    assert_eq!("pct", funcs.ops().get("%of").unwrap().func);
+
+    // longest symbols are tried first
+    funcs.define_op("%", 15, Assoc::Left, "pct").unwrap();
+    let symbols: Vec<&str> = funcs.ops().iter().map(|op|op.symbol.as_str()).collect();
+    assert_eq!(vec!["%of", "±", "%"], symbols);
+    assert!(funcs.remove_op("%").is_some());
+  }
+}
@@ -6,5 +6,16 @@ pub mod cell;
 pub mod rpc;
 pub mod parser;
 pub mod eval;
+pub mod builtins;
+pub mod linalg;
+pub mod collection;
+pub mod complex;
+pub mod rounding;
+pub mod closure;
+pub mod ast;
+pub mod funcs;
+pub mod printer;
+pub mod complete;
+pub mod translate;
 pub use parser::Parser;
 pub mod err;
\ No newline at end of file
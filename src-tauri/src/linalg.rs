@@ -0,0 +1,251 @@
+use rust_decimal::Decimal;
+
+use crate::cell::{ErrorKind, Val};
+use crate::eval::number;
+
+/// A matrix of decimals, by rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+  rows: usize,
+  cols: usize,
+  elems: Vec<Decimal>,
+}
+
+/// An operand of the linear algebra functions. Lists and one dimensional
+/// arrays are vectors, two dimensional arrays are matrices.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+  Vector(Vec<Decimal>),
+  Matrix(Matrix),
+}
+
+impl Operand {
+  pub fn new(val: &Val) -> Result<Operand, ErrorKind> {
+    let decimals = |elems: &[Val]| elems.iter().map(number).collect::<Result<Vec<_>, _>>();
+    match val {
+      Val::List(elems) => Ok(Operand::Vector(decimals(elems)?)),
+      Val::Array{elems, axes} => match axes[..] {
+        [n] if n as usize == elems.len() => Ok(Operand::Vector(decimals(elems)?)),
+        [rows, cols] if (rows * cols) as usize == elems.len() =>
+          Ok(Operand::Matrix(Matrix{ rows: rows as usize, cols: cols as usize, elems: decimals(elems)? })),
+        _ => Err(ErrorKind::Shape),
+      },
+      _ => Err(ErrorKind::Type),
+    }
+  }
+
+  /// The operand as a matrix. Vectors are a single column.
+  pub fn matrix(self) -> Matrix {
+    match self {
+      Operand::Vector(elems) => Matrix{ rows: elems.len(), cols: 1, elems },
+      Operand::Matrix(m) => m,
+    }
+  }
+}
+
+impl Matrix {
+  pub fn identity(n: usize) -> Matrix {
+    let elems = (0..n * n).map(|i| if i % (n + 1) == 0 { Decimal::ONE } else { Decimal::ZERO }).collect();
+    Matrix{ rows: n, cols: n, elems }
+  }
+
+  fn get(&self, row: usize, col: usize) -> Decimal {
+    self.elems[row * self.cols + col]
+  }
+
+  fn row(&self, row: usize) -> &[Decimal] {
+    &self.elems[row * self.cols..(row + 1) * self.cols]
+  }
+
+  pub fn transpose(&self) -> Matrix {
+    let elems = (0..self.cols).flat_map(|c| (0..self.rows).map(move |r| (r, c))).map(|(r, c)| self.get(r, c)).collect();
+    Matrix{ rows: self.cols, cols: self.rows, elems }
+  }
+
+  pub fn matmul(&self, other: &Matrix) -> Result<Matrix, ErrorKind> {
+    if self.cols != other.rows {
+      return Err(ErrorKind::Shape);
+    }
+    let mut elems = Vec::with_capacity(self.rows * other.cols);
+    for r in 0..self.rows {
+      for c in 0..other.cols {
+        elems.push(inner(self.row(r), (0..other.rows).map(|k| other.get(k, c)))?);
+      }
+    }
+    Ok(Matrix{ rows: self.rows, cols: other.cols, elems })
+  }
+
+  /// Determinant by elimination, zero for singular matrices.
+  pub fn det(&self) -> Result<Decimal, ErrorKind> {
+    if self.rows != self.cols {
+      return Err(ErrorKind::Shape);
+    }
+    let mut a = self.clone();
+    let mut det = Decimal::ONE;
+    for k in 0..a.rows {
+      let pivot = match a.pivot(k) {
+        Some(pivot) => pivot,
+        None => return Ok(Decimal::ZERO),
+      };
+      if pivot != k {
+        a.swap(k, pivot);
+        det = -det;
+      }
+      det = det.checked_mul(a.get(k, k)).ok_or(ErrorKind::Num)?;
+      for r in k + 1..a.rows {
+        let factor = a.get(r, k).checked_div(a.get(k, k)).ok_or(ErrorKind::Num)?;
+        a.sub_row(r, k, factor)?;
+      }
+    }
+    Ok(det)
+  }
+
+  pub fn inverse(&self) -> Result<Matrix, ErrorKind> {
+    self.solve(&Matrix::identity(self.rows))
+  }
+
+  /// Solves `self * x = b` for `x` by Gauss-Jordan elimination. `self` must
+  /// be square and not singular.
+  pub fn solve(&self, b: &Matrix) -> Result<Matrix, ErrorKind> {
+    if self.rows != self.cols || b.rows != self.rows {
+      return Err(ErrorKind::Shape);
+    }
+    let n = self.rows;
+    // augmented matrix, `[self | b]`
+    let mut a = Matrix{
+      rows: n,
+      cols: n + b.cols,
+      elems: (0..n).flat_map(|r| self.row(r).iter().chain(b.row(r)).copied()).collect(),
+    };
+    for k in 0..n {
+      let pivot = a.pivot(k).ok_or(ErrorKind::Singular)?;
+      a.swap(k, pivot);
+      let scale = a.get(k, k);
+      for c in 0..a.cols {
+        a.elems[k * a.cols + c] = a.get(k, c).checked_div(scale).ok_or(ErrorKind::Num)?;
+      }
+      for r in (0..n).filter(|r| *r != k) {
+        let factor = a.get(r, k);
+        a.sub_row(r, k, factor)?;
+      }
+    }
+    let elems = (0..n).flat_map(|r| a.row(r)[n..].to_vec()).collect();
+    Ok(Matrix{ rows: n, cols: b.cols, elems })
+  }
+
+  /// Row at or below `k` with the largest value in column `k`, or `None` if
+  /// they're all zero.
+  fn pivot(&self, k: usize) -> Option<usize> {
+    (k..self.rows).filter(|r| !self.get(*r, k).is_zero()).max_by_key(|r| self.get(*r, k).abs())
+  }
+
+  fn swap(&mut self, a: usize, b: usize) {
+    for c in 0..self.cols {
+      self.elems.swap(a * self.cols + c, b * self.cols + c);
+    }
+  }
+
+  /// Subtracts `factor` times row `k` from row `r`.
+  fn sub_row(&mut self, r: usize, k: usize, factor: Decimal) -> Result<(), ErrorKind> {
+    for c in 0..self.cols {
+      let sub = factor.checked_mul(self.get(k, c)).ok_or(ErrorKind::Num)?;
+      self.elems[r * self.cols + c] = self.get(r, c).checked_sub(sub).ok_or(ErrorKind::Num)?;
+    }
+    Ok(())
+  }
+
+  pub fn into_val(self) -> Val {
+    Val::Array{ elems: self.elems.into_iter().map(Val::Num).collect(), axes: vec![self.rows as u32, self.cols as u32] }
+  }
+
+  /// The elements as a one dimensional array, for a single row or column.
+  pub fn into_vector(self) -> Val {
+    Val::Array{ axes: vec![self.elems.len() as u32], elems: self.elems.into_iter().map(Val::Num).collect() }
+  }
+}
+
+/// Sum of the products of `a` and `b`, element by element.
+fn inner(a: &[Decimal], b: impl Iterator<Item = Decimal>) -> Result<Decimal, ErrorKind> {
+  a.iter().zip(b).try_fold(Decimal::ZERO, |acc, (a, b)| acc.checked_add(a.checked_mul(b)?)).ok_or(ErrorKind::Num)
+}
+
+/// The first error among the elements of `vals`, passed on by the linear
+/// algebra functions.
+pub fn first_error(vals: &[Val]) -> Option<&Val> {
+  vals.iter().find_map(|val| match val {
+    Val::Error{..} => Some(val),
+    Val::List(elems) | Val::Array{elems, ..} => first_error(elems),
+    _ => None,
+  })
+}
+
+/// Dot product. Two vectors give a number, matrices and vectors are
+/// multiplied as matrices, with vectors as a row on the left and a column on
+/// the right.
+pub fn dot(left: &Val, right: &Val) -> Result<Val, ErrorKind> {
+  use Operand::*;
+  match (Operand::new(left)?, Operand::new(right)?) {
+    (Vector(a), Vector(b)) if a.len() == b.len() => inner(&a, b.into_iter()).map(Val::Num),
+    (Vector(_), Vector(_)) => Err(ErrorKind::Shape),
+    (Matrix(a), Matrix(b)) => a.matmul(&b).map(self::Matrix::into_val),
+    (Matrix(a), b @ Vector(_)) => a.matmul(&b.matrix()).map(self::Matrix::into_vector),
+    (a @ Vector(_), Matrix(b)) => a.matrix().transpose().matmul(&b).map(self::Matrix::into_vector),
+  }
+}
+
+/// Cross product of two vectors of length 3, or the length of the cross
+/// product of two vectors of length 2. Lists give a list.
+pub fn cross(left: &Val, right: &Val) -> Result<Val, ErrorKind> {
+  let (a, b) = match (Operand::new(left)?, Operand::new(right)?) {
+    (Operand::Vector(a), Operand::Vector(b)) if a.len() == b.len() => (a, b),
+    _ => return Err(ErrorKind::Shape),
+  };
+  let mul = |x: Decimal, y: Decimal| x.checked_mul(y).ok_or(ErrorKind::Num);
+  let det = |i: usize, j: usize| mul(a[i], b[j])?.checked_sub(mul(a[j], b[i])?).ok_or(ErrorKind::Num);
+  let elems = match a.len() {
+    2 => return det(0, 1).map(Val::Num),
+    3 => vec![det(1, 2)?, det(2, 0)?, det(0, 1)?],
+    _ => return Err(ErrorKind::Shape),
+  };
+  let elems = elems.into_iter().map(Val::Num).collect();
+  match (left, right) {
+    (Val::List(_), Val::List(_)) => Ok(Val::List(elems)),
+    _ => Ok(Val::Array{ elems, axes: vec![3] }),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  fn matrix(rows: usize, cols: usize, elems: &[i64]) -> Matrix {
+    Matrix{ rows, cols, elems: elems.iter().map(|e| Decimal::from(*e)).collect() }
+  }
+
+  #[test]
+  fn test_linalg_matrix() {
+    let a = matrix(2, 3, &[1, 2, 3, 4, 5, 6]);
+    assert_eq!(a.transpose(), matrix(3, 2, &[1, 4, 2, 5, 3, 6]));
+    assert_eq!(a.matmul(&a.transpose()), Ok(matrix(2, 2, &[14, 32, 32, 77])));
+    assert_eq!(a.matmul(&a), Err(ErrorKind::Shape));
+
+    let b = matrix(3, 3, &[1, 2, 3, 0, 1, 4, 5, 6, 0]);
+    assert_eq!(b.det(), Ok(dec!(1)));
+    assert_eq!(b.inverse(), Ok(matrix(3, 3, &[-24, 18, 5, 20, -15, -4, -5, 4, 1])));
+    assert_eq!(b.solve(&matrix(3, 1, &[14, 14, 17])), Ok(matrix(3, 1, &[1, 2, 3])));
+    // a zero pivot is swapped for a row below
+    let c = matrix(2, 2, &[0, 1, 1, 0]);
+    assert_eq!(c.det(), Ok(dec!(-1)));
+    assert_eq!(c.inverse(), Ok(c.clone()));
+    assert_eq!(c.solve(&matrix(2, 1, &[3, 4])), Ok(matrix(2, 1, &[4, 3])));
+
+    let singular = matrix(2, 2, &[1, 2, 2, 4]);
+    assert_eq!(singular.det(), Ok(dec!(0)));
+    assert_eq!(singular.inverse(), Err(ErrorKind::Singular));
+    assert_eq!(a.det(), Err(ErrorKind::Shape));
+    assert_eq!(a.inverse(), Err(ErrorKind::Shape));
+    assert_eq!(b.solve(&matrix(2, 1, &[1, 2])), Err(ErrorKind::Shape));
+    assert_eq!(matrix(1, 1, &[i64::MAX]).matmul(&matrix(1, 1, &[i64::MAX])).and_then(|m| m.matmul(&m)), Err(ErrorKind::Num));
+  }
+}
@@ -21,17 +21,29 @@ mod cell;
 mod rpc;
 mod parser;
 mod eval;
+mod builtins;
+mod linalg;
+mod collection;
+mod complex;
+mod rounding;
+mod closure;
+mod ast;
+mod funcs;
+mod printer;
+mod complete;
+mod translate;
 mod err;
 
 use std::{sync::RwLock, fmt::Debug};
 
-use rpc::TileUi;
+use rpc::{TileUi, TokenUi, CompletionUi};
 use tile::TileId;
 use tauri::State;
 
 use board::Board;
 use cell::Cell;
-use parser::Parser;
+use parser::{Assoc, Parser};
+use rounding::Rounding;
 
 
 
@@ -52,7 +64,7 @@ fn tile(state: State<BoardState>) -> TileUi {
   board.set_pos(tag, [0, 2], 37.8);
   board.set_pos(tag, [1, 0], 3.0);
 
-  return board.tile(tag).render();
+  return board.render_tile(tag);
 }
 
 #[tauri::command]
@@ -118,6 +130,60 @@ fn update_cell(state: State<BoardState>, tag: TileId, pos: [usize; 2], value: St
   return board.render()
 }
 
+#[tauri::command]
+fn define(state: State<BoardState>, source: String) -> Result<board::BoardUi, String> {
+  let mut board = state.board.write().unwrap();
+
+  board.define(&source).map_err(|err| err.to_string())?;
+
+  Ok(board.render())
+}
+
+#[tauri::command]
+fn define_op(state: State<BoardState>, symbol: String, prec: u8, assoc: Assoc, func: String) -> Result<board::BoardUi, String> {
+  let mut board = state.board.write().unwrap();
+
+  board.define_op(&symbol, prec, assoc, &func).map_err(|err| err.to_string())?;
+
+  Ok(board.render())
+}
+
+#[tauri::command]
+fn set_rounding(state: State<BoardState>, rounding: Rounding) -> board::BoardUi {
+  let mut board = state.board.write().unwrap();
+
+  board.set_rounding(rounding);
+
+  board.render()
+}
+
+#[tauri::command]
+fn highlight(state: State<BoardState>, formula: String) -> Vec<TokenUi> {
+  let board = state.board.read().unwrap();
+  Parser::new(formula).with_ops(board.funcs().ops()).highlight()
+}
+
+#[tauri::command]
+fn translate(formula: String) -> Result<String, String> {
+  translate::translate(&formula).map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+fn export(state: State<BoardState>, tag: TileId, pos: [usize; 2]) -> Result<String, String> {
+  let board = state.board.read().unwrap();
+  let cell = board.tile(tag).get_cell(pos);
+
+  let mut parser = Parser::new(cell.formula.as_str()).with_ops(board.funcs().ops());
+  let ast = parser.parse().ok_or_else(|| err::Err::Parse{ pos: parser.furthest() }.to_string())?;
+  Ok(translate::export(&ast, &parser))
+}
+
+#[tauri::command]
+fn completions(state: State<BoardState>, tag: TileId, formula: String, cursor: u32) -> Vec<CompletionUi> {
+  let board = state.board.read().unwrap();
+  complete::complete(&board, tag, &formula, cursor)
+}
+
 fn main() {
   let decorator = slog_term::TermDecorator::new().build();
   let drain = slog_term::FullFormat::new(decorator).build().fuse();
@@ -138,6 +204,13 @@ fn main() {
         add_column,
         add_row,
         update_cell,
+        define,
+        define_op,
+        set_rounding,
+        highlight,
+        completions,
+        translate,
+        export,
     ])
     .run(tauri::generate_context!())
     .expect("error while running tauri application");
@@ -1,19 +1,25 @@
-use std::any::Any;
+use std::borrow::Cow;
 use std::cmp::min;
 use std::fmt::Debug;
 use std::hash::Hash;
-use std::convert::TryInto;
+use std::ops::Range;
+use std::sync::Arc;
 
 use const_str;
 use log_derive::{logfn, logfn_inputs};
 use rust_decimal::Decimal;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
 #[allow(unused)]
 use slog::{info, warn};
 
-use crate::cell::{Val, Cell, CellId, CRef};
-use crate::eval::{ObjectContext, Node};
-use crate::eval::LIST_ELEMS;
-use crate::tile::TileContext;
+use crate::cell::{Val, ErrorKind};
+use crate::closure::Closure;
+use crate::eval::{FuncContext, ObjectContext, Node};
+use crate::funcs::{OpDef, Ops};
+use crate::rounding::Rounding;
+use crate::rpc::{TokenUi, TokenTypeUi};
+use crate::tile::{Axis, TileContext};
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u16)]
@@ -29,6 +35,9 @@ pub enum Tok {
   LBck, RBck,
   LBrc, RBrc,
   At,
+  Dollar,
+  Amp,
+  Err,
 }
 
 impl Default for Tok {
@@ -67,6 +76,12 @@ impl Default for Token {
 pub struct NodeId(pub u32);
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ValueId(pub u32);
+/// A run of list elements in the `elems` arena.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ElemRange {
+  pub start: u32,
+  pub len: u32,
+}
 
 impl Default for NodeId {
   fn default() -> Self {
@@ -96,44 +111,165 @@ impl TokCtx {
 struct ParseState {
   pos: usize,
   len_toks: usize,
-  len_nodes: usize,
 }
 
+/// Incremental parses fall back to a full reparse once the node arena grows
+/// past this multiple of the last full parse, bounding unreachable nodes.
+const REPARSE_FACTOR: usize = 4;
+/// Arena size below which incremental parses never fall back to a reparse.
+const REPARSE_MIN_LEN: usize = 8;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct RuleKey(usize);
 
-const N_RULE_KEYS: usize = 3;
 const fn rule_key(name: &'static str) -> RuleKey {
   if const_str::equal!(name, "expr_list") {
     RuleKey(2)
   } else if const_str::equal!(name, "expr") {
     RuleKey(1)
+  } else if const_str::equal!(name, "term") {
+    RuleKey(3)
+  } else if const_str::equal!(name, "binop") {
+    RuleKey(4)
   } else {
     RuleKey(0)
   }
 }
 
-type MemoArray = [Option<Box<dyn Any>>; N_RULE_KEYS];
+/// Memo table key: a rule, its argument (e.g. minimum precedence) and the
+/// position it was matched at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct MemoKey {
+  rule: RuleKey,
+  arg: u8,
+  pos: usize,
+  no_in: bool,
+}
+
+/// The memoized result of matching a rule.
+#[derive(Debug, Clone)]
+struct Memo {
+  node: Option<Node>,
+  end: usize,
+  /// Furthest position examined while matching, including lookahead.
+  extent: usize,
+  /// Tokens produced by the match, stored in `memo_toks`.
+  toks: Range<usize>,
+}
+
+/// Binary operators, the char they're stored as in `Node::BinOp`, and their
+/// precedence. Higher binds tighter. Builtin binary operators are left
+/// associative, except `^`. Precedences are spaced so board operators can
+/// bind between them. Collection operators bind looser than arithmetic,
+/// boolean ones loosest, and word operators must end the word, like keywords.
+const BINOPS: [(&str, char, u8); 19] = [
+  ("or", '∨', 2),
+  ("xor", '⊻', 3),
+  ("and", '∧', 4),
+  ("in", '∈', 5),
+  ("<", '<', 6),
+  (">", '>', 6),
+  ("<=", '≤', 6),
+  (">=", '≥', 6),
+  ("==", '=', 6),
+  ("!=", '≠', 6),
+  ("++", '⧺', 8),
+  ("union", '∪', 8),
+  ("inter", '∩', 9),
+  ("+", '+', 10),
+  ("-", '-', 10),
+  ("*", '*', 20),
+  ("**", '×', 20),
+  ("/", '/', 20),
+  ("^", '^', 30),
+];
+
+/// Precedence of the operand of `not`, which binds looser than comparisons
+/// and tighter than `and`: `not a == b` is `not (a == b)`.
+pub const NOT_PREC: u8 = 5;
+
+/// Highest precedence of a board operator.
+pub const MAX_OP_PREC: u8 = 100;
+
+/// Associativity of board operators: `a ± b ± c` is `(a ± b) ± c` when left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Serialize, Deserialize)]
+pub enum Assoc {
+  Left,
+  Right,
+}
+
+/// A matched binary operator, builtin or defined on the board.
+#[derive(Clone, Copy, Debug)]
+enum Infix<'a> {
+  Builtin(char),
+  Custom(&'a OpDef),
+}
+
+static NO_OPS: Ops = Ops::new();
+
+/// Reserved words of the formula language.
+pub const KEYWORDS: [&str; 11] = ["true", "false", "infinity", "let", "in", "def", "not", "if", "else", "match", "as"];
+
+pub fn binop_prec(op: char) -> Option<u8> {
+  BINOPS.iter().find(|(_, o, _)| *o == op).map(|(_, _, prec)| *prec)
+}
 
-pub struct Parser {
+/// How `op` is written, `**` for `×`.
+pub fn binop_symbol(op: char) -> &'static str {
+  BINOPS.iter().find(|(_, o, _)| *o == op).map_or("", |(symbol, _, _)| symbol)
+}
+
+/// The builtin binary operator written as `symbol`.
+pub fn binop(symbol: &str) -> Option<char> {
+  BINOPS.iter().find(|(s, _, _)| *s == symbol).map(|(_, op, _)| *op)
+}
+
+/// The text of a string literal's body, with each escaping backslash removed.
+fn unescape(body: &str) -> String {
+  let mut out = String::with_capacity(body.len());
+  let mut chars = body.chars();
+  while let Some(ch) = chars.next() {
+    match ch {
+      '\\' => out.extend(chars.next()),
+      ch => out.push(ch),
+    }
+  }
+  out
+}
+
+pub struct Parser<'a> {
   tokens: Vec<Token>,
   nodes: Vec<Node>,
   values: Vec<Val>,
+  elems: Vec<NodeId>,
 
-  memos: MemoArray,
+  memos: FxHashMap<MemoKey, Memo>,
+  memo_toks: Vec<Token>,
+  full_len: usize,
 
-  buf: Vec<char>,
+  /// Board operators recognized alongside the builtin ones.
+  ops: &'a Ops,
+  /// The formula, borrowed until an edit modifies it. Positions are byte offsets.
+  buf: Cow<'a, str>,
   pos: usize,
+  furthest: usize,
+  /// Whether `in` ends the expression rather than testing membership, as
+  /// it does in let bindings outside parentheses.
+  no_in: bool,
+  /// Whether builtin operators group from the right, see `right_grouped`.
+  right_grouped: bool,
 }
 
-impl Debug for Parser {
+impl Debug for Parser<'_> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     f.write_str("«")?;
-    for (i, ch) in self.buf.iter().enumerate() {
-      if i == self.pos {
+    match self.buf.get(..self.pos) {
+      Some(head) => {
+        f.write_str(head)?;
         f.write_str("▶")?;
-      }
-      f.write_str(ch.to_string().as_str())?;
+        f.write_str(&self.buf[self.pos..])?;
+      },
+      None => f.write_str(&self.buf)?,
     }
     f.write_str("»")?;
     Ok(())
@@ -141,18 +277,39 @@ impl Debug for Parser {
 }
 
 #[allow(unused)]
-impl Parser {
-  pub fn new<S: Into<String>>(input: S) -> Parser {
+impl<'a> Parser<'a> {
+  pub fn new<S: Into<Cow<'a, str>>>(input: S) -> Parser<'a> {
     Parser {
       tokens: vec![],
       nodes: vec![Node::default()],
       values: vec![Val::default()],
-      memos: [None, None, None],
-      buf: input.into().chars().collect(),
+      elems: vec![],
+      memos: FxHashMap::default(),
+      memo_toks: vec![],
+      full_len: 0,
+      ops: &NO_OPS,
+      buf: input.into(),
       pos: 0,
+      furthest: 0,
+      no_in: false,
+      right_grouped: false,
     }
   }
 
+  /// Recognizes the board operators `ops` in addition to the builtin ones.
+  pub fn with_ops(mut self, ops: &'a Ops) -> Parser<'a> {
+    self.ops = ops;
+    self
+  }
+
+  /// Reads builtin operators the way formulas were written before they had
+  /// precedence: all binding alike and grouping from the right, so `1 - 2 - 3`
+  /// is `1 - (2 - 3)` and `2 * 3 + 4` is `2 * (3 + 4)`.
+  pub fn right_grouped(mut self) -> Parser<'a> {
+    self.right_grouped = true;
+    self
+  }
+
   fn tok_ctx(&self, tag: Tok) -> TokCtx {
     TokCtx{ tok: Token::empty(tag, self.pos as u32) }
   }
@@ -181,6 +338,12 @@ impl Parser {
     ValueId(id)
   }
 
+  fn push_elems(&mut self, elems: &[NodeId]) -> ElemRange {
+    let start = self.elems.len() as u32;
+    self.elems.extend_from_slice(elems);
+    ElemRange{ start, len: elems.len() as u32 }
+  }
+
   fn yield_tok<T: Copy + Default>(&mut self, tag: Tok, rule: impl Fn(&mut Parser) -> Option<T>) -> Option<Token> {
     let tokctx = self.tok_ctx(tag);
     let res = rule(self);
@@ -194,15 +357,14 @@ impl Parser {
     }
   }
 
-  fn tok_value(&self, tok: Token) -> String {
+  fn tok_value(&self, tok: Token) -> &str {
     let p = tok.pos as usize;
     let len = tok.len as usize;
-    let val: String = self.buf[p..p+len].iter().collect();
-    val
+    &self.buf[p..p+len]
   }
 
   fn tok_values(&self) -> Vec<String> {
-    self.tokens.iter().map(|t|{ self.tok_value(*t) }).collect()
+    self.tokens.iter().map(|t|{ self.tok_value(*t).to_owned() }).collect()
   }
 
   fn get_pos(&self) -> usize {
@@ -214,7 +376,7 @@ impl Parser {
   }
 
   fn get_char(&self, tok: Token) -> char {
-    self.buf[tok.pos as usize]
+    self.buf[tok.pos as usize..].chars().next().unwrap_or_default()
   }
 
   fn set_pos(&mut self, p: usize) {
@@ -222,31 +384,85 @@ impl Parser {
   }
 
   fn next(&mut self) -> Option<char> {
-    let item = self.buf.get(self.pos)?;
-    self.pos += 1;
-    Some(*item)
+    self.furthest = self.furthest.max(self.pos);
+    let byte = *self.buf.as_bytes().get(self.pos)?;
+    if byte.is_ascii() {
+      self.pos += 1;
+      return Some(byte as char);
+    }
+    let item = self.buf[self.pos..].chars().next()?;
+    self.pos += item.len_utf8();
+    Some(item)
   }
 
 
   fn reset(&mut self) {
     self.set_pos(0);
+    self.furthest = 0;
+    self.no_in = false;
     self.tokens.truncate(0);
     self.nodes.truncate(1);
-    self.memos = [None, None, None];
+    self.values.truncate(1);
+    self.elems.truncate(0);
+    self.memos.clear();
+    self.memo_toks.truncate(0);
   }
 
   fn save(&self) -> ParseState {
     ParseState{
       pos: self.get_pos(),
       len_toks: self.tokens.len(),
-      len_nodes: self.nodes.len(),
     }
   }
 
+  /// Restores the position and tokens to `state`.
+  ///
+  /// Nodes are not truncated: memoized matches may still refer to them.
   fn rollback(&mut self, state: ParseState) {
     self.set_pos(state.pos);
     self.tokens.truncate(state.len_toks);
-    self.nodes.truncate(state.len_nodes);
+  }
+
+  fn record(&mut self, key: MemoKey, start: ParseState, node: Option<Node>) {
+    let toks_start = self.memo_toks.len();
+    let end = match node {
+      Some(_) => {
+        self.memo_toks.extend_from_slice(&self.tokens[start.len_toks..]);
+        self.pos
+      },
+      None => start.pos,
+    };
+    self.memos.insert(key, Memo{
+      node,
+      end,
+      extent: self.furthest,
+      toks: toks_start..self.memo_toks.len(),
+    });
+  }
+
+  fn replay(&mut self, memo: &Memo) -> Option<Node> {
+    self.furthest = self.furthest.max(memo.extent);
+    memo.node?;
+    self.tokens.extend_from_slice(&self.memo_toks[memo.toks.clone()]);
+    self.set_pos(memo.end);
+    memo.node
+  }
+
+  /// Matches `rule`, memoized by `key`, `arg` and the current position.
+  fn memo(&mut self, key: RuleKey, arg: u8, rule: impl Fn(&mut Parser) -> Option<Node>) -> Option<Node> {
+    let mkey = MemoKey{ rule: key, arg, pos: self.pos, no_in: self.no_in };
+    if let Some(memo) = self.memos.get(&mkey) {
+      let memo = memo.clone();
+      return self.replay(&memo);
+    }
+
+    let start = self.save();
+    let outer = self.furthest;
+    self.furthest = start.pos;
+    let res = rule(self);
+    self.record(mkey, start, res);
+    self.furthest = self.furthest.max(outer);
+    res
   }
 
   fn match_ws(&mut self) -> Option<char> {
@@ -261,8 +477,9 @@ impl Parser {
       item = self.next();
     }
     if matched {
-      if self.pos != self.buf.len() {
-        self.pos -= 1;
+      // step back over the non-whitespace char that ended the run
+      if let Some(ch) = item {
+        self.pos -= ch.len_utf8();
       }
       return first;
     }
@@ -398,53 +615,6 @@ impl Parser {
     last
   }
 
-  /// "Calls" a left-recursive rule.
-  #[logfn(Trace)]
-  #[logfn_inputs(Trace)]
-  fn left<T: Copy + Default + 'static + Debug>(&self, key: RuleKey) -> Option<T> {
-    let saved = self.memos.get(key.0)?;
-    if let Some(saved) = saved {
-      let res = *saved.downcast_ref::<Option<T>>()?;
-      res
-    } else {
-      None
-    }
-  }
-
-  #[logfn_inputs(Trace)]
-  fn _leftpoline(&self, key: RuleKey) {
-    // dummy
-  }
-
-  #[logfn(Trace)]
-  /// Marks a rule as left-recursive
-  fn leftpoline<T: Copy + Default + 'static + Debug>(&mut self, key: RuleKey, rule: impl Fn(&mut Parser) -> Option<T>) -> Option<T> {
-    self._leftpoline(key);
-    let mut saved: Option<T> = None;
-    let state = self.save();
-    let mut len_parsed = state.pos - self.pos;
-
-    // call rule repeatedly so long as it finds longer parse
-    // this does apparantly have a mathematic proof but
-    // I only read a post by Guido who read the paper.
-    // Thanks, Guido.
-    loop {
-      let res = rule(self);
-      if res.is_none() {
-        break;
-      }
-      let new_len = self.pos - state.pos;
-      if new_len <= len_parsed {
-        break;
-      }
-      saved = res;
-      // save the value in the memo for left "call"
-      self.memos[key.0] = Some(Box::new(saved));
-      len_parsed = new_len;
-    }
-    saved
-  }
-
   fn match_num_nonzero(&mut self) -> Option<char> {
     self.maybe(|s|s.char('-'))?;
     self.class("123456789")?;
@@ -456,7 +626,10 @@ impl Parser {
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
   fn match_num_zero(&mut self) -> Option<char> {
-    self.char('0')
+    self.maybe(|s|s.char('-'))?;
+    self.char('0')?;
+    self.maybe(|s|s.char('.'))?;
+    self.zero_or_more(|s|s.class("0123456789"))
   }
 
   #[logfn(Trace)]
@@ -466,14 +639,22 @@ impl Parser {
       |s|s.match_num_zero(),
       |s|s.match_num_nonzero(),
     ])).and_then(|tok|{
-      let decval = Decimal::from_str_radix(&self.tok_value(tok), 10).unwrap_or(Decimal::default());
+      let decval = Decimal::from_str_radix(self.tok_value(tok), 10).unwrap_or(Decimal::default());
       Some(Node::Leaf { value: self.push_value(Val::Num(decval)) })
     })
   }
 
+  /// Matches a string between `bookend`s, where a backslash escapes the
+  /// next char, `'a:\' b:"'`.
   fn match_string(&mut self, bookend: char) -> Option<char> {
     self.char(bookend)?;
-    self.zero_or_more(move |s|{s.not_char(bookend)})?;
+    self.zero_or_more(move |s|{
+      match s.next()? {
+        '\\' => s.next(),
+        ch if ch == bookend => None,
+        ch => Some(ch),
+      }
+    })?;
     self.char(bookend)?;
     Some(bookend)
   }
@@ -489,7 +670,7 @@ impl Parser {
     }).and_then(|tok|{
       let pos = tok.pos as usize;
       let end = tok.len as usize + pos;
-      let body: String = self.buf[pos+1..end-1].iter().collect();
+      let body = unescape(&self.buf[pos+1..end-1]);
       Some(Node::Leaf{ value: self.push_value(Val::Str(body)) })
     })
   }
@@ -501,6 +682,52 @@ impl Parser {
     })
   }
 
+  /// Matches `needle` as a whole word.
+  fn match_kw(&mut self, needle: &'static str) -> Option<char> {
+    self.push_tok(Tok::KW, |s|s.match_word(needle))
+  }
+
+  /// Matches any of `KEYWORDS`.
+  fn match_any_kw(&mut self) -> Option<char> {
+    for kw in KEYWORDS {
+      let state = self.save();
+      match self.match_kw(kw) {
+        Some(ch) => return Some(ch),
+        None => self.rollback(state),
+      }
+    }
+    None
+  }
+
+  fn match_word(&mut self, needle: &'static str) -> Option<char> {
+    let res = self.string(needle)?;
+    let state = self.save();
+    match self.next() {
+      Some(ch) if ch.is_alphanumeric() || ch == '_' => None,
+      _ => {
+        self.rollback(state);
+        Some(res)
+      },
+    }
+  }
+
+  /// Matches `∞` or `infinity`, optionally negated, as an infinite `Float`.
+  fn r_infinity(&mut self) -> Option<Node> {
+    self.yield_tok(Tok::Num, |s|{
+      s.maybe(|s|s.char('-'))?;
+      s.select([
+        |s|s.char('∞'),
+        |s|s.match_word("infinity"),
+      ])
+    }).map(|tok|{
+      let inf = match self.tok_value(tok).starts_with('-') {
+        true => f64::NEG_INFINITY,
+        false => f64::INFINITY,
+      };
+      Node::Leaf { value: self.push_value(Val::Float(inf)) }
+    })
+  }
+
   fn r_true(&mut self) -> Option<Node> {
     self.match_bool("true", true)
   }
@@ -518,10 +745,6 @@ impl Parser {
     ])
   }
 
-  fn match_plus(&mut self) -> Option<char> { self.char('+') }
-  fn match_minus(&mut self) -> Option<char> { self.char('-') }
-  fn match_star(&mut self) -> Option<char> { self.char('*') }
-  fn match_fslash(&mut self) -> Option<char> { self.char('/') }
   fn match_bslash(&mut self) -> Option<char> { self.char('\\') }
 
   #[logfn(Trace)]
@@ -529,6 +752,7 @@ impl Parser {
   fn r_term_literal(&mut self) -> Option<Node> {
     self.select([
       |s|{s.r_num()},
+      |s|{s.r_infinity()},
       |s|{s.r_string()},
       |s|{s.r_bool()},
     ])
@@ -545,162 +769,502 @@ impl Parser {
   #[logfn_inputs(Trace)]
   fn r_term_paren(&mut self) -> Option<Node> {
     self.match_lpar()?;
-    let expr = self.r_expr()?;
+    let no_in = std::mem::replace(&mut self.no_in, false);
+    let expr = self.r_expr();
+    self.no_in = no_in;
     self.match_rpar()?;
-    Some(expr)
+    expr
   }
 
+  /// Matches `name = value`.
+  fn match_binding(&mut self) -> Option<(Node, Node)> {
+    let name = self.r_term_sym()?;
+    self.maybe_ws()?;
+    self.push_tok(Tok::Op, |s|s.char('='))?;
+    self.maybe_ws()?;
+    let value = self.r_expr_binop()?;
+    Some((name, value))
+  }
+
+  /// Matches one or more comma separated bindings.
+  fn match_bindings(&mut self) -> Option<Vec<(Node, Node)>> {
+    let mut binds = vec![self.match_binding()?];
+    self.zero_or_more(|s|{
+      s.maybe_ws()?;
+      s.char(',')?;
+      s.maybe_ws()?;
+      let bind = s.match_binding()?;
+      binds.push(bind);
+      Some(bind)
+    })?;
+    Some(binds)
+  }
+
+  /// Matches `let name = value, name = value in body`.
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
-  fn r_term(&mut self) -> Option<Node> {
-    self.select([
-      |s|s.r_term_literal(),
-      |s|s.r_term_sym(),
-      |s|s.r_term_paren(),
-    ])
+  fn r_term_let(&mut self) -> Option<Node> {
+    self.match_kw("let")?;
+    self.maybe_ws()?;
+    // `in` ends the bindings, so a membership test in one is parenthesized
+    let no_in = std::mem::replace(&mut self.no_in, true);
+    let binds = self.match_bindings();
+    self.no_in = no_in;
+    let binds = binds?;
+    self.maybe_ws()?;
+    self.match_kw("in")?;
+    self.maybe_ws()?;
+    let body = self.r_expr()?;
+
+    let binds: Vec<NodeId> = binds.into_iter()
+      .flat_map(|(name, value)| [name, value])
+      .map(|n|self.push_node(n))
+      .collect();
+    Some(Node::Let{ binds: self.push_elems(&binds), body: self.push_node(body) })
   }
 
-  fn match_binop(&mut self) -> Option<char> {
-    self.push_tok(Tok::Op, |s|{
-      s.select([
-        |s|s.match_plus(),
-        |s|s.match_minus(),
-        |s|s.match_star(),
-        |s|s.match_fslash(),
-      ])
-    })
+  /// Matches `not operand`.
+  fn r_term_not(&mut self) -> Option<Node> {
+    self.match_kw("not")?;
+    self.maybe_ws()?;
+    let operand = self.match_binop_prec(NOT_PREC)?;
+    Some(Node::UniOp{ op: '¬', rhs: self.push_node(operand) })
   }
 
+  /// Matches `else other`, the rest of an `if` or `match`.
+  fn match_else(&mut self) -> Option<Node> {
+    self.maybe_ws()?;
+    self.match_kw("else")?;
+    self.maybe_ws()?;
+    self.r_expr_binop()
+  }
+
+  /// Matches `if cond then else other`. The branches follow the condition
+  /// without a separator, so a branch starting with `-` is parenthesized.
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
-  fn r_expr_binop(&mut self) -> Option<Node> {
-    let lnode = self.r_term()?;
-    let left = self.push_node(lnode);
+  fn r_term_if(&mut self) -> Option<Node> {
+    self.match_kw("if")?;
+    self.maybe_ws()?;
+    let cond = self.r_expr_binop()?;
+    self.maybe_ws()?;
+    let then = self.r_expr_binop()?;
+    let other = self.match_else()?;
+    Some(Node::If{ cond: self.push_node(cond), then: self.push_node(then), other: self.push_node(other) })
+  }
 
+  fn match_arm(&mut self) -> Option<(Node, Node)> {
+    self.maybe_ws()?;
+    self.match_kw("as")?;
     self.maybe_ws()?;
-    let op = self.match_binop()?;
+    let pattern = self.r_term()?;
     self.maybe_ws()?;
-    let rnode = self.r_expr()?;
-    let right = self.push_node(rnode);
-    Some(Node::BinOp { op: op, lhs: left, rhs: right })
+    let result = self.r_expr_binop()?;
+    Some((pattern, result))
   }
 
-  /// Construct a list from a zero_or_more list match
-  fn build_list(&mut self, elems: Vec<NodeId>) -> Node  {
-    let len = elems.len();
-    let clampled_len = min(len, LIST_ELEMS);
-
-    if len <= clampled_len {
-      let padding = vec![NodeId(0); LIST_ELEMS - clampled_len];
-      let extended = [elems, padding].concat();
-      return Node::List {
-        elems: extended.try_into().unwrap(),
-        len: len,
-        link: None,
-      };
-    }
+  /// Matches `match subject as pattern result ...`, optionally followed by
+  /// `else other`. Patterns are terms.
+  #[logfn(Trace)]
+  #[logfn_inputs(Trace)]
+  fn r_term_match(&mut self) -> Option<Node> {
+    self.match_kw("match")?;
+    self.maybe_ws()?;
+    let subject = self.r_expr_binop()?;
+    let mut arms = vec![self.match_arm()?];
+    self.zero_or_more(|s|{
+      let arm = s.match_arm()?;
+      arms.push(arm);
+      Some(arm)
+    })?;
+    let arms: Vec<NodeId> = arms.into_iter()
+      .flat_map(|(pattern, result)| [pattern, result])
+      .map(|n|self.push_node(n))
+      .collect();
+    let state = self.save();
+    let other = match self.match_else() {
+      Some(other) => self.push_node(other),
+      None => {
+        self.rollback(state);
+        NodeId(0)
+      },
+    };
+    Some(Node::Match{ subject: self.push_node(subject), arms: self.push_elems(&arms), other })
+  }
 
-    let link_node = self.build_list(elems[LIST_ELEMS..].to_vec());
-    Node::List {
-      elems: elems[..LIST_ELEMS].try_into().unwrap(),
-      len: len,
-      link: Some(self.push_node(link_node)),
+  /// Matches parenthesized parameter names, `(param, ...)`.
+  fn match_params(&mut self) -> Option<Vec<Node>> {
+    let mut params = vec![];
+    self.match_lpar()?;
+    self.maybe_ws()?;
+    let start = self.save();
+    match self.r_term_sym() {
+      Some(first) => {
+        params.push(first);
+        self.zero_or_more(|s|{
+          s.maybe_ws()?;
+          s.char(',')?;
+          s.maybe_ws()?;
+          let param = s.r_term_sym()?;
+          params.push(param);
+          Some(param)
+        })?;
+      },
+      None => self.rollback(start),
     }
+    self.maybe_ws()?;
+    self.match_rpar()?;
+    Some(params)
+  }
+
+  fn build_lambda(&mut self, params: Vec<Node>, body: Node) -> Node {
+    let params: Vec<NodeId> = params.into_iter().map(|p|self.push_node(p)).collect();
+    Node::Lambda{ params: self.push_elems(&params), body: self.push_node(body) }
   }
 
-  /// Construct a list from a left_rec list match
-  fn cons_list(&mut self, lnode: &Node, left: NodeId, right: NodeId) -> Node {
-    match lnode {
-      &Node::List{elems, len: LIST_ELEMS, link: None} => {
-        let empty_node = Node::List{elems: [NodeId(0); LIST_ELEMS], len: 0, link: None};
-        let empty = self.push_node(empty_node);
-        let link = self.cons_list(&empty_node, empty, right);
-        Node::List{elems: elems, len: LIST_ELEMS + 1, link: Some(self.push_node(link))}
+  /// Matches `param -> body` or `(param, ...) -> body`.
+  #[logfn(Trace)]
+  #[logfn_inputs(Trace)]
+  fn r_term_lambda(&mut self) -> Option<Node> {
+    let state = self.save();
+    let params = match self.r_term_sym() {
+      Some(param) => vec![param],
+      None => {
+        self.rollback(state);
+        self.match_params()?
       },
-      &Node::List{elems, len, link: Some(link)} => {
-        let orig_link = *self.get_node(&link);
-        let new_link_node = self.cons_list(&orig_link, link, right);
-        let new_link = self.push_node(new_link_node);
-        Node::List{elems: elems, len: len + 1, link: Some(new_link)}
+    };
+    self.maybe_ws()?;
+    self.push_tok(Tok::Op, |s|s.string("->"))?;
+    self.maybe_ws()?;
+    let body = self.r_expr_binop()?;
+    Some(self.build_lambda(params, body))
+  }
+
+  /// Matches `def name(param, ...) = body`, returning the name and the
+  /// equivalent lambda.
+  fn r_def(&mut self) -> Option<(String, Node)> {
+    self.maybe_ws()?;
+    self.match_kw("def")?;
+    self.maybe_ws()?;
+    let name = match self.r_term_sym()? {
+      Node::Sym{value} => self.get_value(&value).to_string(),
+      _ => return None,
+    };
+    self.maybe_ws()?;
+    let params = self.match_params()?;
+    self.maybe_ws()?;
+    self.push_tok(Tok::Op, |s|s.char('='))?;
+    self.maybe_ws()?;
+    let body = self.r_expr()?;
+    Some((name, self.build_lambda(params, body)))
+  }
+
+  #[logfn(Trace)]
+  #[logfn_inputs(Trace)]
+  fn r_term(&mut self) -> Option<Node> {
+    self.memo(rule_key("term"), 0, |s|s.match_term())
+  }
+
+  fn match_term(&mut self) -> Option<Node> {
+    self.select([
+      |s|s.r_term_literal(),
+      |s|s.r_term_let(),
+      |s|s.r_term_not(),
+      |s|s.r_term_if(),
+      |s|s.r_term_match(),
+      |s|s.r_term_lambda(),
+      |s|s.r_term_call(),
+      |s|s.r_term_paren(),
+      |s|s.r_term_abs(),
+      |s|s.r_expr_index(),
+      |s|s.r_expr_addr(),
+      |s|s.r_expr_legacy(),
+      |s|s.r_term_tile(),
+      // last, so a keyword form that doesn't parse isn't retried as a name
+      |s|s.r_term_sym(),
+    ])
+  }
+
+  fn match_dollar(&mut self) -> Option<char> {
+    self.push_tok(Tok::Dollar, |s|s.char('$'))
+  }
+
+  /// Matches `rule`, optionally marked absolute with a leading `$`.
+  fn match_abs(&mut self, rule: Rule<Node>) -> Option<Node> {
+    let state = self.save();
+    if self.match_dollar().is_none() {
+      self.rollback(state);
+      return rule(self);
+    }
+    let inner = rule(self)?;
+    Some(Node::UniOp { op: '$', rhs: self.push_node(inner) })
+  }
+
+  #[logfn(Trace)]
+  #[logfn_inputs(Trace)]
+  fn r_term_abs(&mut self) -> Option<Node> {
+    self.match_dollar()?;
+    let inner = self.r_term()?;
+    Some(Node::UniOp { op: '$', rhs: self.push_node(inner) })
+  }
+
+  /// Matches the longest builtin binary operator, `**` rather than `*`.
+  fn match_binop(&mut self) -> Option<char> {
+    let rest = &self.buf[self.pos.min(self.buf.len())..];
+    let is_word = |ch: char| ch.is_alphanumeric() || ch == '_';
+    let (symbol, op, _) = BINOPS.iter()
+      .filter(|(symbol, _, _)| rest.starts_with(symbol))
+      .filter(|(symbol, _, _)| !symbol.ends_with(is_word) || !rest[symbol.len()..].starts_with(is_word))
+      .filter(|(_, op, _)| *op != '∈' || !self.no_in)
+      .max_by_key(|(symbol, _, _)| symbol.len())?;
+    self.push_tok(Tok::Op, |s|s.string(*symbol))?;
+    Some(*op)
+  }
+
+  /// Matches a board operator. Operators ending in a word character must
+  /// end the word, like keywords.
+  fn match_custom_op(&mut self) -> Option<&'a OpDef> {
+    let ops = self.ops;
+    for op in ops.iter() {
+      let state = self.save();
+      let matched = self.push_tok(Tok::Op, |s|{
+        let last = s.string(op.symbol.as_str())?;
+        let end = s.save();
+        match s.next() {
+          Some(ch) if (last.is_alphanumeric() || last == '_') && (ch.is_alphanumeric() || ch == '_') => None,
+          _ => {
+            s.rollback(end);
+            Some(last)
+          },
+        }
+      });
+      if matched.is_some() {
+        return Some(op);
       }
-      &Node::List{mut elems, len, link} => {
-        elems[len] = right;
-        Node::List{elems: elems, len: len+1, link: link}
+      self.rollback(state);
+    }
+    None
+  }
+
+  fn match_infix(&mut self) -> Option<Infix<'a>> {
+    let state = self.save();
+    if let Some(op) = self.match_custom_op() {
+      return Some(Infix::Custom(op));
+    }
+    self.rollback(state);
+    self.match_binop().map(Infix::Builtin)
+  }
+
+  /// Precedence of `op` and the minimum precedence of its right operand.
+  fn infix_prec(&self, op: Infix) -> (u8, u8) {
+    match op {
+      // all at the precedence of `+`
+      Infix::Builtin(_) if self.right_grouped => (10, 10),
+      Infix::Builtin(op) => {
+        let prec = binop_prec(op).unwrap_or(0);
+        match op {
+          '^' => (prec, prec),
+          _ => (prec, prec + 1),
+        }
       },
-      _ => {
-        let mut elems = [NodeId(0); LIST_ELEMS];
-        elems[0] = left;
-        elems[1] = right;
-        Node::List{elems: elems, len: 2, link: None}
+      Infix::Custom(op) => {
+        match op.assoc {
+          Assoc::Left => (op.prec, op.prec + 1),
+          Assoc::Right => (op.prec, op.prec),
+        }
       },
     }
   }
 
+  fn match_binop_prec(&mut self, min_prec: u8) -> Option<Node> {
+    self.memo(rule_key("binop"), min_prec, |s|s.match_binop_climb(min_prec))
+  }
+
+  /// Precedence climbing over `BINOPS` and the board operators, starting
+  /// from a single term.
+  fn match_binop_climb(&mut self, min_prec: u8) -> Option<Node> {
+    let mut lnode = self.r_term()?;
+
+    loop {
+      let state = self.save();
+      self.maybe_ws()?;
+      let (op, rprec) = match self.match_infix() {
+        Some(op) if self.infix_prec(op).0 >= min_prec => (op, self.infix_prec(op).1),
+        _ => {
+          self.rollback(state);
+          break;
+        }
+      };
+      self.maybe_ws()?;
+
+      let rnode = match self.match_binop_prec(rprec) {
+        Some(rnode) => rnode,
+        None => {
+          self.rollback(state);
+          break;
+        }
+      };
+
+      let lhs = self.push_node(lnode);
+      let rhs = self.push_node(rnode);
+      lnode = match op {
+        Infix::Builtin(op) => Node::BinOp { op, lhs, rhs },
+        Infix::Custom(def) => {
+          let op = self.push_value(Val::Str(def.symbol.clone()));
+          Node::CustomOp { op, prec: def.prec, assoc: def.assoc, lhs, rhs }
+        },
+      };
+    }
+    Some(lnode)
+  }
+
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
-  fn match_list_zero_or_more(&mut self) -> Option<Node> {
-    let lnode: Node = self.left(rule_key("expr"))?;
-    let first = self.push_node(lnode);
+  fn r_expr_binop(&mut self) -> Option<Node> {
+    self.match_binop_prec(0)
+  }
 
-    let mut elems = vec![first];
+  fn build_list(&mut self, elems: Vec<NodeId>) -> Node  {
+    Node::List { elems: self.push_elems(&elems) }
+  }
+
+  /// Matches two or more comma separated elements.
+  #[logfn(Trace)]
+  #[logfn_inputs(Trace)]
+  fn match_list(&mut self) -> Option<Node> {
+    let first = self.match_elem()?;
+    let mut rest = vec![];
 
-    self.maybe_ws()?;
-    self.char(',')?;
     self.zero_or_more(|s|{
       s.maybe_ws()?;
-      let node = s.r_term()?;
-      let nid = s.push_node(node);
-      s.maybe_ws()?;
-      s.maybe(|s|s.char(','))?;
+      s.char(',')?;
       s.maybe_ws()?;
-      elems.push(nid);
+      let node = s.match_elem()?;
+      rest.push(node);
       Some(node)
     })?;
+
+    if rest.is_empty() {
+      return None;
+    }
+    let elems = std::iter::once(first).chain(rest).map(|n|self.push_node(n)).collect();
     Some(self.build_list(elems))
   }
 
-
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
-  fn match_list_left_rec(&mut self) -> Option<Node> {
-    // TODO benchmark match_list_left_rec against match_list_zero_or_more
-    let lnode: Node = self.left(rule_key("expr"))?;
-    let left = self.push_node(lnode);
+  fn r_expr_list(&mut self) -> Option<Node> {
+    self.match_list()
+  }
 
-    self.maybe_ws()?;
-    self.char(',')?;
-    self.maybe_ws()?;
-    let rnode = self.r_term()?;
-    let right = self.push_node(rnode);
+  /// Matches an element of a list or of call arguments: a record field, or
+  /// an expression without commas.
+  #[inline]
+  fn match_elem(&mut self) -> Option<Node> {
+    match self.match_field() {
+      Some(field) => Some(field),
+      None => self.r_expr_binop(),
+    }
+  }
 
-    Some(self.cons_list(&lnode, left, right))
+  /// Matches a record field, `key: value`, keyed by a name or a string.
+  /// Rolls back on failure.
+  fn match_field(&mut self) -> Option<Node> {
+    let state = self.save();
+    let field = self.match_field_inner();
+    if field.is_none() {
+      self.rollback(state);
+    }
+    field
   }
 
-  #[logfn(Trace)]
-  #[logfn_inputs(Trace)]
-  fn r_expr_list(&mut self) -> Option<Node> {
-    self.match_list_left_rec()
+  fn match_field_inner(&mut self) -> Option<Node> {
+    let key = self.select([
+      |s|s.r_term_sym(),
+      |s|s.r_string(),
+    ])?;
+    self.maybe_ws()?;
+    self.char(':')?;
+    self.maybe_ws()?;
+    let value = self.r_expr_binop()?;
+    Some(Node::Field{ key: self.push_node(key), value: self.push_node(value) })
   }
 
 
+  /// Matches a name. Keywords aren't names, so a `let` or `if` that doesn't
+  /// parse as one fails rather than being read as a symbol.
   fn r_term_sym(&mut self) -> Option<Node> {
     self.yield_tok(Tok::Sym, |s|{
-      s.one_or_more(|s|{ s.class_caseins("abcdefghijklmnopqrstuvwxyz") })
+      let start = s.pos;
+      s.one_or_more(|s|{ s.class_caseins("abcdefghijklmnopqrstuvwxyz") })?;
+      // dotted names, e.g. `SparseArray.fill`
+      let last = s.zero_or_more(|s|{
+        s.char('.')?;
+        s.one_or_more(|s|{ s.class_caseins("abcdefghijklmnopqrstuvwxyz") })
+      })?;
+      match KEYWORDS.contains(&&s.buf[start..s.pos]) {
+        true => None,
+        false => Some(last),
+      }
     }).and_then(|tok|{
       // todo cache value
-      let value = self.tok_value(tok);
-      Some(Node::Leaf { value: self.push_value(Val::Str(value)) })
+      let value = self.tok_value(tok).to_owned();
+      Some(Node::Sym { value: self.push_value(Val::Str(value)) })
     })
   }
 
+  /// Matches call arguments separated by `,` or `;`, returning them as a
+  /// list node.
+  fn match_args(&mut self) -> Option<NodeId> {
+    let mut elems = vec![];
+    let state = self.save();
+    match self.match_elem() {
+      Some(first) => {
+        elems.push(self.push_node(first));
+        self.zero_or_more(|s|{
+          s.maybe_ws()?;
+          s.class(",;")?;
+          s.maybe_ws()?;
+          let node = s.match_elem()?;
+          elems.push(s.push_node(node));
+          Some(node)
+        })?;
+      },
+      None => self.rollback(state),
+    }
+    let list = self.build_list(elems);
+    Some(self.push_node(list))
+  }
+
+  #[logfn(Trace)]
+  #[logfn_inputs(Trace)]
+  fn r_term_call(&mut self) -> Option<Node> {
+    let name = self.r_term_sym()?;
+    self.match_lpar()?;
+    self.maybe_ws()?;
+    let args = self.match_args()?;
+    self.maybe_ws()?;
+    self.match_rpar()?;
+    Some(Node::Call { func: self.push_node(name), args })
+  }
+
   fn match_compound(&mut self, start: (char, Tok), end: (char, Tok), cb: impl Fn(NodeId, NodeId) -> Node) -> Option<Node> {
     self.push_tok(start.1, |s|s.char(start.0))?;
     self.maybe_ws()?;
-    let inner = self.r_expr()?;
+    let state = self.save();
+    let inner = match self.match_slices() {
+      Some(inner) => inner,
+      None => {
+        self.rollback(state);
+        self.r_expr()?
+      },
+    };
     let mut row = NodeId(0);
     let mut col = NodeId(0);
 
-    if let Node::List{elems, len, link: _} = inner {
+    if let Node::List{elems} = inner {
+      let elems = self.get_elems(&elems);
       row = elems[0];
       col = elems[1];
     } else {
@@ -712,6 +1276,63 @@ impl Parser {
     Some(cb(row, col))
   }
 
+  /// Matches a slice, `1:3`, `:3`, `1:` or `:`, followed by whitespace.
+  fn match_slice(&mut self) -> Option<Node> {
+    let state = self.save();
+    let start = self.r_expr_binop();
+    if start.is_none() {
+      self.rollback(state);
+    }
+    self.maybe_ws()?;
+    self.char(':')?;
+    self.maybe_ws()?;
+    // pushed once matched, leaving node ids as they were for other references
+    let start = start.map_or(NodeId(0), |start|self.push_node(start));
+    let state = self.save();
+    let end = match self.r_expr_binop() {
+      Some(end) => self.push_node(end),
+      None => {
+        self.rollback(state);
+        NodeId(0)
+      },
+    };
+    self.maybe_ws()?;
+    Some(Node::Slice{ start, end })
+  }
+
+  /// Matches the inside of a reference with at least one slice, `[0, 1:3]`.
+  fn match_slices(&mut self) -> Option<Node> {
+    let mut elems = vec![];
+    for i in 0..2 {
+      let state = self.save();
+      if i > 0 && self.char(',').and_then(|_|self.maybe_ws()).is_none() {
+        self.rollback(state);
+        break;
+      }
+      let state = self.save();
+      let elem = match self.match_slice() {
+        Some(slice) => slice,
+        None => {
+          self.rollback(state);
+          let elem = self.r_expr_binop()?;
+          self.maybe_ws()?;
+          elem
+        },
+      };
+      elems.push(elem);
+    }
+    if !elems.iter().any(|elem| matches!(elem, Node::Slice{..})) {
+      return None;
+    }
+    match elems[..] {
+      [elem] => Some(elem),
+      _ => {
+        let elems = elems.into_iter().map(|elem|self.push_node(elem)).collect();
+        Some(self.build_list(elems))
+      },
+    }
+  }
+
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
   fn r_expr_index(&mut self) -> Option<Node> {
@@ -727,14 +1348,14 @@ impl Parser {
   }
 
   fn match_legacy_row(&mut self) -> Option<Node> {
-    let r = self.r_num()?;
-    let c = self.r_term_sym()?;
+    let r = self.match_abs(|s|s.r_num())?;
+    let c = self.match_abs(|s|s.r_term_sym())?;
     Some(Node::Addr { row: self.push_node(r), col: self.push_node(c) })
   }
 
   fn match_legacy_col(&mut self) -> Option<Node> {
-    let c = self.r_term_sym()?;
-    let r = self.r_num()?;
+    let c = self.match_abs(|s|s.r_term_sym())?;
+    let r = self.match_abs(|s|s.r_num())?;
     Some(Node::Addr { row: self.push_node(r), col: self.push_node(c) })
   }
 
@@ -747,8 +1368,31 @@ impl Parser {
     })
   }
 
-  fn r_expr_lookup(&mut self) -> Option<Node> {
-    self.r_term_sym()
+  /// Matches an unquoted tile name, which unlike other names may contain
+  /// digits and underscores after the first letter, `Sheet2`.
+  fn match_tile_name(&mut self) -> Option<Node> {
+    let tok = self.yield_tok(Tok::Sym, |s|{
+      s.class_caseins("abcdefghijklmnopqrstuvwxyz")?;
+      s.zero_or_more(|s|s.class_caseins("abcdefghijklmnopqrstuvwxyz0123456789_"))
+    })?;
+    let value = self.tok_value(tok).to_owned();
+    Some(Node::Sym { value: self.push_value(Val::Str(value)) })
+  }
+
+  /// Matches a reference into another tile, by name or id: `&Units{B, 3}`,
+  /// `&'Rent Roll'[2, 2]` or `&2[0, :]`.
+  fn r_term_tile(&mut self) -> Option<Node> {
+    self.push_tok(Tok::Amp, |s|s.char('&'))?;
+    let tile = self.match_abs(|s|s.select([
+      |s|s.match_tile_name(),
+      |s|s.r_string(),
+      |s|s.r_num(),
+    ]))?;
+    let cell = self.select([
+      |s|s.r_expr_index(),
+      |s|s.r_expr_addr(),
+    ])?;
+    Some(Node::TileRef{ tile: self.push_node(tile), cell: self.push_node(cell) })
   }
 
   #[logfn(Trace)]
@@ -756,14 +1400,9 @@ impl Parser {
   fn match_expr(&mut self) -> Option<Node>  {
     self.maybe_ws()?;
     let res = self.select([
-      |s| s.r_expr_binop(),
       |s| s.r_expr_list(),
-      |s| s.r_term(),
+      |s| s.match_elem(),
       // |s| s.r_expr_assign(),
-      |s| s.r_expr_lookup(),
-      |s| s.r_expr_index(),
-      |s| s.r_expr_addr(),
-      |s| s.r_expr_legacy(),
     ])?;
     self.maybe_ws()?;
     Some(res)
@@ -772,7 +1411,7 @@ impl Parser {
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
   fn r_expr(&mut self) -> Option<Node> {
-    self.leftpoline(rule_key("expr"), |s|s.match_expr())
+    self.memo(rule_key("expr"), 0, |s|s.match_expr())
   }
 
   pub fn scan(&mut self) -> Vec<String> {
@@ -782,35 +1421,270 @@ impl Parser {
     }
   }
 
+  /// Parses the buffer as a function definition, see `r_def`.
+  pub fn parse_def(&mut self) -> Option<(String, Node)> {
+    self.reset();
+    let def = self.r_def()?;
+    if self.pos < self.buf.len() {
+      return None;
+    }
+    Some(def)
+  }
+
+  /// Furthest position examined by the last parse, where a failed parse
+  /// went wrong.
+  pub fn furthest(&self) -> usize {
+    self.furthest
+  }
+
   pub fn reparse(&mut self) -> Option<Node> {
     self.reset();
-    self.r_expr()
+    self.parse()
+  }
+
+  /// Total size of the append-only arenas, which incremental parses grow.
+  fn arena_len(&self) -> usize {
+    self.nodes.len() + self.elems.len() + self.memo_toks.len()
   }
 
+  /// Parses the whole buffer as a formula, failing if any of it is left over.
   pub fn parse(&mut self) -> Option<Node> {
-    self.r_expr()
+    let res = self.r_expr();
+    self.full_len = self.arena_len();
+    self.whole(res)
+  }
+
+  /// `res`, if it ends at the end of the buffer.
+  fn whole(&self, res: Option<Node>) -> Option<Node> {
+    res.filter(|_| self.pos >= self.buf.len())
+  }
+
+  /// Replaces the byte `range` of the buffer with `text` and parses the result.
+  ///
+  /// Memoized matches that did not examine the edited range are kept, shifted
+  /// past the edit where needed, so only the damaged region and the rules
+  /// enclosing it are reparsed. Their nodes are shared with the new AST.
+  ///
+  /// Gives `None`, leaving the buffer as it was, if `range` does not lie on
+  /// char boundaries.
+  pub fn edit(&mut self, range: Range<usize>, text: &str) -> Option<Node> {
+    let end = min(range.end, self.buf.len());
+    let start = min(range.start, end);
+    if !self.buf.is_char_boundary(start) || !self.buf.is_char_boundary(end) {
+      return None;
+    }
+    let delta = text.len() as isize - (end - start) as isize;
+    let shift = |pos: usize| (pos as isize + delta) as usize;
+
+    self.buf.to_mut().replace_range(start..end, text);
+
+    if self.arena_len() > REPARSE_FACTOR * self.full_len.max(REPARSE_MIN_LEN) {
+      return self.reparse();
+    }
+
+    // A match before the edit is only kept if its lookahead stopped short of
+    // the edit, e.g. `12` is not reused when a digit is typed after it.
+    let mut shifted = vec![];
+    self.memos.retain(|key, memo| {
+      if key.pos >= end && memo.extent >= start {
+        shifted.push((*key, memo.clone()));
+      }
+      memo.extent < start
+    });
+    for (key, memo) in shifted {
+      for tok in &mut self.memo_toks[memo.toks.clone()] {
+        tok.pos = shift(tok.pos as usize) as u32;
+      }
+      self.memos.insert(MemoKey{ pos: shift(key.pos), ..key }, Memo{
+        end: shift(memo.end),
+        extent: shift(memo.extent),
+        ..memo
+      });
+    }
+
+    self.tokens.truncate(0);
+    self.set_pos(0);
+    self.furthest = 0;
+    let res = self.r_expr();
+    self.whole(res)
+  }
+
+  /// `edit` with `range` in UTF-16 code units, as the frontend counts them.
+  /// Gives `None` if an offset splits a surrogate pair.
+  pub fn edit_utf16(&mut self, range: Range<usize>, text: &str) -> Option<Node> {
+    let start = self.byte_offset(range.start)?;
+    let end = self.byte_offset(range.end)?;
+    self.edit(start..end, text)
+  }
+
+  /// The byte offset of UTF-16 offset `utf16`, or the end of the buffer past it.
+  fn byte_offset(&self, utf16: usize) -> Option<usize> {
+    let mut offset = 0;
+    for (i, ch) in self.buf.char_indices() {
+      if offset >= utf16 {
+        return (offset == utf16).then_some(i);
+      }
+      offset += ch.len_utf16();
+    }
+    (offset <= utf16).then_some(self.buf.len())
+  }
+
+  /// Scans a single token without regard to the grammar.
+  fn match_any_tok(&mut self) -> Option<()> {
+    self.select([
+      |s|s.ws().map(|_|()),
+      |s|s.r_term_literal().map(|_|()),
+      |s|s.match_any_kw().map(|_|()),
+      |s|s.r_term_sym().map(|_|()),
+      |s|s.push_tok(Tok::Op, |s|s.string("->")).map(|_|()),
+      |s|s.match_custom_op().map(|_|()),
+      |s|s.match_binop().map(|_|()),
+      |s|s.match_lpar().map(|_|()),
+      |s|s.match_rpar().map(|_|()),
+      |s|s.push_tok(Tok::LBck, |s|s.char('[')).map(|_|()),
+      |s|s.push_tok(Tok::RBck, |s|s.char(']')).map(|_|()),
+      |s|s.push_tok(Tok::LBrc, |s|s.char('{')).map(|_|()),
+      |s|s.push_tok(Tok::RBrc, |s|s.char('}')).map(|_|()),
+      |s|s.push_tok(Tok::At, |s|s.char('@')).map(|_|()),
+      |s|s.push_tok(Tok::Amp, |s|s.char('&')).map(|_|()),
+      |s|s.match_dollar().map(|_|()),
+      |s|s.push_tok(Tok::Op, |s|s.char('=')).map(|_|()),
+      |s|s.char(',').map(|_|()),
+      |s|s.char(';').map(|_|()),
+      |s|s.char(':').map(|_|()),
+    ])
+  }
+
+  /// Tokenizes the whole buffer, including any input the grammar rejects.
+  ///
+  /// Input past the end of the parse is scanned token by token. The token at
+  /// the furthest position the parser reached is marked `Tok::Err`, as are
+  /// any characters that do not form a token.
+  fn scan_tolerant(&mut self) -> Vec<Token> {
+    self.reparse();
+    let mut toks: Vec<Token> = self.tokens.iter().filter(|t|t.pos < self.pos as u32).copied().collect();
+    toks.sort();
+
+    if self.pos >= self.buf.len() {
+      return toks;
+    }
+
+    let err = self.furthest as u32;
+    self.tokens.truncate(0);
+    while self.pos < self.buf.len() {
+      if self.match_any_tok().is_none() {
+        let pos = self.pos as u32;
+        let len = self.buf[self.pos..].chars().next().map_or(1, char::len_utf8);
+        self.pos += len;
+        match self.tokens.last_mut() {
+          Some(last) if last.tag == Tok::Err && last.pos + last.len as u32 == pos => last.len += len as u16,
+          _ => self.tokens.push(Token::new(Tok::Err, pos, len as u16)),
+        }
+      }
+    }
+
+    let mut marked = false;
+    for tok in self.tokens.iter_mut() {
+      if tok.pos <= err && err < tok.pos + tok.len as u32 && tok.tag != Tok::WS {
+        tok.tag = Tok::Err;
+        marked = true;
+      }
+    }
+    toks.extend(self.tokens.iter().copied());
+    if !marked && err as usize >= self.buf.len() {
+      toks.push(Token::empty(Tok::Err, err));
+    }
+    toks
+  }
+
+  /// Classifies the tokens of the formula for syntax highlighting.
+  ///
+  /// Offsets are in UTF-16 code units, as used by the frontend editor.
+  pub fn highlight(&mut self) -> Vec<TokenUi> {
+    // UTF-16 offsets indexed by byte offset, at char boundaries
+    let mut utf16 = vec![0; self.buf.len() + 1];
+    let mut offset = 0;
+    for (i, ch) in self.buf.char_indices() {
+      utf16[i] = offset;
+      offset += ch.len_utf16() as u32;
+    }
+    utf16[self.buf.len()] = offset;
+
+    let mut depth = 0;
+    let mut legacy_end: Option<u32> = None;
+    let mut res = vec![];
+    for tok in self.scan_tolerant() {
+      let end = tok.pos + tok.len as u32;
+      // names after `@` and `&` are references, `@B3` or `&Units`
+      let in_legacy = legacy_end == Some(tok.pos) && matches!(tok.tag, Tok::Sym | Tok::Num | Tok::Str | Tok::Dollar);
+      legacy_end = if in_legacy || matches!(tok.tag, Tok::At | Tok::Amp) { Some(end) } else { None };
+
+      let typ = match tok.tag {
+        Tok::Nil | Tok::WS => continue,
+        Tok::Err => TokenTypeUi::Error,
+        Tok::Num | Tok::Sym if in_legacy || depth > 0 => TokenTypeUi::Reference,
+        Tok::Str if in_legacy => TokenTypeUi::Reference,
+        Tok::Num => TokenTypeUi::Number,
+        Tok::Sym => TokenTypeUi::Symbol,
+        Tok::Str => TokenTypeUi::String,
+        Tok::KW => TokenTypeUi::Keyword,
+        Tok::Op => TokenTypeUi::Operator,
+        Tok::LPar | Tok::RPar => TokenTypeUi::Punctuation,
+        Tok::LBck | Tok::LBrc => {
+          depth += 1;
+          TokenTypeUi::Reference
+        },
+        Tok::RBck | Tok::RBrc => {
+          depth = depth.max(1) - 1;
+          TokenTypeUi::Reference
+        },
+        Tok::At | Tok::Amp | Tok::Dollar => TokenTypeUi::Reference,
+      };
+      res.push(TokenUi{
+        typ,
+        start: utf16[tok.pos as usize],
+        end: utf16[end as usize],
+      });
+    }
+    res
   }
 }
 
 
-impl ObjectContext for Parser {
+impl ObjectContext for Parser<'_> {
   fn get_value(&self, node: &ValueId) -> &Val {
     &self.values[node.0 as usize]
   }
   fn get_node(&self, node: &NodeId) -> &Node {
     &self.nodes[node.0 as usize]
   }
+  fn get_elems(&self, elems: &ElemRange) -> &[NodeId] {
+    &self.elems[elems.start as usize..(elems.start + elems.len) as usize]
+  }
 }
 
-impl TileContext for Parser {
-  fn get_cell<const CARD: usize, R: CRef<CARD>>(&mut self, _cref: R) ->(CellId, Cell) {
+impl TileContext for Parser<'_> {
+  fn get_range(&mut self, _tile: Option<&Val>, _axes: [Axis; 2]) -> Result<Val, ErrorKind> {
     panic!("not impl!")
   }
 }
 
+impl FuncContext for Parser<'_> {
+  fn get_func(&self, _name: &str) -> Option<Arc<Closure>> {
+    None
+  }
+  fn get_op(&self, symbol: &str) -> Option<String> {
+    self.ops.get(symbol).map(|op|op.func.clone())
+  }
+  fn rounding(&self) -> Rounding {
+    Rounding::default()
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::closure::MAX_DEPTH;
   use rust_decimal_macros::dec;
   use slog::{Drain, Logger, o};
 
@@ -850,6 +1724,12 @@ mod tests {
     p = Parser::new("0");
     assert_eq!(p.scan(), vec_strings!["0"]);
 
+    p = Parser::new("0.05");
+    assert_eq!(p.scan(), vec_strings!["0.05"]);
+
+    p = Parser::new("-0.5");
+    assert_eq!(p.scan(), vec_strings!["-0.5"]);
+
     p = Parser::new("111111");
     assert_eq!(p.scan(), vec_strings!["111111"]);
 
@@ -976,14 +1856,9 @@ mod tests {
     assert!(list_opt.is_some());
     assert_eq!(p.tok_values(), vec_strings!["1","2","3","4","5","6","7","8","9","10","11","12"]);
     let list = list_opt.unwrap();
-    assert!(match list {
-      Node::List { elems: _, len: _, link} => link.is_some(),
-      _ => false,
-    });
     match list {
-      Node::List { elems: _, len: _, link: Some(n)} =>
-        assert!(matches!(p.get_node(&n), _List)),
-      _ => assert!(false),
+      Node::List { elems } => assert_eq!(p.get_elems(&elems).len(), 12),
+      _ => panic!("expected list, got {list:?}"),
     };
 
     let list_val = list.eval(&mut p);
@@ -1004,6 +1879,33 @@ mod tests {
     ]));
   }
 
+  #[test]
+  fn test_parse_eval_long_list() {
+    let n = 5000;
+    let src = (0..n).map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+    let mut p = Parser::new(src);
+    let list = p.parse().unwrap();
+    assert_eq!(p.elems.len(), n);
+    assert!(p.nodes.len() <= n + 1);
+    assert_eq!(p.tokens.len(), 2 * n - 1);
+
+    match list.eval(&mut p) {
+      Val::List(vals) => {
+        assert_eq!(vals.len(), n);
+        assert_eq!(vals[n - 1], Val::Num(Decimal::from(n - 1)));
+      },
+      val => panic!("expected list, got {val:?}"),
+    }
+
+    // nested lists are kept as elements
+    let mut p = Parser::new("(1, 2), 3");
+    let res = p.parse().unwrap().eval(&mut p);
+    assert_eq!(res, Val::List(vec![
+      Val::List(vec![Val::Num(dec!(1)), Val::Num(dec!(2))]),
+      Val::Num(dec!(3)),
+    ]));
+  }
+
   #[test]
   fn test_parse_eval_math() {
     let mut p = Parser::new("3*7*(1+1)/2");
@@ -1014,6 +1916,25 @@ mod tests {
     assert_eq!(res, Val::Num(Decimal::new(21,0)))
   }
 
+  #[test]
+  fn test_parse_eval_precedence() {
+    let mut p = Parser::new("2*3+1");
+    let res = p.parse().unwrap().eval(&mut p);
+    assert_eq!(res, Val::Num(dec!(7)));
+
+    let mut p = Parser::new("1 + 2 * 3");
+    let res = p.parse().unwrap().eval(&mut p);
+    assert_eq!(res, Val::Num(dec!(7)));
+
+    let mut p = Parser::new("1-2-3");
+    let res = p.parse().unwrap().eval(&mut p);
+    assert_eq!(res, Val::Num(dec!(-4)));
+
+    let mut p = Parser::new("8/4/2");
+    let res = p.parse().unwrap().eval(&mut p);
+    assert_eq!(res, Val::Num(dec!(1)));
+  }
+
   #[test]
   fn test_parse_eval_values() {
     let mut p = Parser::new("1,2,3");
@@ -1028,6 +1949,364 @@ mod tests {
     ]))
   }
 
+  #[test]
+  fn test_parse_eval_let() {
+    let eval = |src: &str| {
+      let mut p = Parser::new(src);
+      let node = p.parse().unwrap();
+      node.eval(&mut p)
+    };
+
+    assert_eq!(eval("let x = 2, y = x * 3 in x + y"), Val::Num(dec!(8)));
+    assert_eq!(eval("let x = 1 in (let x = 2 in x) + x"), Val::Num(dec!(3)));
+    assert_eq!(eval("2 * let x = 3 in x + 1"), Val::Num(dec!(8)));
+    // bindings are not visible outside the body
+    assert_eq!(eval("(let x = 5 in x), x"), Val::List(vec![Val::Num(dec!(5)), Val::Str("x".to_owned())]));
+
+    let mut p = Parser::new("let x = 1 in x");
+    assert!(p.parse().is_some());
+    assert_eq!(p.tok_values(), vec_strings!["let", " ", "x", " ", "=", " ", "1", " ", "in", " ", "x"]);
+
+    // keywords must be whole words
+    let mut p = Parser::new("letter");
+    assert!(matches!(p.parse(), Some(Node::Sym { .. })));
+    // and aren't names, so a form that doesn't parse fails instead of
+    // leaving its keyword as a symbol
+    let forms = ["let", "let x = 1 in -x", "let in = 1 in 2", "in", "def", "def f(x) = x", "not", "if 1 2", "else", "match", "match 1 as", "as", "f(if)", "x -> else"];
+    for src in forms {
+      assert!(Parser::new(src).parse().is_none(), "{src}");
+    }
+    for kw in KEYWORDS {
+      assert!(!matches!(Parser::new(kw).parse(), Some(Node::Sym { .. })), "{kw}");
+    }
+
+    // `in` ends a binding unless parenthesized
+    assert_eq!(eval("let f = x -> x in f(2) in (1, 2)"), Val::Bool(true));
+    assert_eq!(eval("let b = (2 in (1, 2)), s = 'ab' in b, s in 'cab'"), Val::List(vec![Val::Bool(true), Val::Bool(true)]));
+  }
+
+  #[test]
+  fn test_parse_word_ops() {
+    let mut p = Parser::new("a union b inter c in d");
+    let node = p.parse().unwrap();
+    assert_eq!(p.tok_values(), vec_strings!["a", " ", "union", " ", "b", " ", "inter", " ", "c", " ", "in", " ", "d"]);
+    assert_eq!(node.print(&p), "a union b inter c in d");
+    // word operators must end the word, leaving two names that don't parse
+    for src in ["a unions", "a inside", "a interb"] {
+      assert!(Parser::new(src).parse().is_none(), "{src}");
+    }
+    assert!(Parser::new("a ++ b ** 2").parse().is_some());
+  }
+
+  #[test]
+  fn test_parse_right_grouped() {
+    for (src, expected) in [
+      ("1 - 2 - 3", "1 - (2 - 3)"),
+      ("8 / 4 / 2", "8 / (4 / 2)"),
+      ("2 * 3 + 4", "2 * (3 + 4)"),
+      ("2 + 3 * 4", "2 + 3 * 4"),
+      ("(1 - 2) - 3", "1 - 2 - 3"),
+      ("sum(1 - 2 - 3, 4)", "sum(1 - (2 - 3), 4)"),
+    ] {
+      let mut p = Parser::new(src).right_grouped();
+      assert_eq!(p.parse().unwrap().print(&p), expected, "{src}");
+    }
+    assert!(Parser::new("1 -").right_grouped().parse().is_none());
+  }
+
+  #[test]
+  fn test_parse_keyword_forms() {
+    // each form parses in full and prints as written
+    let forms = [
+      "let x = 1 in x",
+      "not x",
+      "if x 1 else 2",
+      "if x 1 else if y 2 else 3",
+      "match x as 1 'one' as 2 'two' else 'many'",
+      "match x as 1 'one'",
+      "x -> x + 1",
+      "(x, y) -> x in y",
+    ];
+    for src in forms {
+      let mut p = Parser::new(src);
+      let node = p.parse().unwrap_or_else(|| panic!("{src}"));
+      assert_eq!(src, node.print(&p));
+    }
+    // or, missing a part, fails as a whole rather than giving a prefix
+    let broken = [
+      "let x = 1",
+      "let x in x",
+      "let = 1 in 2",
+      "not",
+      "if x",
+      "if x 1 else",
+      "if x else 2",
+      "match x",
+      "match x as 1",
+      "match x else 1",
+      "x ->",
+      "(x, 1) -> x",
+    ];
+    for src in broken {
+      assert!(Parser::new(src).parse().is_none(), "{src}");
+    }
+  }
+
+  #[test]
+  fn test_parse_eval_lambda() {
+    let eval = |src: &str| {
+      let mut p = Parser::new(src);
+      let node = p.parse().unwrap();
+      node.eval(&mut p)
+    };
+    let nums = |ns: &[i64]| Val::List(ns.iter().map(|&n| Val::Num(Decimal::from(n))).collect());
+
+    assert_eq!(eval("map((1, 2, 3), r -> r * 2)"), nums(&[2, 4, 6]));
+    assert_eq!(eval("filter((0, 1, 0, 2), x -> x)"), nums(&[1, 2]));
+    // the predicate is a condition as for `if`, and its errors are passed on
+    assert_eq!(eval("filter((1, 2, 3), x -> x != 2)"), nums(&[1, 3]));
+    assert_eq!(eval("filter((1, 0, 2), x -> 1 / x)"), Val::error(ErrorKind::Div0, "1 / x"));
+    assert_eq!(eval("filter((1, 2), x -> 'a')"), Val::error(ErrorKind::Type, "filter"));
+    assert_eq!(eval("reduce((1, 2, 3), 0, (acc, x) -> acc + x)"), Val::Num(dec!(6)));
+    assert_eq!(eval("let f = x -> x + 1 in f(2)"), Val::Num(dec!(3)));
+
+    // closures capture the scope they are created in
+    assert_eq!(eval("let k = 3 in map((1, 2), x -> x * k)"), nums(&[3, 6]));
+    assert_eq!(eval("let f = (let k = 2 in x -> x * k), k = 10 in f(5)"), Val::Num(dec!(10)));
+    assert_eq!(eval("let add = x -> y -> x + y, inc = add(1) in inc(5)"), Val::Num(dec!(6)));
+
+    assert_eq!(eval("(acc, x) -> acc + x").to_string(), "(acc, x) -> acc + x");
+
+    // calls nested past the limit give an error rather than overflow the stack
+    assert_eq!(eval("let y = g -> g(g) in y(y)"), Val::error(ErrorKind::Num, "g -> g(g)"));
+    assert_eq!(eval("let y = g -> reduce(Array(1), 0, (acc, x) -> g(g)) in y(y)"), Val::error(ErrorKind::Num, "g -> reduce(Array(1), 0, (acc, x) -> g(g))"));
+    let down = "let down = (f, n) -> if n < 1 0 else f(f, n - 1) in down(down, ";
+    assert_eq!(eval(&format!("{down}{})", MAX_DEPTH - 1)), Val::Num(dec!(0)));
+    assert_eq!(eval(&format!("{down}{})", MAX_DEPTH)), Val::error(ErrorKind::Num, "(f, n) -> if n < 1 0 else f(f, n - 1)"));
+  }
+
+  #[test]
+  fn test_parse_def() {
+    let mut p = Parser::new("def noi(rent, opex) = rent - opex");
+    let (name, node) = p.parse_def().unwrap();
+    assert_eq!(name, "noi");
+    assert_eq!(node.eval(&mut p).to_string(), "(rent, opex) -> rent - opex");
+
+    let mut p = Parser::new("def one() = 1");
+    assert_eq!(p.parse_def().unwrap().1.eval(&mut p).to_string(), "() -> 1");
+
+    assert!(Parser::new("def noi(rent) = rent )").parse_def().is_none());
+    assert!(Parser::new("def noi rent = rent").parse_def().is_none());
+    assert!(Parser::new("noi(rent) = rent").parse_def().is_none());
+  }
+
+  #[test]
+  fn test_parse_eval_constructors() {
+    let eval = |src: &str| {
+      let mut p = Parser::new(src);
+      let node = p.parse().unwrap();
+      node.eval(&mut p)
+    };
+
+    assert_eq!(eval("List()"), Val::List(vec![]));
+    assert_eq!(eval("len(Array())"), Val::Int(0));
+    assert_eq!(eval("OrderedMap(b:5; a:10)").to_string(), "b:5,a:10");
+    assert_eq!(eval("len(SparseArray.fill(0, (100,300)))"), Val::Int(30000));
+
+    // lists of fields are records, keys are not looked up
+    let record = eval("name: 'Daniel', id: 17");
+    assert_eq!(record, Val::Record{
+      value: vec![Val::Str("name".to_owned()), Val::Str("Daniel".to_owned()), Val::Str("id".to_owned()), Val::Num(dec!(17))],
+      fields: 2,
+    });
+    assert_eq!(eval("let b = 1 in OrderedMap(b: b + 1)").to_string(), "b:2");
+  }
+
+  #[test]
+  fn test_parse_eval_call() {
+    let mut p = Parser::new("sum(1, 2, 3) * 2");
+    let res = p.parse().unwrap().eval(&mut p);
+    assert_eq!(res, Val::Num(dec!(12)));
+
+    let mut p = Parser::new("max((1,7,3)) + len((1,2))");
+    let res = p.parse().unwrap().eval(&mut p);
+    assert_eq!(res, Val::Num(dec!(9)));
+  }
+
+  #[test]
+  fn test_parser_edit() {
+    let mut p = Parser::new("(1+2)*(3+4)");
+    assert!(p.parse().is_some());
+    let base = p.nodes.len();
+
+    // "(1+2)*(3+40)"
+    let node = p.edit(9..10, "40").unwrap();
+    assert_eq!(p.tok_values(), Parser::new("(1+2)*(3+40)").scan());
+    assert_eq!(node.eval(&mut p), Val::Num(dec!(129)));
+
+    // the untouched left operand shares nodes with the previous parse
+    let lhs = match node {
+      Node::BinOp { op: '*', lhs, rhs: _ } => *p.get_node(&lhs),
+      _ => panic!("expected binop, got {node:?}"),
+    };
+    assert!(matches!(lhs, Node::BinOp { op: '+', lhs, rhs } if lhs.0 < base as u32 && rhs.0 < base as u32));
+
+    // terms after the edit are shifted
+    let node = p.edit(0..0, "10+").unwrap();
+    assert_eq!(p.tok_values(), Parser::new("10+(1+2)*(3+40)").scan());
+    assert_eq!(node.eval(&mut p), Val::Num(dec!(139)));
+
+    // a term touching the edit is reparsed rather than reused
+    let mut p = Parser::new("12+3");
+    assert!(p.parse().is_some());
+    let node = p.edit(2..2, "5").unwrap();
+    assert_eq!(p.tok_values(), vec_strings!["125", "+", "3"]);
+    assert_eq!(node.eval(&mut p), Val::Num(dec!(128)));
+  }
+
+  #[test]
+  fn test_parser_memo() {
+    // "1" is the seed the list grows from, "2" is left over so the parse fails
+    let mut p = Parser::new("1 2");
+    assert!(p.parse().is_none());
+
+    let mut p = Parser::new("1, 2 * 3, 4");
+    let res = p.parse().unwrap().eval(&mut p);
+    assert_eq!(res, Val::List(vec![1, 6, 4].into_iter().map(|i| Val::Num(Decimal::from(i))).collect()));
+
+    // each term is parsed once, however deeply nested
+    let depth = 200;
+    let mut p = Parser::new(format!("{}1{}", "sum(".repeat(depth), ")".repeat(depth)));
+    assert_eq!(p.parse().unwrap().eval(&mut p), Val::Num(dec!(1)));
+    assert!(p.nodes.len() < 4 * depth);
+    let mut p = Parser::new(format!("{}1", "sum(".repeat(depth)));
+    p.parse();
+    assert!(p.nodes.len() < 4 * depth);
+  }
+
+  #[test]
+  fn test_parser_edit_lookahead() {
+    // `sum` was matched as a symbol because no `(` followed it
+    let mut p = Parser::new("sum(1");
+    assert!(p.parse().is_none());
+    let node = p.edit(5..5, ")").unwrap();
+    assert!(matches!(node, Node::Call { .. }));
+    assert_eq!(node.eval(&mut p), Val::Num(dec!(1)));
+  }
+
+  #[test]
+  fn test_parser_unicode() {
+    let mut p = Parser::new("'naïve' + '日本'");
+    assert!(p.parse().is_some());
+    assert_eq!(p.tok_values(), vec_strings!["'naïve'", " ", "+", " ", "'日本'"]);
+    assert_eq!(p.tokens[4].pos, 11);
+
+    // edit offsets are in bytes
+    let node = p.edit(12..15, "").unwrap();
+    assert_eq!(p.tok_values(), vec_strings!["'naïve'", " ", "+", " ", "'本'"]);
+    assert_eq!(p.get_value(match &node {
+      Node::BinOp { rhs, .. } => match p.get_node(rhs) {
+        Node::Leaf { value } => value,
+        n => panic!("expected leaf, got {n:?}"),
+      },
+      n => panic!("expected binop, got {n:?}"),
+    }), &Val::Str("本".to_owned()));
+
+    // a backslash escapes the quote and is dropped from the value
+    let mut p = Parser::new("'a:\\' b:\"' ++ '\\\\'");
+    let node = p.parse().unwrap();
+    assert_eq!(p.tok_values(), vec_strings!["'a:\\' b:\"'", " ", "++", " ", "'\\\\'"]);
+    assert_eq!(node.eval(&mut p), Val::Str("a:' b:\"\\".to_owned()));
+
+    // offsets inside a char are rejected rather than splitting it
+    let mut p = Parser::new("∞ + 1");
+    assert!(p.parse().is_some());
+    assert!(p.edit(1..2, "").is_none());
+    assert_eq!(p.tok_values(), vec_strings!["∞", " ", "+", " ", "1"]);
+    // the frontend counts UTF-16 code units, where `∞` is one
+    assert!(p.edit_utf16(4..5, "2").is_some());
+    assert_eq!(p.tok_values(), vec_strings!["∞", " ", "+", " ", "2"]);
+    let mut p = Parser::new("'😀' ++ 'a'");
+    assert!(p.parse().is_some());
+    assert!(p.edit_utf16(2..2, "b").is_none());
+    assert!(p.edit_utf16(3..3, "b").is_some());
+    assert_eq!(p.tok_values(), vec_strings!["'😀b'", " ", "++", " ", "'a'"]);
+
+    let toks = Parser::new("1 + é").highlight();
+    let last = toks.last().unwrap();
+    assert_eq!((last.typ, last.start, last.end), (TokenTypeUi::Error, 4, 5));
+  }
+
+  #[test]
+  fn test_parser_edit_typing() {
+    let formula = "(1 + 2) * [0, 1] + 3, 'abc', {A, 2}";
+    let mut p = Parser::new("");
+    p.parse();
+    for (i, ch) in formula.char_indices() {
+      p.edit(i..i, &ch.to_string());
+    }
+    // delete and retype the middle of the formula
+    p.edit(4..7, "");
+    let node = p.edit(4..4, " 2)").unwrap();
+
+    let mut fresh = Parser::new(formula);
+    let expected = fresh.parse().unwrap();
+    assert_eq!(p.tok_values(), fresh.tok_values());
+    assert_eq!(node.print(&p), expected.print(&fresh));
+    assert!(p.arena_len() <= REPARSE_FACTOR * p.full_len.max(REPARSE_MIN_LEN) + fresh.arena_len());
+  }
+
+  #[test]
+  fn test_parser_highlight() {
+    use TokenTypeUi::*;
+    fn kinds(src: &str) -> Vec<(TokenTypeUi, u32, u32)> {
+      Parser::new(src).highlight().iter().map(|t|(t.typ, t.start, t.end)).collect()
+    }
+
+    assert_eq!(kinds("1 + 'a' * x"), vec![
+      (Number, 0, 1), (Operator, 2, 3), (String, 4, 7), (Operator, 8, 9), (Symbol, 10, 11),
+    ]);
+    assert_eq!(kinds("(true)"), vec![
+      (Punctuation, 0, 1), (Keyword, 1, 5), (Punctuation, 5, 6),
+    ]);
+    assert_eq!(kinds("{a, $B} + @C2"), vec![
+      (Reference, 0, 1), (Reference, 1, 2), (Reference, 4, 5), (Reference, 5, 6), (Reference, 6, 7),
+      (Operator, 8, 9),
+      (Reference, 10, 11), (Reference, 11, 12), (Reference, 12, 13),
+    ]);
+    assert_eq!(kinds("&'Rent Roll'[2:, 1] * &Units{B, 3}"), vec![
+      (Reference, 0, 1), (Reference, 1, 12), (Reference, 12, 13), (Reference, 13, 14), (Reference, 17, 18), (Reference, 18, 19),
+      (Operator, 20, 21),
+      (Reference, 22, 23), (Reference, 23, 28), (Reference, 28, 29), (Reference, 29, 30), (Reference, 32, 33), (Reference, 33, 34),
+    ]);
+
+    // offsets count UTF-16 code units
+    assert_eq!(kinds("'😀' + 1"), vec![
+      (String, 0, 4), (Operator, 5, 6), (Number, 7, 8),
+    ]);
+  }
+
+  #[test]
+  fn test_parser_highlight_errors() {
+    use TokenTypeUi::*;
+    fn kinds(src: &str) -> Vec<(TokenTypeUi, u32, u32)> {
+      Parser::new(src).highlight().iter().map(|t|(t.typ, t.start, t.end)).collect()
+    }
+
+    // tokens after the error are still classified
+    assert_eq!(kinds("1 + + 2"), vec![
+      (Number, 0, 1), (Operator, 2, 3), (Error, 4, 5), (Number, 6, 7),
+    ]);
+    assert_eq!(kinds("1 + #% 2"), vec![
+      (Number, 0, 1), (Operator, 2, 3), (Error, 4, 6), (Number, 7, 8),
+    ]);
+    // unexpected end of input
+    assert_eq!(kinds("(1+2"), vec![
+      (Punctuation, 0, 1), (Number, 1, 2), (Operator, 2, 3), (Number, 3, 4), (Error, 4, 4),
+    ]);
+    assert_eq!(kinds(""), vec![]);
+  }
+
   #[test]
   fn test_util_rule_key() {
     assert_eq!(RuleKey(0), rule_key("asdf"))
@@ -0,0 +1,492 @@
+use std::fmt::Write;
+
+use crate::cell::Val;
+use crate::eval::{Node, ObjectContext};
+use crate::parser::{binop_prec, binop_symbol, Assoc, NodeId, NOT_PREC};
+
+/// Precedence of list construction and `let`, looser than any binary operator.
+const LIST_PREC: u8 = 0;
+/// Precedence of lambdas: list elements, but not binary operands.
+const LAMBDA_PREC: u8 = 1;
+/// Precedence of terms, tighter than any binary operator.
+const TERM_PREC: u8 = u8::MAX;
+
+/// A string literal of `s`, in single quotes unless only double quotes
+/// avoid escaping. Backslashes and the chosen quote are escaped.
+pub fn quote(s: &str) -> String {
+  let quote = if s.contains('\'') && !s.contains('"') { '"' } else { '\'' };
+  let mut out = String::from(quote);
+  for ch in s.chars() {
+    if ch == quote || ch == '\\' {
+      out.push('\\');
+    }
+    out.push(ch);
+  }
+  out.push(quote);
+  out
+}
+
+/// Prints formulas in canonical form.
+///
+/// Spacing is normalized and parentheses are only emitted where the
+/// precedence of the parser requires them, so parsing the output yields
+/// the same AST as the original formula.
+pub struct Printer<'a, C: ObjectContext> {
+  ctx: &'a C,
+  out: String,
+  /// Whether `in` would end the expression, in let bindings.
+  no_in: bool,
+}
+
+impl<'a, C: ObjectContext> Printer<'a, C> {
+  pub fn new(ctx: &'a C) -> Printer<'a, C> {
+    Printer {
+      ctx,
+      out: String::new(),
+      no_in: false,
+    }
+  }
+
+  pub fn print(mut self, node: &Node) -> String {
+    self.node(node);
+    self.out
+  }
+
+  fn prec(&self, node: &Node) -> u8 {
+    match node {
+      Node::BinOp { op, .. } => binop_prec(*op).map_or(TERM_PREC, |p| p + LAMBDA_PREC),
+      Node::CustomOp { prec, .. } => prec + LAMBDA_PREC,
+      Node::List { .. } | Node::Let { .. } => LIST_PREC,
+      // `not` takes in any operator binding tighter than `and`
+      Node::UniOp { op: '¬', .. } => NOT_PREC - 1 + LAMBDA_PREC,
+      // the last branch takes in any operators that follow
+      Node::Lambda { .. } | Node::Field { .. } | Node::Slice { .. } | Node::If { .. } | Node::Match { .. } => LAMBDA_PREC,
+      _ => TERM_PREC,
+    }
+  }
+
+  /// Prints `node`, wrapped in parens when it binds looser than `min_prec`,
+  /// or is a membership test where `in` would end a let binding.
+  fn operand(&mut self, id: &NodeId, min_prec: u8) {
+    let node = *self.ctx.get_node(id);
+    if self.prec(&node) < min_prec || self.no_in && matches!(node, Node::BinOp{op: '∈', ..}) {
+      let no_in = std::mem::replace(&mut self.no_in, false);
+      self.out.push('(');
+      self.node(&node);
+      self.out.push(')');
+      self.no_in = no_in;
+    } else {
+      self.node(&node);
+    }
+  }
+
+  /// Prints a branch of `if` or `match`, which follows another expression
+  /// without a separator. One starting with `-` is parenthesized so it isn't
+  /// read as a subtraction.
+  fn branch(&mut self, id: &NodeId) {
+    let node = *self.ctx.get_node(id);
+    let start = self.out.len();
+    self.operand(id, LAMBDA_PREC + 1);
+    if self.out[start..].starts_with('-') {
+      self.out.truncate(start);
+      let no_in = std::mem::replace(&mut self.no_in, false);
+      self.out.push('(');
+      self.node(&node);
+      self.out.push(')');
+      self.no_in = no_in;
+    }
+  }
+
+  fn value(&mut self, value: &Val) {
+    match value {
+      Val::Str(s) => self.out.push_str(&quote(s)),
+      v => self.out.push_str(&v.to_string()),
+    }
+  }
+
+  fn list_elems(&self, node: &Node) -> Vec<NodeId> {
+    match node {
+      Node::List { elems } => self.ctx.get_elems(elems).to_vec(),
+      _ => vec![],
+    }
+  }
+
+  fn compound(&mut self, open: char, close: char, row: &NodeId, col: &NodeId) {
+    self.out.push(open);
+    self.operand(row, LAMBDA_PREC);
+    if *col != NodeId(0) {
+      self.out.push_str(", ");
+      self.operand(col, LAMBDA_PREC);
+    }
+    self.out.push(close);
+  }
+
+  fn node(&mut self, node: &Node) {
+    match node {
+      Node::Zero {} => (),
+      Node::Leaf { value } => {
+        let value = self.ctx.get_value(value).clone();
+        self.value(&value);
+      },
+      Node::Sym { value } => {
+        let value = self.ctx.get_value(value).to_string();
+        self.out.push_str(&value);
+      },
+      Node::BinOp { op, lhs, rhs } => {
+        let prec = self.prec(node);
+        let (lprec, rprec) = match (op, self.ctx.get_node(rhs)) {
+          ('^', _) => (prec + 1, prec),
+          // operators following a right operand `not` bind looser than its operand
+          (_, Node::UniOp { op: '¬', .. }) if prec < NOT_PREC + LAMBDA_PREC => (prec, prec),
+          _ => (prec, prec + 1),
+        };
+        self.operand(lhs, lprec);
+        write!(self.out, " {} ", binop_symbol(*op)).unwrap();
+        self.operand(rhs, rprec);
+      },
+      Node::CustomOp { op, assoc, lhs, rhs, .. } => {
+        let prec = self.prec(node);
+        let (lprec, rprec) = match assoc {
+          Assoc::Left => (prec, prec + 1),
+          Assoc::Right => (prec + 1, prec),
+        };
+        self.operand(lhs, lprec);
+        write!(self.out, " {} ", self.ctx.get_value(op).to_string()).unwrap();
+        self.operand(rhs, rprec);
+      },
+      Node::UniOp { op: '¬', rhs } => {
+        self.out.push_str("not ");
+        self.operand(rhs, NOT_PREC + LAMBDA_PREC);
+      },
+      Node::UniOp { op, rhs } => {
+        self.out.push(*op);
+        self.operand(rhs, TERM_PREC);
+      },
+      Node::Call { func, args } => {
+        self.operand(func, TERM_PREC);
+        self.out.push('(');
+        let args = *self.ctx.get_node(args);
+        self.node(&args);
+        self.out.push(')');
+      },
+      Node::Index { row, col } => self.compound('[', ']', row, col),
+      Node::Addr { row, col } => self.compound('{', '}', row, col),
+      Node::Slice { start, end } => {
+        if *start != NodeId(0) {
+          self.operand(start, LAMBDA_PREC + 1);
+        }
+        self.out.push(':');
+        if *end != NodeId(0) {
+          self.operand(end, LAMBDA_PREC + 1);
+        }
+      },
+      Node::TileRef { tile, cell } => {
+        self.out.push('&');
+        self.operand(tile, TERM_PREC);
+        self.operand(cell, TERM_PREC);
+      },
+      Node::List { .. } => {
+        for (i, elem) in self.list_elems(node).iter().enumerate() {
+          if i > 0 {
+            self.out.push_str(", ");
+          }
+          self.operand(elem, LAMBDA_PREC);
+        }
+      },
+      Node::Let { binds, body } => {
+        self.out.push_str("let ");
+        let binds = self.ctx.get_elems(binds).to_vec();
+        for (i, pair) in binds.chunks_exact(2).enumerate() {
+          if i > 0 {
+            self.out.push_str(", ");
+          }
+          self.operand(&pair[0], TERM_PREC);
+          self.out.push_str(" = ");
+          let no_in = std::mem::replace(&mut self.no_in, true);
+          self.operand(&pair[1], LAMBDA_PREC);
+          self.no_in = no_in;
+        }
+        self.out.push_str(" in ");
+        self.operand(body, LIST_PREC);
+      },
+      Node::Field { key, value } => {
+        self.operand(key, TERM_PREC);
+        self.out.push_str(": ");
+        self.operand(value, LAMBDA_PREC);
+      },
+      Node::If { cond, then, other } => {
+        self.out.push_str("if ");
+        self.operand(cond, LAMBDA_PREC + 1);
+        self.out.push(' ');
+        self.branch(then);
+        self.out.push_str(" else ");
+        self.operand(other, LAMBDA_PREC);
+      },
+      Node::Match { subject, arms, other } => {
+        self.out.push_str("match ");
+        self.operand(subject, LAMBDA_PREC + 1);
+        let arms = self.ctx.get_elems(arms).to_vec();
+        for pair in arms.chunks_exact(2) {
+          self.out.push_str(" as ");
+          self.operand(&pair[0], TERM_PREC);
+          self.out.push(' ');
+          self.branch(&pair[1]);
+        }
+        if *other != NodeId(0) {
+          self.out.push_str(" else ");
+          self.operand(other, LAMBDA_PREC);
+        }
+      },
+      Node::Lambda { params, body } => {
+        let params = self.ctx.get_elems(params).to_vec();
+        if params.len() != 1 {
+          self.out.push('(');
+        }
+        for (i, param) in params.iter().enumerate() {
+          if i > 0 {
+            self.out.push_str(", ");
+          }
+          self.operand(param, TERM_PREC);
+        }
+        if params.len() != 1 {
+          self.out.push(')');
+        }
+        self.out.push_str(" -> ");
+        self.operand(body, LAMBDA_PREC);
+      },
+    }
+  }
+}
+
+impl Node {
+  /// Prints the formula rooted at this node in canonical form.
+  pub fn print(&self, ctx: &impl ObjectContext) -> String {
+    Printer::new(ctx).print(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::funcs::{Funcs, Ops};
+  use crate::parser::Parser;
+
+  /// Renders an AST as an s-expression, resolving node and value ids.
+  fn sexpr(ctx: &impl ObjectContext, node: &Node) -> String {
+    let sub = |id: &NodeId| sexpr(ctx, ctx.get_node(id));
+    match node {
+      Node::Zero {} => "()".to_owned(),
+      Node::Leaf { value } => format!("{:?}", ctx.get_value(value)),
+      Node::Sym { value } => format!("(sym {:?})", ctx.get_value(value)),
+      Node::BinOp { op, lhs, rhs } => format!("({op} {} {})", sub(lhs), sub(rhs)),
+      Node::CustomOp { op, lhs, rhs, .. } => format!("({} {} {})", ctx.get_value(op).to_string(), sub(lhs), sub(rhs)),
+      Node::UniOp { op, rhs } => format!("({op} {})", sub(rhs)),
+      Node::Index { row, col } => format!("(index {} {})", sub(row), sub(col)),
+      Node::Addr { row, col } => format!("(addr {} {})", sub(row), sub(col)),
+      Node::Call { func, args } => format!("(call {} {})", sub(func), sub(args)),
+      Node::List { .. } => {
+        let elems: Vec<String> = Printer::new(ctx).list_elems(node).iter().map(sub).collect();
+        format!("(list {})", elems.join(" "))
+      },
+      Node::Let { binds, body } => {
+        let binds: Vec<String> = ctx.get_elems(binds).iter().map(sub).collect();
+        format!("(let ({}) {})", binds.join(" "), sub(body))
+      },
+      Node::Lambda { params, body } => {
+        let params: Vec<String> = ctx.get_elems(params).iter().map(sub).collect();
+        format!("(lambda ({}) {})", params.join(" "), sub(body))
+      },
+      Node::Field { key, value } => format!("(field {} {})", sub(key), sub(value)),
+      Node::Slice { start, end } => format!("(slice {} {})", sub(start), sub(end)),
+      Node::TileRef { tile, cell } => format!("(tile {} {})", sub(tile), sub(cell)),
+      Node::If { cond, then, other } => format!("(if {} {} {})", sub(cond), sub(then), sub(other)),
+      Node::Match { subject, arms, other } => {
+        let arms: Vec<String> = ctx.get_elems(arms).iter().map(sub).collect();
+        format!("(match {} ({}) {})", sub(subject), arms.join(" "), sub(other))
+      },
+    }
+  }
+
+  fn round_trip(src: &str) -> String {
+    round_trip_ops(src, &Ops::new())
+  }
+
+  fn round_trip_ops(src: &str, ops: &Ops) -> String {
+    let mut p1 = Parser::new(src).with_ops(ops);
+    let ast1 = p1.parse().unwrap();
+    let printed = ast1.print(&p1);
+
+    let mut p2 = Parser::new(printed.clone()).with_ops(ops);
+    let ast2 = p2.parse().unwrap();
+    assert_eq!(sexpr(&p1, &ast1), sexpr(&p2, &ast2), "{src} => {printed}");
+    assert_eq!(printed, ast2.print(&p2));
+    printed
+  }
+
+  #[test]
+  fn test_print_spacing() {
+    assert_eq!(round_trip("1+2"), "1 + 2");
+    assert_eq!(round_trip("  1   *2 "), "1 * 2");
+    assert_eq!(round_trip("1,2,3"), "1, 2, 3");
+    assert_eq!(round_trip("[1,2]"), "[1, 2]");
+    assert_eq!(round_trip("[1]"), "[1]");
+    assert_eq!(round_trip("{a,Z}"), "{a, Z}");
+  }
+
+  #[test]
+  fn test_print_parens() {
+    assert_eq!(round_trip("(1+2)"), "1 + 2");
+    assert_eq!(round_trip("1+(2*3)"), "1 + 2 * 3");
+    assert_eq!(round_trip("(1+2)*3"), "(1 + 2) * 3");
+    assert_eq!(round_trip("1-(2-3)"), "1 - (2 - 3)");
+    assert_eq!(round_trip("(1-2)-3"), "1 - 2 - 3");
+    assert_eq!(round_trip("3*7*(1+1)/2"), "3 * 7 * (1 + 1) / 2");
+    assert_eq!(round_trip("1,2,(3,4,5)"), "1, 2, (3, 4, 5)");
+    assert_eq!(round_trip("(1,2)*3"), "(1, 2) * 3");
+    assert_eq!(round_trip("1 --1"), "1 - -1");
+    assert_eq!(round_trip("(1,2,3)**(4,5,6)*2"), "(1, 2, 3) ** (4, 5, 6) * 2");
+    assert_eq!(round_trip("a**(b+c)"), "a ** (b + c)");
+    assert_eq!(round_trip("2^3^2"), "2 ^ 3 ^ 2");
+    assert_eq!(round_trip("(2^3)^2"), "(2 ^ 3) ^ 2");
+    assert_eq!(round_trip("3+4*i^2"), "3 + 4 * i ^ 2");
+  }
+
+  #[test]
+  fn test_print_refs() {
+    assert_eq!(round_trip("[$0, $2]"), "[$0, $2]");
+    assert_eq!(round_trip("{Tomatoes,$Cost}"), "{Tomatoes, $Cost}");
+    assert_eq!(round_trip("@A1"), "{1, A}");
+    assert_eq!(round_trip("@$A$1"), "{$1, $A}");
+    assert_eq!(round_trip("[0,1]*{Tomatoes,$Cost}+@B2"), "[0, 1] * {Tomatoes, $Cost} + {2, B}");
+  }
+
+  #[test]
+  fn test_print_slices() {
+    assert_eq!(round_trip("[0, 1 : 3]"), "[0, 1:3]");
+    assert_eq!(round_trip("[:,:2]"), "[:, :2]");
+    assert_eq!(round_trip("[x+1:]"), "[x + 1:]");
+    assert_eq!(round_trip("[$0, $0:$5]"), "[$0, $0:$5]");
+    assert_eq!(round_trip("{[1:3], Price}"), "{[1:3], Price}");
+    assert_eq!(round_trip("sum([0:3, 0:2])"), "sum([0:3, 0:2])");
+  }
+
+  #[test]
+  fn test_print_tile_refs() {
+    assert_eq!(round_trip("&Sheet2[1,3]"), "&Sheet2[1, 3]");
+    assert_eq!(round_trip("&'Rent Roll'[2, 2]*12"), "&'Rent Roll'[2, 2] * 12");
+    assert_eq!(round_trip("&$2{B,3}"), "&$2{B, 3}");
+    assert_eq!(round_trip("&Units{[:],Rent}*&Units{[:],Occupancy}"), "&Units{[:], Rent} * &Units{[:], Occupancy}");
+  }
+
+  #[test]
+  fn test_print_quoting() {
+    assert_eq!(round_trip("\"hello\""), "'hello'");
+    assert_eq!(round_trip("\"it's\""), "\"it's\"");
+    // a string with both quotes escapes the one it's printed in
+    assert_eq!(round_trip("'a:\\' b:\"'"), "'a:\\' b:\"'");
+    assert_eq!(round_trip("\"a:' b:\\\"\""), "'a:\\' b:\"'");
+    assert_eq!(round_trip("'back\\\\slash'"), "'back\\\\slash'");
+    assert_eq!(round_trip("'a'+b"), "'a' + b");
+    assert_eq!(round_trip("true,false"), "true, false");
+  }
+
+  #[test]
+  fn test_print_calls() {
+    assert_eq!(round_trip("sum( 1,2 ,3 )"), "sum(1, 2, 3)");
+    assert_eq!(round_trip("sum()"), "sum()");
+    assert_eq!(round_trip("max((1,2), abs(-3)*2)"), "max((1, 2), abs(-3) * 2)");
+    assert_eq!(round_trip("OrderedMap(b:5; a:10)"), "OrderedMap(b: 5, a: 10)");
+    assert_eq!(round_trip("SparseArray.fill(0,(100,300))"), "SparseArray.fill(0, (100, 300))");
+    assert_eq!(round_trip("name:'Daniel',id : 17"), "name: 'Daniel', id: 17");
+    assert_eq!(round_trip("(a: 1) + 2"), "(a: 1) + 2");
+  }
+
+  #[test]
+  fn test_print_let() {
+    assert_eq!(round_trip("let x=1,y = x*2 in x+y"), "let x = 1, y = x * 2 in x + y");
+    assert_eq!(round_trip("1 + (let x = 2 in x)"), "1 + (let x = 2 in x)");
+    assert_eq!(round_trip("let x = (let y = 1 in y) in x, 2"), "let x = (let y = 1 in y) in x, 2");
+    assert_eq!(round_trip("(let x = 1 in x), 2"), "(let x = 1 in x), 2");
+  }
+
+  #[test]
+  fn test_print_collection_ops() {
+    assert_eq!(round_trip("a++b union c"), "a ++ b union c");
+    assert_eq!(round_trip("(a+b)++c inter d"), "a + b ++ c inter d");
+    assert_eq!(round_trip("a ++ (b in c)"), "a ++ (b in c)");
+    assert_eq!(round_trip("(x in a) ++ b"), "(x in a) ++ b");
+    assert_eq!(round_trip("'ab' ** 2 in s"), "'ab' ** 2 in s");
+    assert_eq!(round_trip("let b = (x in xs), n = 1 in b in c"), "let b = (x in xs), n = 1 in b in c");
+    assert_eq!(round_trip("let f = y -> (y in xs) in f"), "let f = y -> (y in xs) in f");
+  }
+
+  #[test]
+  fn test_print_comparisons() {
+    assert_eq!(round_trip("a<=b"), "a <= b");
+    assert_eq!(round_trip("a + 1!=b"), "a + 1 != b");
+    assert_eq!(round_trip("(a < b) == c"), "a < b == c");
+    assert_eq!(round_trip("a == (b < c)"), "a == (b < c)");
+    assert_eq!(round_trip("1 in a == b"), "1 in a == b");
+    assert_eq!(round_trip("-∞ < infinity"), "-∞ < ∞");
+  }
+
+  #[test]
+  fn test_print_conditionals() {
+    assert_eq!(round_trip("a and not b or c"), "a and not b or c");
+    assert_eq!(round_trip("not (a and b)"), "not (a and b)");
+    assert_eq!(round_trip("not a == b"), "not a == b");
+    assert_eq!(round_trip("(not a) == b"), "(not a) == b");
+    assert_eq!(round_trip("a xor (b or c)"), "a xor (b or c)");
+    assert_eq!(round_trip("if a>0 1 else 2"), "if a > 0 1 else 2");
+    assert_eq!(round_trip("if a (-1) else if b 2 else 3"), "if a (-1) else if b 2 else 3");
+    assert_eq!(round_trip("(if a 1 else 2) + 1"), "(if a 1 else 2) + 1");
+    assert_eq!(round_trip("if a (if b 1 else 2) else 3, 4"), "if a (if b 1 else 2) else 3, 4");
+    assert_eq!(round_trip("match x as 1 'one' as 2 'two' else 'many'"), "match x as 1 'one' as 2 'two' else 'many'");
+    assert_eq!(round_trip("match x < 0 as true 0 - x as false x"), "match x < 0 as true 0 - x as false x");
+    assert_eq!(round_trip("match x as 1 -1 as 2 y -> y"), "match x as 1 (-1) as 2 (y -> y)");
+  }
+
+  #[test]
+  fn test_print_lambda() {
+    assert_eq!(round_trip("map(xs,r->r*2)"), "map(xs, r -> r * 2)");
+    assert_eq!(round_trip("reduce(xs, 0, (acc,x) -> acc+x)"), "reduce(xs, 0, (acc, x) -> acc + x)");
+    assert_eq!(round_trip("() -> 1"), "() -> 1");
+    assert_eq!(round_trip("x -> y -> x * y"), "x -> y -> x * y");
+    assert_eq!(round_trip("(x -> x) + 1"), "(x -> x) + 1");
+    assert_eq!(round_trip("let f = x -> x + 1 in f(2)"), "let f = x -> x + 1 in f(2)");
+  }
+
+  #[test]
+  fn test_print_custom_ops() {
+    let mut funcs = Funcs::default();
+    funcs.define_op("%of", 15, Assoc::Left, "min").unwrap();
+    funcs.define_op("^^", 30, Assoc::Right, "max").unwrap();
+    funcs.define_op("+-", 10, Assoc::Left, "sum").unwrap();
+    funcs.define_op("mod", 20, Assoc::Left, "min").unwrap();
+    let ops = funcs.ops();
+    let tree = |src: &str| {
+      let mut p = Parser::new(src).with_ops(ops);
+      let ast = p.parse().unwrap();
+      sexpr(&p, &ast)
+    };
+
+    assert_eq!(tree("1 + 2 %of 3"), tree("1 + (2 %of 3)"));
+    assert_eq!(tree("1 * 2 %of 3"), tree("(1 * 2) %of 3"));
+    assert_eq!(tree("a ^^ b ^^ c"), tree("a ^^ (b ^^ c)"));
+    assert_eq!(tree("1 +- 2"), "(+- Num(1) Num(2))");
+    assert_eq!(tree("7mod 2"), "(mod Num(7) Num(2))");
+    assert!(Parser::new("a modx").with_ops(ops).parse().is_none());
+
+    assert_eq!(round_trip_ops("1+2%of 3", ops), "1 + 2 %of 3");
+    assert_eq!(round_trip_ops("(1+2)%of 3", ops), "(1 + 2) %of 3");
+    assert_eq!(round_trip_ops("(a^^b)^^c", ops), "(a ^^ b) ^^ c");
+    assert_eq!(round_trip_ops("a^^(b^^c)", ops), "a ^^ b ^^ c");
+    assert_eq!(round_trip_ops("(1 mod 2) mod 3", ops), "1 mod 2 mod 3");
+  }
+
+  #[test]
+  fn test_print_long_list() {
+    let src = (1..=20).map(|i| i.to_string()).collect::<Vec<_>>().join(",");
+    assert_eq!(round_trip(&src), src.replace(',', ", "));
+  }
+}
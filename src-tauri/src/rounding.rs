@@ -0,0 +1,121 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use serde::{Deserialize, Serialize};
+
+use crate::cell::Val;
+use crate::complex::Complex;
+
+/// How a number halfway between two others is rounded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mode {
+  /// Away from zero, `2.5` to `3`, as spreadsheets do.
+  #[default]
+  HalfUp,
+  /// To the even neighbour, `2.5` to `2`, banker's rounding.
+  HalfEven,
+}
+
+impl Mode {
+  pub fn strategy(self) -> RoundingStrategy {
+    match self {
+      Mode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+      Mode::HalfEven => RoundingStrategy::MidpointNearestEven,
+    }
+  }
+}
+
+/// A board's numeric policy: the decimal places numbers are shown with, how
+/// halves are rounded, and whether results are rounded as they're computed.
+///
+/// Cells keep every digit and are rounded when rendered, unless
+/// `intermediate` is set, when the result of each operator and function is
+/// rounded so that later computations see the rounded value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rounding {
+  /// Decimal places of results, or `None` for every digit.
+  pub places: Option<u32>,
+  pub mode: Mode,
+  pub intermediate: bool,
+}
+
+impl Rounding {
+  /// Rounds the numbers in `val`, including those in lists, arrays and
+  /// records, to the policy's places.
+  pub fn apply(&self, val: Val) -> Val {
+    match self.places {
+      // rounding to decimal places can't overflow, only to tens and above
+      Some(places) => round_val(val, &|d| round(d, places as i64, self.mode.strategy()).unwrap_or(d)),
+      None => val,
+    }
+  }
+
+  /// Rounds the result of an operator or function, if intermediate results
+  /// are rounded.
+  pub fn intermediate(&self, val: Val) -> Val {
+    match self.intermediate {
+      true => self.apply(val),
+      false => val,
+    }
+  }
+}
+
+/// Rounds `d` to `places` decimal places, or to a multiple of a power of ten
+/// for negative places, `round(1234, -2, ..)` is `1200`. `None` if the
+/// rounded number is too large for a `Decimal`.
+pub fn round(d: Decimal, places: i64, strategy: RoundingStrategy) -> Option<Decimal> {
+  if places >= 0 {
+    return Some(d.round_dp_with_strategy(places.min(u32::MAX as i64) as u32, strategy));
+  }
+  let scale = pow10(places.unsigned_abs());
+  match scale.is_zero() {
+    true => Some(Decimal::ZERO),
+    false => d.checked_div(scale)?.round_dp_with_strategy(0, strategy).checked_mul(scale),
+  }
+}
+
+/// `10 ^ n`, or zero when it's too large for a `Decimal`.
+fn pow10(n: u64) -> Decimal {
+  (0..n).try_fold(Decimal::ONE, |acc, _| acc.checked_mul(Decimal::TEN)).unwrap_or(Decimal::ZERO)
+}
+
+/// Applies `f` to each number in `val`, keeping its type. Floats without a
+/// decimal value, infinities, are kept as they are.
+pub fn round_val(val: Val, f: &dyn Fn(Decimal) -> Decimal) -> Val {
+  let float = |x: f64| Decimal::from_f64(x).and_then(|d| f(d).to_f64()).unwrap_or(x);
+  match val {
+    Val::Num(d) => Val::Num(f(d)),
+    Val::Float(x) => Val::Float(float(x)),
+    Val::Complex(z) => Complex::new(float(z.re), float(z.im)).into_val().unwrap_or(Val::Complex(z)),
+    Val::List(elems) => Val::List(elems.into_iter().map(|e| round_val(e, f)).collect()),
+    Val::Array{elems, axes} => Val::Array{ elems: elems.into_iter().map(|e| round_val(e, f)).collect(), axes },
+    Val::Record{value, fields} => Val::Record{ value: value.into_iter().map(|e| round_val(e, f)).collect(), fields },
+    val => val,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rust_decimal_macros::dec;
+
+  #[test]
+  fn test_rounding_policy() {
+    let half_up = Rounding{ places: Some(2), ..Rounding::default() };
+    let half_even = Rounding{ mode: Mode::HalfEven, ..half_up };
+    assert_eq!(half_up.apply(Val::Num(dec!(0.125))), Val::Num(dec!(0.13)));
+    assert_eq!(half_even.apply(Val::Num(dec!(0.125))), Val::Num(dec!(0.12)));
+    assert_eq!(half_up.apply(Val::Float(2.0 / 3.0)), Val::Float(0.67));
+    assert_eq!(half_up.apply(Val::Float(f64::INFINITY)), Val::Float(f64::INFINITY));
+    assert_eq!(half_up.apply(Val::List(vec![Val::Num(dec!(1.005)), Val::Int(3)])), Val::List(vec![Val::Num(dec!(1.01)), Val::Int(3)]));
+    assert_eq!(Rounding::default().apply(Val::Num(dec!(0.125))), Val::Num(dec!(0.125)));
+    // only rounded as computed when asked to be
+    assert_eq!(half_up.intermediate(Val::Num(dec!(0.125))), Val::Num(dec!(0.125)));
+
+    let strategy = RoundingStrategy::MidpointAwayFromZero;
+    assert_eq!(round(dec!(1250), -2, strategy), Some(dec!(1300)));
+    assert_eq!(round(dec!(-1.5), 0, strategy), Some(dec!(-2)));
+    assert_eq!(round(dec!(1), -40, strategy), Some(dec!(0)));
+    assert_eq!(round(Decimal::MAX, -1, strategy), None);
+    assert_eq!(round(Decimal::MIN, -1, strategy), None);
+  }
+}
@@ -8,6 +8,7 @@ use crate::tile::TileId;
 #[allow(non_snake_case)]
 pub struct TileUi {
   pub tag: TileId,
+  pub name: String,
   pub rows: u32,
   pub cells: Vec<CellUi>,
   pub colLabels: Vec<String>,
@@ -25,6 +26,10 @@ pub enum TypeUi {
   List,
   Array,
   Record,
+  Lambda,
+  OrderedMap,
+  SparseArray,
+  Error,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -53,6 +58,25 @@ pub struct RecordValueUi {
   pub fields: u32,
 }
 
+/// Only the elements that differ from `fill` are sent, at row-major `index`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SparseArrayValueUi {
+  pub typ: TypeUi,
+  pub fill: String,
+  pub dims: Vec<u32>,
+  pub index: Vec<u32>,
+  pub value: Vec<String>,
+}
+
+/// `value` is the error code, `#REF`, and `origin` the formula text that
+/// raised it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ErrorValueUi {
+  pub typ: TypeUi,
+  pub value: String,
+  pub origin: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "typ")]
 pub enum ValueUi {
@@ -60,6 +84,8 @@ pub enum ValueUi {
   L(ListValueUi),
   A(ArrayValueUi),
   R(RecordValueUi),
+  S(SparseArrayValueUi),
+  E(ErrorValueUi),
 }
 
 impl Default for ValueUi {
@@ -87,3 +113,44 @@ impl Default for CellUi {
       }
   }
 }
+
+#[derive(Serialize_repr, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TokenTypeUi {
+  Number,
+  String,
+  Operator,
+  Reference,
+  Keyword,
+  Symbol,
+  Punctuation,
+  Error,
+}
+
+/// A classified token of a formula. Offsets are in UTF-16 code units.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenUi {
+  pub typ: TokenTypeUi,
+  pub start: u32,
+  pub end: u32,
+}
+
+#[derive(Serialize_repr, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CompletionKindUi {
+  Label,
+  Tile,
+  Function,
+  Keyword,
+}
+
+/// A completion candidate replacing the formula text from `start` to `end`.
+/// Offsets are in UTF-16 code units.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CompletionUi {
+  pub kind: CompletionKindUi,
+  pub label: String,
+  pub detail: String,
+  pub start: u32,
+  pub end: u32,
+}
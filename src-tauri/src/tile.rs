@@ -4,15 +4,20 @@ use std::fmt;
 
 use itertools::Itertools;
 use log_derive::{logfn, logfn_inputs};
-use petgraph::Directed;
+use petgraph::{Directed, Direction};
+use petgraph::algo::has_path_connecting;
 use petgraph::stable_graph::{StableGraph, NodeIndex, DefaultIx};
+use petgraph::visit::{Dfs, EdgeRef};
 use serde::{Serialize, Deserialize};
 
 use crate::constants::*;
 use crate::eval::MainContext;
+use crate::funcs::Funcs;
+use crate::rounding::Rounding;
 #[allow(unused)]
 use crate::handle::{pos_to_cellid, index_to_pos, pos_to_index};
-use crate::cell::{CellOps, Val, Cell, CellId, CRef, CellRef};
+use crate::board::TileMap;
+use crate::cell::{CellOps, Val, Cell, CellId, CellRef, ErrorKind};
 use crate::parser::Parser;
 use crate::rpc::{TileUi, CellUi};
 
@@ -28,7 +33,20 @@ impl TileId {
 
 
 pub trait TileContext {
-  fn get_cell<const CARD: usize, R: Into<CellRef<CARD>>+fmt::Debug>(&mut self, cellref: R) -> (CellId, Cell);
+  /// Reads the cells at `axes` of `tile`, by name or id, or of the tile being
+  /// evaluated. A single cell gives its value, one slice a list and two an
+  /// array of rows.
+  fn get_range(&mut self, tile: Option<&Val>, axes: [Axis; 2]) -> Result<Val, ErrorKind>;
+}
+
+/// One axis of a reference: a position, a column or row label, or a slice of
+/// positions from the start, inclusive, to the end, exclusive. Slices without
+/// an end stop after the last used column or row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Axis {
+  Pos(usize),
+  Label(String),
+  Slice(Option<usize>, Option<usize>),
 }
 
 type DepsIx = DefaultIx;
@@ -37,12 +55,15 @@ type DepsLookup = HashMap<CellId, NodeIndex<DepsIx>>;
 
 pub struct Tile<Cell: CellOps>{
   pub tag: TileId,
+  pub name: String,
   pub rows: usize,
   pub cols: usize,
   cells: [Cell; ROW_MAX * COL_MAX],
   lbls: [String; ROW_MAX + COL_MAX],
   pub deps: DepsGraph,
   pub lookup: DepsLookup,
+  /// Cells of other tiles each formula read when it was last evaluated.
+  pub reads: HashMap<CellId, Vec<(TileId, CellId)>>,
 }
 
 impl<Cell: CellOps> fmt::Debug for Tile<Cell> {
@@ -86,49 +107,95 @@ impl<Cell: CellOps> Tile<Cell> {
 pub struct TileState<'a> {
   tile: &'a mut Tile<Cell>,
   cell: CellId,
+  others: &'a TileMap<Cell>,
 }
 
 impl TileContext for TileState<'_> {
-  fn get_cell<const CARD: usize, R: CRef<CARD>>(&mut self, cref: R) -> (CellId, Cell) {
-    let cellref: CellRef<CARD> = cref.into();
-    self.tile.track_dep(self.cell, cellref.clone());
-    (self.tile.resolve(cellref.clone()), self.tile.get_cell(cellref))  
+  fn get_range(&mut self, tile: Option<&Val>, axes: [Axis; 2]) -> Result<Val, ErrorKind> {
+    match tile {
+      Some(name) if !self.tile.is_named(name) => {
+        let other = self.others.values().find(|tile| tile.is_named(name)).ok_or(ErrorKind::Ref)?;
+        let (ids, val) = other.range(axes).ok_or(ErrorKind::Ref)?;
+        // the board evaluates the cell again when these change
+        self.tile.reads.entry(self.cell).or_default().extend(ids.into_iter().map(|id| (other.tag, id)));
+        Ok(val)
+      },
+      _ => self.tile.get_range(self.cell, axes),
+    }
   }
 }
 
 impl Tile<Cell> {
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
-  pub fn eval_cell<const CARD: usize, R: Into<CellRef<CARD>>+std::fmt::Debug>(&mut self, tile: TileId, cref: R) -> Option<Cell> {
+  pub fn eval_cell<const CARD: usize, R: Into<CellRef<CARD>>+std::fmt::Debug>(&mut self, tile: TileId, cref: R, others: &TileMap<Cell>, funcs: &Funcs, rounding: Rounding) -> Option<Cell> {
     let cellid = self.resolve(cref);
     let cell = self.get_cell_by_id(cellid);
-    let mut p = Parser::new(cell.formula.clone());
+    let mut p = Parser::new(cell.formula.as_str()).with_ops(funcs.ops());
 
-    match p.parse() {
+    // references are tracked again as the formula is evaluated
+    self.untrack_deps(cellid);
+    let res = match p.parse() {
       Some(node) => {
-        let mut state = TileState{tile: self, cell: cellid};
-        let mut ctx = MainContext{parser: &p, state: &mut state};
-        let res = node.eval(&mut ctx);
-        
-        let deps = self.cell_deps(cellid);
+        let mut state = TileState{tile: self, cell: cellid, others};
+        let mut ctx = MainContext{parser: &p, state: &mut state, funcs, rounding};
+        node.eval(&mut ctx)
+      },
+      None => Val::error(ErrorKind::Parse, cell.formula.clone()),
+    };
+    self.set_cell(cellid, Cell{ value: res, ..cell });
+
+    let cycle = self.cycle(cellid);
+    let deps = match cycle.is_empty() {
+      true => self.cell_deps(cellid),
+      // cells in the cycle can't be evaluated, but cells using them can
+      false => {
+        let origin = cycle.iter().map(|id| self.label(*id)).join(", ");
+        for id in &cycle {
+          self.update_cell(*id, |cell| Cell{ value: Val::error(ErrorKind::Cycle, origin.clone()), ..cell });
+        }
+        cycle.iter().flat_map(|id| self.cell_deps(*id)).filter(|id| !cycle.contains(id)).unique().collect()
+      },
+    };
+
+    for dep in deps {
+      self.eval_cell(tile, dep, others, funcs, rounding);
+    }
 
-        let cell = Cell{ value: res, ..cell };
-        self.set_cell(cellid, cell.clone());
+    Some(self.get_cell_by_id(cellid))
+  }
 
-        for dep in deps {
-          self.eval_cell(tile, dep).map(|c|self.set_cell_by_id(dep, c));
-        }
+  /// Values of the cells at `axes`, see `TileContext::get_range`.
+  pub fn range(&self, axes: [Axis; 2]) -> Option<(Vec<CellId>, Val)> {
+    let slices = axes.iter().filter(|axis| matches!(axis, Axis::Slice(..))).count();
+    let [cols, rows] = self.positions(axes)?;
+    // row-major, as arrays are
+    let ids = rows.iter().flat_map(|row| cols.iter().map(|col| pos_to_cellid([*col, *row]))).collect_vec();
+    let mut vals = ids.iter().map(|id| self.get_cell_by_id(*id).value).collect_vec();
+    let val = match slices {
+      0 => vals.pop()?,
+      1 => Val::List(vals),
+      _ => Val::Array{ elems: vals, axes: vec![rows.len() as u32, cols.len() as u32] },
+    };
+    Some((ids, val))
+  }
 
-        Some(cell)
-      },
-      None => {
-        self.update_cell(cellid, |cell|
-          Cell{ value: Val::Str("error".to_owned()), ..cell}
-        );
-        None
-      }
+  /// Like `range`, tracking the cells as dependencies of `cell`.
+  pub fn get_range(&mut self, cell: CellId, axes: [Axis; 2]) -> Result<Val, ErrorKind> {
+    let (ids, val) = self.range(axes).ok_or(ErrorKind::Ref)?;
+    for id in ids {
+      self.track_dep(cell, id);
+    }
+    Ok(val)
+  }
+
+  /// Whether a tile reference, `&Units` or `&2`, is to this tile.
+  pub fn is_named(&self, tile: &Val) -> bool {
+    match tile {
+      Val::Str(name) => *name == self.name,
+      Val::Num(_) | Val::Int(_) => i64::from(tile.clone()) == self.tag.0 as i64,
+      _ => false,
     }
-    // None
   }
 }
 
@@ -148,12 +215,14 @@ impl<C: CellOps>  Tile<C>{
 
     return Tile {
       tag: tag,
+      name: format!("Tile{}", tag.0),
       rows: 0,
       cols: 0,
       cells: cells,
       lbls: lbls,
       deps: DepsGraph::default(),
       lookup: DepsLookup::default(),
+      reads: HashMap::new(),
     }
   }
 
@@ -184,17 +253,88 @@ impl<C: CellOps>  Tile<C>{
     self.cells[index] = data;
   }
 
+  /// Deps graph node of a cell, added if the cell was never set.
+  fn node(&mut self, cell: CellId) -> NodeIndex {
+    match self.lookup.get(&cell) {
+      Some(ix) => *ix,
+      None => {
+        let ix = self.deps.add_node(cell);
+        self.lookup.insert(cell, ix);
+        ix
+      },
+    }
+  }
+
   #[logfn(Trace)]
   #[logfn_inputs(Trace)]
   pub fn cell_deps<const CARD: usize, R: Into<CellRef<CARD>>+std::fmt::Debug>(&self, cellref: R) -> Vec<CellId> {
     let cellid = self.resolve(cellref);
-    let ix: NodeIndex = self.lookup[&cellid];
+    let ix: NodeIndex = match self.lookup.get(&cellid) {
+      Some(ix) => *ix,
+      None => return vec![],
+    };
 
     self.deps.neighbors(ix)
       .map(|target|*self.deps.node_weight(target).unwrap())
+      .unique()
       .collect_vec()
   }
 
+  /// Drops the references made by the formula in `cell`.
+  fn untrack_deps(&mut self, cell: CellId) {
+    self.reads.remove(&cell);
+    let ix = self.node(cell);
+    let edges = self.deps.edges_directed(ix, Direction::Incoming).map(|edge| edge.id()).collect_vec();
+    for edge in edges {
+      self.deps.remove_edge(edge);
+    }
+  }
+
+  /// `cell` and the cells depending on it in this tile, directly or not.
+  pub fn downstream(&self, cell: CellId) -> Vec<CellId> {
+    let ix = match self.lookup.get(&cell) {
+      Some(ix) => *ix,
+      None => return vec![cell],
+    };
+    let mut cells = vec![];
+    let mut dfs = Dfs::new(&self.deps, ix);
+    while let Some(next) = dfs.next(&self.deps) {
+      cells.push(self.deps[next]);
+    }
+    cells
+  }
+
+  /// Cells whose formulas read any of `cells` of the tile `tag`.
+  pub fn readers(&self, tag: TileId, cells: &[CellId]) -> Vec<CellId> {
+    self.reads.iter()
+      .filter(|(_, read)| read.iter().any(|(t, id)| *t == tag && cells.contains(id)))
+      .map(|(cell, _)| *cell)
+      .sorted()
+      .collect_vec()
+  }
+
+  /// Cells on a cycle of references through `cell`, including itself, or
+  /// none if its value doesn't depend on itself.
+  pub fn cycle(&self, cell: CellId) -> Vec<CellId> {
+    let ix = match self.lookup.get(&cell) {
+      Some(ix) => *ix,
+      None => return vec![],
+    };
+    let mut cycle = vec![];
+    let mut dfs = Dfs::new(&self.deps, ix);
+    while let Some(next) = dfs.next(&self.deps) {
+      let on_cycle = match next == ix {
+        true => self.deps.neighbors(ix).any(|n| has_path_connecting(&self.deps, n, ix, None)),
+        false => has_path_connecting(&self.deps, next, ix, None),
+      };
+      if on_cycle {
+        cycle.push(self.deps[next]);
+      }
+    }
+    cycle.sort();
+    cycle
+  }
+
   pub fn get_cell<const CARD: usize, R: Into<CellRef<CARD>>+std::fmt::Debug>(&self, cellref: R) -> C {
     let cellid = self.resolve(cellref);
     self.get_cell_by_id(cellid)
@@ -214,6 +354,14 @@ impl<C: CellOps>  Tile<C>{
   }
 
 
+  pub fn col_labels(&self) -> &[String] {
+    &self.lbls[..self.cols]
+  }
+
+  pub fn row_labels(&self) -> &[String] {
+    &self.lbls[COL_MAX..COL_MAX + self.rows]
+  }
+
   fn pos_for<const CARD: usize>(&self, lbls: [String; CARD]) -> [usize; CARD] {
     let mut pos: [usize; CARD] = [0; CARD];
 
@@ -230,6 +378,53 @@ impl<C: CellOps>  Tile<C>{
     return pos
   }
 
+  pub fn set_col_label(&mut self, col: usize, label: impl Into<String>) {
+    self.lbls[col] = label.into();
+  }
+
+  pub fn set_row_label(&mut self, row: usize, label: impl Into<String>) {
+    self.lbls[COL_MAX + row] = label.into();
+  }
+
+  /// Columns and rows at `axes`, or `None` for labels the tile doesn't have
+  /// and positions past its last row or column.
+  ///
+  /// Labels take the axis they name, in either order, `{B, 3}` or `{3, B}`.
+  /// Positions and slices take the remaining axes, columns first.
+  fn positions(&self, axes: [Axis; 2]) -> Option<[Vec<usize>; 2]> {
+    let max = [COL_MAX, ROW_MAX];
+    let used = [self.cols, self.rows];
+    let mut res: [Option<Vec<usize>>; 2] = [None, None];
+    for axis in &axes {
+      if let Axis::Label(lbl) = axis {
+        let n = self.lbls.iter().position(|hay| hay == lbl)?;
+        let (k, i) = if n < COL_MAX { (0, n) } else { (1, n - COL_MAX) };
+        if res[k].replace(vec![i]).is_some() {
+          return None;
+        }
+      }
+    }
+    for axis in axes {
+      let k = match axis {
+        Axis::Label(_) => continue,
+        _ => res.iter().position(Option::is_none)?,
+      };
+      res[k] = Some(match axis {
+        Axis::Pos(pos) if pos < max[k] => vec![pos],
+        Axis::Slice(start, end) => (start.unwrap_or(0)..end.unwrap_or(used[k]).min(max[k])).collect(),
+        _ => return None,
+      });
+    }
+    let [cols, rows] = res;
+    Some([cols?, rows?])
+  }
+
+  /// The cell's column and row labels, `B3`.
+  pub fn label(&self, cell: CellId) -> String {
+    let (col, row) = index_to_pos(cell.0 as usize);
+    format!("{}{}", self.lbls[col], self.lbls[COL_MAX + row])
+  }
+
   pub fn resolve<const CARD: usize, R: Into<CellRef<CARD>>+std::fmt::Debug>(&self, cellref: R) -> CellId {
     let cellref: CellRef<CARD> = cellref.into();
     match cellref {
@@ -246,8 +441,8 @@ impl<C: CellOps>  Tile<C>{
     let downstream = self.resolve(downstream);
     let upstream = self.resolve(upstream);
 
-    let upstream_ix: NodeIndex = self.lookup[&upstream];
-    let downstream_ix: NodeIndex = self.lookup[&downstream];
+    let upstream_ix: NodeIndex = self.node(upstream);
+    let downstream_ix: NodeIndex = self.node(downstream);
 
     // The edge points upstream -> downstream so we can scan upstream.neighbors()
     // to recalculate values when upstream changes.
@@ -257,7 +452,8 @@ impl<C: CellOps>  Tile<C>{
   }
 
 
-  pub fn render(&self) -> TileUi {
+  /// Cells of the tile for display, with numbers rounded by `rounding`.
+  pub fn render(&self, rounding: &Rounding) -> TileUi {
     let c = self.cols;
     let r = self.rows;
     let mut cells: Vec<CellUi> = vec![Default::default(); c*r];
@@ -270,16 +466,17 @@ impl<C: CellOps>  Tile<C>{
     // 1, 2 => 5
     for ic in 0..c {
       for ir in 0..r {
-        cells[ir * c + ic] = self.get_cell([ic, ir]).render();
+        cells[ir * c + ic] = self.get_cell([ic, ir]).render_rounded(rounding);
       }
     }
 
     return TileUi {
       tag: self.tag,
+      name: self.name.clone(),
       rows: r as u32,
       cells: cells,
-      colLabels: self.lbls.iter().take(c).cloned().collect(),
-      rowLabels: self.lbls.iter().skip(COL_MAX).take(r).cloned().collect(),
+      colLabels: self.col_labels().to_vec(),
+      rowLabels: self.row_labels().to_vec(),
     }
   }
 }
@@ -345,7 +542,7 @@ mod tests {
       t.set_cell([1, 1], 5);
       t.set_cell([1, 2], 6);
 
-      let ui = t.render();
+      let ui = t.render(&Rounding::default());
 
       assert_eq!(ui.cells.len() as u32 / ui.rows, 2);
       assert_eq!(ui.rows, 3);
@@ -376,6 +573,192 @@ mod tests {
       assert_eq!(ui.cells, expected_cells);
     }
 
+    #[test]
+    fn test_tile_eval_funcs() {
+      use rust_decimal_macros::dec;
+      use crate::board::Board;
+      use crate::parser::Assoc;
+
+      let (mut board, tag) = Board::<Cell>::example();
+      board.define("def gross(rent, vacancy) = rent * (100 - vacancy) / 100").unwrap();
+      board.define("def noi(rent, vacancy, opex) = gross(rent, vacancy) - opex").unwrap();
+
+      let mut eval = |formula: &str| {
+        board.update_cell(tag, [2, 0], |cell| Cell{ formula: formula.to_owned(), ..cell });
+        board.eval_cell(tag, [2, 0]).unwrap().value
+      };
+      assert_eq!(eval("noi(100, 10, 20)"), Val::Num(dec!(70)));
+      assert_eq!(eval("map((100, 200), r -> gross(r, 50))"), Val::List(vec![Val::Num(dec!(50)), Val::Num(dec!(100))]));
+      // bindings in scope shadow board functions
+      assert_eq!(eval("let gross = (r, v) -> r in gross(100, 50)"), Val::Num(dec!(100)));
+
+      board.define("def pct(a, b) = a * b / 100").unwrap();
+      board.define_op("%of", 15, Assoc::Left, "pct").unwrap();
+      board.define_op("<?", 5, Assoc::Left, "min").unwrap();
+      let mut eval = |formula: &str| {
+        board.update_cell(tag, [2, 0], |cell| Cell{ formula: formula.to_owned(), ..cell });
+        board.eval_cell(tag, [2, 0]).unwrap().value
+      };
+      assert_eq!(eval("1 + 50 %of 20"), Val::Num(dec!(11)));
+      assert_eq!(eval("3 <? 1 + 1"), Val::Num(dec!(2)));
+      assert_eq!(eval("map((10, 20), x -> 50 %of x)"), Val::List(vec![Val::Num(dec!(5)), Val::Num(dec!(10))]));
+    }
+
+    #[test]
+    fn test_tile_eval_rounding() {
+      use rust_decimal_macros::dec;
+      use crate::board::Board;
+      use crate::rounding::Mode;
+
+      let (mut board, tag) = Board::<Cell>::example();
+      let eval = |board: &mut Board<Cell>, pos: [usize; 2], formula: &str| {
+        board.update_cell(tag, pos, |cell| Cell{ formula: formula.to_owned(), ..cell });
+        board.eval_cell(tag, pos).unwrap().value
+      };
+      let shown = |board: &Board<Cell>, pos: [usize; 2]| {
+        let ui = board.render_tile(tag);
+        let cols = ui.cells.len() / ui.rows as usize;
+        ui.cells[pos[1] * cols + pos[0]].value.clone()
+      };
+      let number = |value: &str| ValueUi::V(ScalarValueUi{ typ: TypeUi::Number, value: value.to_owned() });
+
+      // values keep every digit and are rounded when shown
+      board.set_rounding(Rounding{ places: Some(2), mode: Mode::HalfEven, intermediate: false });
+      assert_eq!(eval(&mut board, [2, 0], "1 / 8 * 3"), Val::Num(dec!(0.375)));
+      assert_eq!(eval(&mut board, [2, 1], "[2, 0] * 2"), Val::Num(dec!(0.75)));
+      assert_eq!(shown(&board, [2, 0]), number("0.38"));
+      assert_eq!(eval(&mut board, [2, 0], "round(2.5)"), Val::Num(dec!(2.5)));
+
+      // or rounded as they're computed
+      board.set_rounding(Rounding{ places: Some(2), mode: Mode::HalfUp, intermediate: true });
+      assert_eq!(eval(&mut board, [2, 0], "1 / 3 * 3"), Val::Num(dec!(0.99)));
+      assert_eq!(eval(&mut board, [2, 1], "[2, 0] * 2"), Val::Num(dec!(1.98)));
+      assert_eq!(eval(&mut board, [2, 0], "map((1, 2), x -> x / 3)"), Val::List(vec![Val::Num(dec!(0.33)), Val::Num(dec!(0.67))]));
+    }
+
+    #[test]
+    fn test_tile_eval_lazy() {
+      use rust_decimal_macros::dec;
+      use crate::board::Board;
+
+      let (mut board, tag) = Board::<Cell>::example();
+      let eval = |board: &mut Board<Cell>, pos: [usize; 2], formula: &str| {
+        board.update_cell(tag, pos, |cell| Cell{ formula: formula.to_owned(), ..cell });
+        board.eval_cell(tag, pos).unwrap().value
+      };
+      let used_by = |board: &Board<Cell>, pos: [usize; 2]| board.get_tile(tag).unwrap().cell_deps(pos);
+      let c1 = pos_to_cellid([2, 0]);
+
+      // only the branch taken is a dependency
+      assert_eq!(eval(&mut board, [2, 0], "if [0, 0] > 1 [1, 0] else [0, 1]"), Val::Float(3.0));
+      assert!(used_by(&board, [1, 0]).contains(&c1));
+      assert!(!used_by(&board, [0, 1]).contains(&c1));
+      assert!(used_by(&board, [0, 0]).contains(&c1));
+
+      // and the other becomes one when the condition changes
+      eval(&mut board, [0, 0], "0");
+      assert_eq!(board.get_tile(tag).unwrap().get_cell([2, 0]).value, Val::Float(17.5));
+      assert!(used_by(&board, [0, 1]).contains(&c1));
+      assert!(!used_by(&board, [1, 0]).contains(&c1));
+
+      // nor is a reference to itself in a branch not taken a cycle
+      assert_eq!(eval(&mut board, [2, 1], "if true 1 else [2, 1] + 1"), Val::Num(dec!(1)));
+      assert_eq!(eval(&mut board, [2, 1], "false and [2, 1]"), Val::Bool(false));
+    }
+
+    #[test]
+    fn test_tile_eval_errors() {
+      use rust_decimal_macros::dec;
+      use crate::board::Board;
+
+      let (mut board, tag) = Board::<Cell>::example();
+      let eval = |board: &mut Board<Cell>, pos: [usize; 2], formula: &str| {
+        board.update_cell(tag, pos, |cell| Cell{ formula: formula.to_owned(), ..cell });
+        board.eval_cell(tag, pos).unwrap().value
+      };
+      let get = |board: &Board<Cell>, pos: [usize; 2]| board.get_tile(tag).unwrap().get_cell(pos).value;
+      let err = |kind, origin: &str| Val::error(kind, origin);
+
+      assert_eq!(eval(&mut board, [2, 0], "1 / 0"), err(ErrorKind::Div0, "1 / 0"));
+      assert_eq!(eval(&mut board, [2, 0], "'a' * 2"), err(ErrorKind::Type, "'a' * 2"));
+      assert_eq!(eval(&mut board, [2, 0], "[99, 0] + 1"), err(ErrorKind::Ref, "[99, 0]"));
+      assert_eq!(eval(&mut board, [2, 0], "@Z3"), err(ErrorKind::Ref, "{3, Z}"));
+      assert_eq!(eval(&mut board, [2, 0], "@B3"), Val::Bool(true));
+      assert_eq!(eval(&mut board, [2, 0], "nope(1)"), err(ErrorKind::Ref, "nope"));
+      assert_eq!(eval(&mut board, [2, 0], ")"), err(ErrorKind::Parse, ")"));
+      // a formula that only parses in part is an error, not the value of the part
+      for formula in ["1 2", "1 + )", "1 +", "1,", "x -> ", "if 1 2"] {
+        assert_eq!(eval(&mut board, [2, 0], formula), err(ErrorKind::Parse, formula), "{formula}");
+      }
+      for formula in ["let x = 1 in -x", "match 1 as", "not", "if 1 2 else"] {
+        assert_eq!(eval(&mut board, [2, 0], formula), err(ErrorKind::Parse, formula), "{formula}");
+      }
+      // errors pass through operators and functions unchanged
+      assert_eq!(eval(&mut board, [2, 0], "sum(1, 2 / 0) * (1 + 'a')"), err(ErrorKind::Div0, "2 / 0"));
+      assert_eq!(eval(&mut board, [2, 0], "map((1, 0), x -> 1 / x)"), Val::List(vec![Val::Num(dec!(1)), err(ErrorKind::Div0, "1 / x")]));
+      // and into the cells that use them
+      assert_eq!(eval(&mut board, [2, 1], "sum([2, 0])"), err(ErrorKind::Div0, "1 / x"));
+      assert_eq!(eval(&mut board, [2, 2], "[2, 1] + 1"), err(ErrorKind::Div0, "1 / x"));
+
+      // C1 and C2 refer to each other, C3 uses them
+      assert_eq!(eval(&mut board, [2, 0], "[2, 1] + 1"), err(ErrorKind::Cycle, "C1, C2"));
+      assert_eq!(get(&board, [2, 1]), err(ErrorKind::Cycle, "C1, C2"));
+      assert_eq!(get(&board, [2, 2]), err(ErrorKind::Cycle, "C1, C2"));
+      assert_eq!(eval(&mut board, [2, 1], "5"), Val::Num(dec!(5)));
+      assert_eq!(get(&board, [2, 0]), Val::Num(dec!(6)));
+      assert_eq!(get(&board, [2, 2]), Val::Num(dec!(6)));
+      assert_eq!(eval(&mut board, [2, 0], "[2, 0] + 1"), err(ErrorKind::Cycle, "C1"));
+    }
+
+    #[test]
+    fn test_tile_eval_ranges() {
+      use rust_decimal::Decimal;
+      use rust_decimal_macros::dec;
+      use crate::board::Board;
+
+      let (mut board, tag) = Board::<Cell>::example();
+      let units = board.add_tile();
+      let tile = board.mut_tile(units).unwrap();
+      tile.name = "Units".to_owned();
+      tile.set_col_label(0, "Rent");
+      tile.set_col_label(1, "Occupancy");
+
+      let eval = |board: &mut Board<Cell>, tag: TileId, pos: [usize; 2], formula: &str| {
+        board.update_cell(tag, pos, |cell| Cell{ formula: formula.to_owned(), ..cell });
+        board.eval_cell(tag, pos).unwrap().value
+      };
+      let get = |board: &Board<Cell>, pos: [usize; 2]| board.get_tile(tag).unwrap().get_cell(pos).value;
+      let nums = |vals: &[Decimal]| Val::List(vals.iter().map(|v| Val::Num(*v)).collect());
+      for (row, (rent, occupancy)) in [("1200", "1"), ("950", "1 / 2"), ("1500", "0")].into_iter().enumerate() {
+        eval(&mut board, units, [0, row], rent);
+        eval(&mut board, units, [1, row], occupancy);
+      }
+
+      // columns of another tile, by label
+      assert_eq!(eval(&mut board, tag, [2, 0], "&Units{[:], Rent} * &Units{[:], Occupancy}"), nums(&[dec!(1200), dec!(475), dec!(0)]));
+      assert_eq!(eval(&mut board, tag, [2, 0], "sum(&Units{[:], Rent} * &Units{[:], Occupancy})"), Val::Num(dec!(1675)));
+      // by id and position, and whole tiles as arrays of rows
+      assert_eq!(eval(&mut board, tag, [2, 0], "&1[0:2, 1] * 2"), nums(&[dec!(1900), dec!(1)]));
+      assert_eq!(eval(&mut board, tag, [2, 0], "&Units[:, :] - (1200, 1)"), Val::Array{
+        elems: [dec!(0), dec!(0), dec!(-250), dec!(-0.5), dec!(300), dec!(-1)].into_iter().map(Val::Num).collect(),
+        axes: vec![3, 2],
+      });
+      assert_eq!(eval(&mut board, tag, [2, 0], "&Units[:, :] * (1, 0)"), Val::Array{
+        elems: [1200, 950, 1500].into_iter().map(|v| Val::Num(Decimal::from(v))).collect(),
+        axes: vec![3],
+      });
+
+      // ranges in the same tile are dependencies
+      assert_eq!(eval(&mut board, tag, [3, 0], "sum([0, 0:3])"), Val::Num(dec!(57.3)));
+      eval(&mut board, tag, [0, 1], "1 / 2");
+      assert_eq!(get(&board, [3, 0]), Val::Num(dec!(40.3)));
+
+      assert_eq!(eval(&mut board, tag, [2, 0], "&Nope[0, 0]"), Val::error(ErrorKind::Ref, "&Nope[0, 0]"));
+      assert_eq!(eval(&mut board, tag, [2, 0], "&Units{Rent, Occupancy}"), Val::error(ErrorKind::Ref, "&Units{Rent, Occupancy}"));
+      assert_eq!(eval(&mut board, tag, [2, 0], "[-1:, 0]"), Val::error(ErrorKind::Ref, "[-1:, 0]"));
+      assert_eq!(eval(&mut board, tag, [2, 0], "&Units[:, :] + (1, 2, 3)"), Val::error(ErrorKind::Shape, "&Units[:, :] + (1, 2, 3)"));
+    }
+
     #[test]
     fn test_dumb() {
       let mut map: HashMap<i32, (usize, usize)> = HashMap::new();
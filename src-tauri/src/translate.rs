@@ -0,0 +1,887 @@
+use std::fmt::Write;
+
+use rust_decimal::Decimal;
+
+use crate::cell::Val;
+use crate::err::Err;
+use crate::eval::{Node, ObjectContext};
+use crate::parser::{binop_prec, NodeId};
+use crate::printer::quote;
+
+/// Excel functions with a builtin of the same meaning.
+const FUNCS: [(&str, &str); 5] = [
+  ("ABS", "abs"),
+  ("AVERAGE", "avg"),
+  ("MAX", "max"),
+  ("MIN", "min"),
+  ("SUM", "sum"),
+];
+
+/// Excel operators and their precedence, loosest first.
+const BINOPS: [(&str, &str, u8); 11] = [
+  ("<>", "!=", CMP_PREC),
+  ("<=", "<=", CMP_PREC),
+  (">=", ">=", CMP_PREC),
+  ("=", "==", CMP_PREC),
+  ("<", "<", CMP_PREC),
+  (">", ">", CMP_PREC),
+  ("&", "++", 2),
+  ("+", "+", 3),
+  ("-", "-", 3),
+  ("*", "*", MUL_PREC),
+  ("/", "/", MUL_PREC),
+];
+
+/// Precedence of `if`, looser than any operator.
+const IF_PREC: u8 = 0;
+const CMP_PREC: u8 = 1;
+const MUL_PREC: u8 = 4;
+const POW_PREC: u8 = 5;
+/// Precedence of negation, which Excel binds tighter than `^`.
+const NEG_PREC: u8 = 6;
+const TERM_PREC: u8 = u8::MAX;
+
+/// A translated expression and the precedence it binds with.
+type Expr = (String, u8);
+
+/// A column or row of a cell in A1 notation, as a 0-based index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Coord {
+  index: u32,
+  abs: bool,
+}
+
+/// Translates a spreadsheet formula (`=SUM(A1:A5)*$B$2`) to valuator syntax
+/// (`sum([0, 0:5]) * [$1, $1]`).
+///
+/// Cells become `[col, row]` positions and ranges become slices, keeping `$`
+/// markers. Sheets become tile references, so `Sheet2!B4` is `&Sheet2[1, 3]`. Functions,
+/// names and errors without a valuator equivalent are reported in
+/// `Err::Untranslatable` rather than approximated.
+pub fn translate(formula: &str) -> Result<String, Err> {
+  let mut t = Translator{ buf: formula, pos: 0, furthest: 0, unknown: vec![] };
+  t.ws();
+  t.char('=');
+  t.ws();
+  let res = t.expr(CMP_PREC);
+  t.ws();
+  match res {
+    Some(_) if t.pos < t.buf.len() => Err(Err::Parse{ pos: t.furthest.max(t.pos) }),
+    Some(_) if !t.unknown.is_empty() => Err(Err::Untranslatable{ names: t.unknown }),
+    Some((out, _)) => Ok(out),
+    None => Err(Err::Parse{ pos: t.furthest.max(t.pos) }),
+  }
+}
+
+struct Translator<'a> {
+  buf: &'a str,
+  pos: usize,
+  furthest: usize,
+  unknown: Vec<String>,
+}
+
+impl<'a> Translator<'a> {
+  fn rest(&self) -> &'a str {
+    &self.buf[self.pos..]
+  }
+
+  fn ws(&mut self) {
+    let rest = self.rest();
+    self.pos += rest.len() - rest.trim_start().len();
+  }
+
+  fn char(&mut self, ch: char) -> Option<char> {
+    self.rest().starts_with(ch).then(|| {
+      self.pos += ch.len_utf8();
+      ch
+    })
+  }
+
+  /// Matches a run of characters, at least one.
+  fn take(&mut self, pred: impl Fn(char) -> bool) -> Option<&'a str> {
+    let rest = self.rest();
+    let len = rest.find(|ch|!pred(ch)).unwrap_or(rest.len());
+    self.pos += len;
+    (len > 0).then(|| &rest[..len])
+  }
+
+  fn fail<T>(&mut self) -> Option<T> {
+    self.furthest = self.furthest.max(self.pos);
+    None
+  }
+
+  fn report(&mut self, name: &str) {
+    if !self.unknown.iter().any(|n|n == name) {
+      self.unknown.push(name.to_owned());
+    }
+  }
+
+  /// Precedence climbing over `BINOPS`, all of which are left associative.
+  fn expr(&mut self, min_prec: u8) -> Option<Expr> {
+    let mut lhs = self.pow()?;
+    loop {
+      let start = self.pos;
+      self.ws();
+      let op = BINOPS.iter().find(|(op, _, prec)|*prec >= min_prec && self.rest().starts_with(op));
+      let Some(&(op, out, prec)) = op else {
+        self.pos = start;
+        return Some(lhs);
+      };
+      self.pos += op.len();
+      self.ws();
+      let rhs = self.expr(prec + 1)?;
+      // wrap compound operands of `++`, which valuator ranks differently
+      let (lprec, rprec) = if out == "++" { (TERM_PREC, TERM_PREC) } else { (prec, prec + 1) };
+      lhs = (format!("{} {} {}", wrap(lhs, lprec), out, wrap(rhs, rprec)), prec);
+    }
+  }
+
+  fn pow(&mut self) -> Option<Expr> {
+    let mut lhs = self.unary()?;
+    loop {
+      let start = self.pos;
+      self.ws();
+      if self.char('^').is_none() {
+        self.pos = start;
+        return Some(lhs);
+      }
+      self.ws();
+      let rhs = self.unary()?;
+      // Excel reads `-2^2` as `(-2)^2` and `2^3^2` as `(2^3)^2`
+      lhs = (format!("{}^{}", wrap(lhs, TERM_PREC), wrap(rhs, NEG_PREC)), POW_PREC);
+    }
+  }
+
+  fn unary(&mut self) -> Option<Expr> {
+    if self.char('-').is_some() {
+      self.ws();
+      let rhs = self.unary()?;
+      // only a number takes a `-` prefix: `-[0, 0]` would read as a relative reference
+      if rhs.0.starts_with(|ch: char|ch.is_ascii_digit()) && rhs.0.parse::<Decimal>().is_ok() {
+        return Some((format!("-{}", rhs.0), NEG_PREC));
+      }
+      return Some((format!("0 - {}", wrap(rhs, MUL_PREC)), 3));
+    }
+    if self.char('+').is_some() {
+      self.ws();
+      return self.unary();
+    }
+    let mut term = self.term()?;
+    while self.char('%').is_some() {
+      term = (format!("{} / 100", wrap(term, MUL_PREC)), MUL_PREC);
+    }
+    Some(term)
+  }
+
+  fn term(&mut self) -> Option<Expr> {
+    let ch = self.rest().chars().next();
+    match ch {
+      Some('(') => {
+        self.pos += 1;
+        self.ws();
+        let inner = self.expr(CMP_PREC)?;
+        self.ws();
+        self.char(')').or_else(||self.fail())?;
+        Some(inner)
+      },
+      Some('"') => self.string(),
+      Some('#') => self.error(),
+      Some(ch) if ch.is_ascii_digit() => self.rows().map_or_else(||self.number(), |rows|Some((rows, TERM_PREC))),
+      Some('.') => self.number(),
+      _ => self.reference(),
+    }
+  }
+
+  fn number(&mut self) -> Option<Expr> {
+    let start = self.pos;
+    self.take(|ch|ch.is_ascii_digit());
+    if self.char('.').is_some() {
+      self.take(|ch|ch.is_ascii_digit());
+    }
+    let mantissa = self.pos;
+    if self.char('E').or_else(||self.char('e')).is_some() {
+      self.char('+').or_else(||self.char('-'));
+      if self.take(|ch|ch.is_ascii_digit()).is_none() {
+        self.pos = mantissa;
+      }
+    }
+    let num = &self.buf[start..self.pos];
+    let num = if num.starts_with('.') { format!("0{num}") } else { num.to_owned() };
+    let num = match num.contains(['e', 'E']) {
+      true => Decimal::from_scientific(&num).ok().map(|d|d.normalize().to_string()),
+      false => num.parse::<Decimal>().ok().map(|_|num),
+    };
+    match num {
+      Some(num) => Some((num, TERM_PREC)),
+      None => {
+        self.pos = start;
+        self.fail()
+      },
+    }
+  }
+
+  /// Matches a string, where `""` escapes a quote.
+  fn string(&mut self) -> Option<Expr> {
+    self.char('"')?;
+    let mut body = String::new();
+    loop {
+      let part = self.take(|ch|ch != '"').unwrap_or("");
+      body.push_str(part);
+      self.char('"').or_else(||self.fail())?;
+      if self.char('"').is_none() {
+        break;
+      }
+      body.push('"');
+    }
+    Some((quote(&body), TERM_PREC))
+  }
+
+  /// Matches an error literal such as `#N/A`, which has no valuator syntax.
+  fn error(&mut self) -> Option<Expr> {
+    let start = self.pos;
+    self.char('#')?;
+    self.take(|ch|ch.is_ascii_alphanumeric() || ch == '/');
+    self.char('!').or_else(||self.char('?'));
+    let err = &self.buf[start..self.pos];
+    self.report(err);
+    Some((err.to_owned(), TERM_PREC))
+  }
+
+  /// Matches a sheet name followed by `!`, quoted if it contains spaces.
+  fn sheet(&mut self) -> Option<String> {
+    let start = self.pos;
+    let name = self.sheet_name().filter(|_|self.char('!').is_some());
+    let Some(name) = name else {
+      self.pos = start;
+      return None;
+    };
+    if name.chars().all(|ch|ch.is_alphanumeric() || ch == '_') && !name.starts_with(|ch: char|ch.is_ascii_digit()) {
+      Some(format!("&{name}"))
+    } else if !name.contains('\'') {
+      Some(format!("&'{name}'"))
+    } else {
+      Some(format!("&\"{name}\""))
+    }
+  }
+
+  fn sheet_name(&mut self) -> Option<String> {
+    if self.char('\'').is_none() {
+      return self.take(|ch|ch.is_alphanumeric() || ch == '_' || ch == '.').map(str::to_owned);
+    }
+    let mut name = String::new();
+    loop {
+      name.push_str(self.take(|ch|ch != '\'').unwrap_or(""));
+      self.char('\'')?;
+      if self.char('\'').is_none() {
+        return Some(name);
+      }
+      name.push('\'');
+    }
+  }
+
+  fn reference(&mut self) -> Option<Expr> {
+    let buf = self.buf;
+    let start = self.pos;
+    let tile = self.sheet().unwrap_or_default();
+
+    if let Some(pos) = self.range() {
+      return Some((format!("{tile}{pos}"), TERM_PREC));
+    }
+    let name = self.take(|ch|ch.is_alphanumeric() || ch == '_' || ch == '.').or_else(||self.fail())?;
+    if !tile.is_empty() {
+      self.report(&buf[start..self.pos]);
+      return Some((format!("{tile}{name}"), TERM_PREC));
+    }
+    if self.char('(').is_some() {
+      return self.call(name);
+    }
+    match name.to_ascii_uppercase().as_str() {
+      "TRUE" => Some(("true".to_owned(), TERM_PREC)),
+      "FALSE" => Some(("false".to_owned(), TERM_PREC)),
+      // defined names have no equivalent outside their workbook
+      _ => {
+        self.report(name);
+        Some((name.to_owned(), TERM_PREC))
+      },
+    }
+  }
+
+  /// Matches a cell (`$B$2`), a range (`A1:B5`), or a whole column (`A:A`) or
+  /// row (`1:3`), as a position with slices.
+  fn range(&mut self) -> Option<String> {
+    let start = self.pos;
+    let (row, col) = self.cell();
+    let end = self.pos;
+    let to = match self.char(':') {
+      Some(_) => self.cell(),
+      None => (None, None),
+    };
+    let pos = match ((row, col), to) {
+      ((Some(r), Some(c)), (None, None)) => {
+        self.pos = end;
+        Some(format!("[{}, {}]", index(c), index(r)))
+      },
+      ((Some(r1), Some(c1)), (Some(r2), Some(c2))) =>
+        Some(format!("[{}, {}]", slice(c1, c2), slice(r1, r2))),
+      ((None, Some(c1)), (None, Some(c2))) => Some(format!("[{}, :]", slice(c1, c2))),
+      ((Some(r1), None), (Some(r2), None)) => Some(format!("[:, {}]", slice(r1, r2))),
+      _ => None,
+    };
+    if pos.is_none() {
+      self.pos = start;
+    }
+    pos
+  }
+
+  /// Matches a range of whole rows, which would otherwise read as a number.
+  fn rows(&mut self) -> Option<String> {
+    self.range().filter(|pos|pos.starts_with("[:"))
+  }
+
+  /// Matches a column and row in A1 notation, either of which may be missing
+  /// in ranges of whole columns or rows.
+  fn cell(&mut self) -> (Option<Coord>, Option<Coord>) {
+    let start = self.pos;
+    let col = self.coord(|ch|ch.is_ascii_alphabetic(), |col|col_index(&col.to_ascii_uppercase()));
+    let row = self.coord(|ch|ch.is_ascii_digit(), |row| {
+      row.parse::<u32>().ok().filter(|&n|n > 0).map(|n|n - 1)
+    });
+    // `A1B` or `SUM(` are names, not cells
+    let next = self.rest().chars().next();
+    if next.is_some_and(|ch|ch.is_alphanumeric() || ch == '_' || ch == '(' || ch == '.') {
+      self.pos = start;
+      return (None, None);
+    }
+    (row, col)
+  }
+
+  fn coord(&mut self, pred: impl Fn(char) -> bool, index: impl Fn(&str) -> Option<u32>) -> Option<Coord> {
+    let start = self.pos;
+    let abs = self.char('$').is_some();
+    match self.take(pred).and_then(index) {
+      Some(index) => Some(Coord{ index, abs }),
+      None => {
+        self.pos = start;
+        None
+      },
+    }
+  }
+
+  fn call(&mut self, name: &str) -> Option<Expr> {
+    let mut args = vec![];
+    self.ws();
+    if self.char(')').is_none() {
+      loop {
+        self.ws();
+        args.push(self.expr(CMP_PREC)?);
+        self.ws();
+        if self.char(')').is_some() {
+          break;
+        }
+        self.char(',').or_else(||self.fail())?;
+      }
+    }
+
+    let upper = name.to_ascii_uppercase();
+    let upper = upper.trim_start_matches("_XLFN.");
+    let conj = match upper {
+      "IF" if (2..=3).contains(&args.len()) => {
+        let mut args = args.into_iter();
+        let (cond, then) = (args.next()?, args.next()?);
+        let other = args.next().unwrap_or(("false".to_owned(), TERM_PREC));
+        // keep `-1` or `(..)` from joining the condition
+        let then = if then.0.starts_with(['-', '(']) { format!("({})", then.0) } else { wrap(then, CMP_PREC) };
+        let out = format!("if {} {} else {}", wrap(cond, CMP_PREC), then, wrap(other, IF_PREC));
+        return Some((out, IF_PREC));
+      },
+      "NOT" if args.len() == 1 => {
+        let arg = args.pop()?;
+        return Some((format!("not {}", wrap(arg, TERM_PREC)), NEG_PREC));
+      },
+      "TRUE" | "FALSE" if args.is_empty() => return Some((upper.to_ascii_lowercase(), TERM_PREC)),
+      "AND" if !args.is_empty() => "and",
+      "OR" if !args.is_empty() => "or",
+      _ => "",
+    };
+    if !conj.is_empty() {
+      let args: Vec<String> = args.into_iter().map(|arg|wrap(arg, TERM_PREC)).collect();
+      return Some((args.join(&format!(" {conj} ")), IF_PREC));
+    }
+
+    let args: Vec<String> = args.into_iter().map(|(arg, _)|arg).collect();
+    match FUNCS.iter().find(|(excel, _)|*excel == upper) {
+      Some((_, func)) => Some((format!("{func}({})", args.join(", ")), TERM_PREC)),
+      None => {
+        self.report(upper);
+        Some((format!("{name}({})", args.join(", ")), TERM_PREC))
+      },
+    }
+  }
+}
+
+/// Parenthesizes `expr` when it binds looser than `min_prec`.
+fn wrap((expr, prec): Expr, min_prec: u8) -> String {
+  if prec < min_prec { format!("({expr})") } else { expr }
+}
+
+fn index(coord: Coord) -> String {
+  format!("{}{}", if coord.abs { "$" } else { "" }, coord.index)
+}
+
+/// A single index, or an end-exclusive slice covering `from` to `to`.
+fn slice(from: Coord, to: Coord) -> String {
+  let (from, to) = if to.index < from.index { (to, from) } else { (from, to) };
+  if from == to {
+    return index(from);
+  }
+  let end = Coord{ index: to.index + 1, ..to };
+  format!("{}:{}", index(from), index(end))
+}
+
+/// Marks a construct without a spreadsheet equivalent in exported formulas.
+/// It is not valid formula syntax, so a spreadsheet rejects the formula
+/// rather than computing something else.
+pub const UNSUPPORTED: &str = "#UNSUPPORTED";
+
+/// Renders a formula as a spreadsheet formula (`=SUM(A1:A5)*$B$2`), the
+/// reverse of `translate`.
+///
+/// Positions and addresses using the default labels become A1 references,
+/// and slices of positions become ranges. Anything else, such as lambdas, custom labels or board functions, is
+/// printed in valuator syntax inside an `UNSUPPORTED` marker:
+/// `=#UNSUPPORTED(let x = 1 in x)+1`.
+pub fn export(node: &Node, ctx: &impl ObjectContext) -> String {
+  let mut e = Exporter{ ctx, out: String::from("=") };
+  e.node(node);
+  e.out
+}
+
+struct Exporter<'a, C: ObjectContext> {
+  ctx: &'a C,
+  out: String,
+}
+
+impl<'a, C: ObjectContext> Exporter<'a, C> {
+  fn unsupported(&mut self, node: &Node) {
+    write!(self.out, "{UNSUPPORTED}({})", node.print(self.ctx)).unwrap();
+  }
+
+  fn prec(&self, node: &Node) -> u8 {
+    match node {
+      Node::BinOp { op, .. } => binop_prec(*op).unwrap_or(TERM_PREC),
+      _ => TERM_PREC,
+    }
+  }
+
+  /// Prints `node`, wrapped in parens when it binds looser than `min_prec`.
+  fn operand(&mut self, id: &NodeId, min_prec: u8) {
+    let node = *self.ctx.get_node(id);
+    if self.prec(&node) < min_prec {
+      self.out.push('(');
+      self.node(&node);
+      self.out.push(')');
+    } else {
+      self.node(&node);
+    }
+  }
+
+  /// An index in a position, `2` or `$2`, defaulting to 0 when omitted.
+  fn index(&self, id: &NodeId) -> Option<Coord> {
+    if *id == NodeId(0) {
+      return Some(Coord{ index: 0, abs: false });
+    }
+    match self.ctx.get_node(id) {
+      Node::Leaf { value } => match self.ctx.get_value(value) {
+        Val::Num(n) if n.is_integer() && !n.is_sign_negative() => Some(Coord{ index: n.to_string().parse().ok()?, abs: false }),
+        _ => None,
+      },
+      Node::UniOp { op: '$', rhs } => self.index(rhs).map(|coord|Coord{ abs: true, ..coord }),
+      _ => None,
+    }
+  }
+
+  /// A default row label, `2` for the second row.
+  fn row_label(&self, id: &NodeId) -> Option<Coord> {
+    self.index(id).filter(|_|*id != NodeId(0)).and_then(|coord|{
+      Some(Coord{ index: coord.index.checked_sub(1)?, ..coord })
+    })
+  }
+
+  /// A default column label, `B` for the second column.
+  fn col_label(&self, id: &NodeId) -> Option<Coord> {
+    match self.ctx.get_node(id) {
+      Node::Sym { value } => col_index(&self.ctx.get_value(value).to_string()).map(|index|Coord{ index, abs: false }),
+      Node::UniOp { op: '$', rhs } => self.col_label(rhs).map(|coord|Coord{ abs: true, ..coord }),
+      _ => None,
+    }
+  }
+
+  /// The first and last index covered by a position, `None` for a whole
+  /// column or row (`:`).
+  fn span(&self, id: &NodeId) -> Option<Option<(Coord, Coord)>> {
+    match self.ctx.get_node(id) {
+      Node::Slice { start, end } if *start == NodeId(0) && *end == NodeId(0) => Some(None),
+      // an open end runs to the edge of the tile, which has no A1 notation
+      Node::Slice { end, .. } if *end == NodeId(0) => None,
+      Node::Slice { start, end } => {
+        let (from, to) = (self.index(start)?, self.index(end)?);
+        (to.index > from.index).then(||Some((from, Coord{ index: to.index - 1, ..to })))
+      },
+      _ => self.index(id).map(|coord|Some((coord, coord))),
+    }
+  }
+
+  fn cell(&mut self, col: Coord, row: Coord) {
+    write!(self.out, "{}{}{}{}", abs(col), col_name(col.index), abs(row), row.index + 1).unwrap();
+  }
+
+  /// A range of cells, `A1:C2`, whole columns, `A:C`, or whole rows, `2:3`.
+  fn range(&mut self, node: &Node, col: &NodeId, row: &NodeId) {
+    match (self.span(col), self.span(row)) {
+      (Some(Some((col, last_col))), Some(Some((row, last_row)))) => {
+        self.cell(col, row);
+        self.out.push(':');
+        self.cell(last_col, last_row);
+      },
+      (Some(Some((col, last_col))), Some(None)) => {
+        write!(self.out, "{}{}:{}{}", abs(col), col_name(col.index), abs(last_col), col_name(last_col.index)).unwrap();
+      },
+      (Some(None), Some(Some((row, last_row)))) => {
+        write!(self.out, "{}{}:{}{}", abs(row), row.index + 1, abs(last_row), last_row.index + 1).unwrap();
+      },
+      _ => self.unsupported(node),
+    }
+  }
+
+  fn call(&mut self, node: &Node, func: &NodeId, args: &NodeId) {
+    let name = match self.ctx.get_node(func) {
+      Node::Sym { value } => self.ctx.get_value(value).to_string(),
+      _ => String::new(),
+    };
+    let args = match self.ctx.get_node(args) {
+      Node::List { elems } => self.ctx.get_elems(elems).to_vec(),
+      Node::Zero {} => vec![],
+      _ => vec![*args],
+    };
+    let fields = args.iter().any(|arg|matches!(self.ctx.get_node(arg), Node::Field { .. }));
+    match FUNCS.iter().find(|(_, func)|*func == name) {
+      Some((excel, _)) if !fields => {
+        write!(self.out, "{excel}(").unwrap();
+        for (i, arg) in args.iter().enumerate() {
+          if i > 0 {
+            self.out.push(',');
+          }
+          self.operand(arg, 0);
+        }
+        self.out.push(')');
+      },
+      _ => self.unsupported(node),
+    }
+  }
+
+  fn node(&mut self, node: &Node) {
+    match node {
+      Node::Leaf { value } => match self.ctx.get_value(value) {
+        Val::Num(n) => write!(self.out, "{n}").unwrap(),
+        Val::Int(n) => write!(self.out, "{n}").unwrap(),
+        Val::Float(n) if n.is_finite() => write!(self.out, "{n}").unwrap(),
+        Val::Bool(b) => self.out.push_str(if *b { "TRUE" } else { "FALSE" }),
+        Val::Str(s) => write!(self.out, "\"{}\"", s.replace('"', "\"\"")).unwrap(),
+        _ => self.unsupported(node),
+      },
+      Node::BinOp { op: '^', lhs, rhs } => {
+        // a spreadsheet groups `^` from the left, so both sides are wrapped
+        let prec = self.prec(node);
+        self.operand(lhs, prec + 1);
+        self.out.push('^');
+        self.operand(rhs, prec + 1);
+      },
+      Node::BinOp { op, lhs, rhs } => match excel_op(*op) {
+        Some(symbol) => {
+          let prec = self.prec(node);
+          self.operand(lhs, prec);
+          self.out.push_str(symbol);
+          self.operand(rhs, prec + 1);
+        },
+        None => self.unsupported(node),
+      },
+      Node::Index { row: col, col: row } if [col, row].iter().any(|id|matches!(self.ctx.get_node(id), Node::Slice { .. })) => {
+        self.range(node, col, row);
+      },
+      Node::Index { row: col, col: row } => match (self.index(col), self.index(row)) {
+        (Some(col), Some(row)) => self.cell(col, row),
+        _ => self.unsupported(node),
+      },
+      Node::If { cond, then, other } => {
+        self.out.push_str("IF(");
+        for (i, arg) in [cond, then, other].into_iter().enumerate() {
+          if i > 0 {
+            self.out.push(',');
+          }
+          self.operand(arg, 0);
+        }
+        self.out.push(')');
+      },
+      Node::Addr { row, col } => match (self.col_label(col), self.row_label(row)) {
+        (Some(col), Some(row)) => self.cell(col, row),
+        _ => self.unsupported(node),
+      },
+      Node::Call { func, args } => self.call(node, func, args),
+      _ => self.unsupported(node),
+    }
+  }
+}
+
+/// The spreadsheet operator for a binary operator, `<>` for `!=`.
+fn excel_op(op: char) -> Option<&'static str> {
+  let op = match op {
+    '+' => "+",
+    '-' => "-",
+    '*' => "*",
+    '/' => "/",
+    '⧺' => "&",
+    '=' => "=",
+    '≠' => "<>",
+    '<' => "<",
+    '>' => ">",
+    '≤' => "<=",
+    '≥' => ">=",
+    _ => return None,
+  };
+  Some(op)
+}
+
+fn abs(coord: Coord) -> &'static str {
+  if coord.abs { "$" } else { "" }
+}
+
+/// Index of a column in A1 notation, `AA` being 26.
+fn col_index(col: &str) -> Option<u32> {
+  if col.is_empty() || !col.bytes().all(|b|b.is_ascii_uppercase()) {
+    return None;
+  }
+  col.bytes()
+    .try_fold(0u32, |n, b|n.checked_mul(26)?.checked_add((b - b'A') as u32 + 1))
+    .map(|n|n - 1)
+}
+
+/// Name of a column in A1 notation, the inverse of `col_index`.
+fn col_name(index: u32) -> String {
+  let mut name = vec![];
+  let mut n = index as u64 + 1;
+  while n > 0 {
+    name.push(b'A' + ((n - 1) % 26) as u8);
+    n = (n - 1) / 26;
+  }
+  name.reverse();
+  String::from_utf8(name).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parser::Parser;
+  use rust_decimal_macros::dec;
+
+  fn untranslatable(formula: &str) -> Vec<String> {
+    match translate(formula) {
+      Err(Err::Untranslatable{names}) => names,
+      res => panic!("expected untranslatable, got {:?}", res),
+    }
+  }
+
+  #[test]
+  fn test_translate_refs() {
+    let cases = [
+      ("=A1", "[0, 0]"),
+      ("=B4", "[1, 3]"),
+      ("=$B$2", "[$1, $1]"),
+      ("=B$2 + $B2", "[1, $1] + [$1, 1]"),
+      ("=AA10", "[26, 9]"),
+      ("=SUM(A1:A5)*$B$2", "sum([0, 0:5]) * [$1, $1]"),
+      ("=SUM(A1:C2)", "sum([0:3, 0:2])"),
+      ("=SUM($A$1:$A$5)", "sum([$0, $0:$5])"),
+      ("=SUM(A:A)", "sum([0, :])"),
+      ("=SUM(B:D)", "sum([1:4, :])"),
+      ("=SUM(2:3)", "sum([:, 1:3])"),
+      ("=Sheet2!B4", "&Sheet2[1, 3]"),
+      ("='Rent Roll'!C3 * 12", "&'Rent Roll'[2, 2] * 12"),
+      ("=SUM(Sheet2!A1:A3)", "sum(&Sheet2[0, 0:3])"),
+    ];
+    for (formula, expected) in cases {
+      assert_eq!(Ok(expected.to_owned()), translate(formula).map_err(|e|e.to_string()), "{formula}");
+    }
+  }
+
+  #[test]
+  fn test_translate_ops() {
+    let cases = [
+      ("1+2*3", "1 + 2 * 3"),
+      ("=(1+2)*3", "(1 + 2) * 3"),
+      ("=1-(2-3)", "1 - (2 - 3)"),
+      ("=A1<>B1", "[0, 0] != [1, 0]"),
+      ("=A1=1", "[0, 0] == 1"),
+      ("=-2^2", "(-2)^2"),
+      ("=2^-1", "2^-1"),
+      ("=-A1*2", "(0 - [0, 0]) * 2"),
+      ("=-(A1+B1)", "0 - ([0, 0] + [1, 0])"),
+      ("=2^-A1", "2^(0 - [0, 0])"),
+      ("=--1", "0 - -1"),
+      ("=(1+2)^2", "(1 + 2)^2"),
+      ("=50%*A1", "50 / 100 * [0, 0]"),
+      ("=.5+1E3+2.50e-1", "0.5 + 1000 + 0.25"),
+      ("=sum(a1:b2)", "sum([0:2, 0:2])"),
+      ("=2^3^2", "(2^3)^2"),
+      ("=\"a\"&\"b\"=\"ab\"", "'a' ++ 'b' == 'ab'"),
+      ("=\"Total: \"&A1+1", "'Total: ' ++ ([0, 0] + 1)"),
+      ("=\"say \"\"hi\"\"\"", "'say \"hi\"'"),
+      ("=\"it's\"", "\"it's\""),
+      ("=\"it's \"\"hi\"\"\"", "'it\\'s \"hi\"'"),
+      ("=\"C:\\dir\"", "'C:\\\\dir'"),
+      ("=TRUE", "true"),
+      ("= AVERAGE( A1 , 2 ) ", "avg([0, 0], 2)"),
+    ];
+    for (formula, expected) in cases {
+      assert_eq!(Ok(expected.to_owned()), translate(formula).map_err(|e|e.to_string()), "{formula}");
+    }
+  }
+
+  #[test]
+  fn test_translate_eval() {
+    let eval = |formula: &str| {
+      let mut p = Parser::new(translate(formula).unwrap());
+      let node = p.parse().unwrap();
+      node.eval(&mut p)
+    };
+    let cases = [
+      ("=.5+1E3+2.50e-1", dec!(1000.75)),
+      ("=0.05*100", dec!(5)),
+      ("=-0.5*4", dec!(-2)),
+      ("=-(1+2)*2", dec!(-6)),
+      ("=-2^2", dec!(4)),
+      ("=2^-1", dec!(0.5)),
+      ("=1-2-3", dec!(-4)),
+      ("=50%*4", dec!(2)),
+      ("=--1", dec!(1)),
+      ("=IF(1>0, -1, 0)", dec!(-1)),
+    ];
+    for (formula, expected) in cases {
+      assert_eq!(Val::Num(expected), eval(formula), "{formula}");
+    }
+    // translations with references parse too
+    for formula in ["=-A1*2", "=-(A1+B1)", "=2^-$B$2", "=SUM(A1:A5)*-B2", "=IF(A1<>B1, A1&\"x\", -C3)"] {
+      let src = translate(formula).unwrap();
+      assert!(Parser::new(&src).parse().is_some(), "{formula}: {src}");
+    }
+  }
+
+  #[test]
+  fn test_translate_logic() {
+    let cases = [
+      ("=IF(C3>0, 1, 0)", "if [2, 2] > 0 1 else 0"),
+      ("=IF(A1, -1)", "if [0, 0] (-1) else false"),
+      ("=IF(A1, 1, IF(B1, 2, 3))", "if [0, 0] 1 else if [1, 0] 2 else 3"),
+      ("=IF(A1, 1, 2) + 1", "(if [0, 0] 1 else 2) + 1"),
+      ("=AND(A1>0, B1)", "([0, 0] > 0) and [1, 0]"),
+      ("=NOT(OR(A1, B1))", "not ([0, 0] or [1, 0])"),
+    ];
+    for (formula, expected) in cases {
+      assert_eq!(Ok(expected.to_owned()), translate(formula).map_err(|e|e.to_string()), "{formula}");
+    }
+    // the translations parse, without the parens the translation keeps for clarity
+    for src in ["if [2, 2] > 0 1 else 0", "if [0, 0] (-1) else false", "(if [0, 0] 1 else 2) + 1", "[0, 0] > 0 and [1, 0]", "not ([0, 0] or [1, 0])"] {
+      let mut p = Parser::new(src);
+      let ast = p.parse().unwrap();
+      assert_eq!(src, ast.print(&p));
+    }
+  }
+
+  #[test]
+  fn test_translate_untranslatable() {
+    assert_eq!(vec!["PMT"], untranslatable("=IF(C3>0, PMT(0.05/12, 360, -B2), 0)"));
+    assert_eq!(vec!["VLOOKUP", "IFERROR"], untranslatable("=IFERROR(VLOOKUP(A1, B:C, 2), vlookup(A1, D:E, 2))"));
+    assert_eq!(vec!["TaxRate"], untranslatable("=A1 * TaxRate"));
+    assert_eq!(vec!["#N/A"], untranslatable("=#N/A"));
+    assert_eq!(vec!["Sheet2!Rate"], untranslatable("=Sheet2!Rate"));
+
+    assert!(matches!(translate("=SUM(A1"), Err(Err::Parse{pos: 7})));
+    assert!(matches!(translate("=1 +"), Err(Err::Parse{..})));
+    assert!(matches!(translate("=(1))"), Err(Err::Parse{pos: 4})));
+  }
+
+  fn export_src(src: &str) -> String {
+    let mut p = Parser::new(src);
+    let ast = p.parse().unwrap();
+    export(&ast, &p)
+  }
+
+  #[test]
+  fn test_export() {
+    let cases = [
+      ("[0, 0]", "=A1"),
+      ("[1, 3]", "=B4"),
+      ("[1]", "=B1"),
+      ("[$1, $1] * 2", "=$B$2*2"),
+      ("[26, 9]", "=AA10"),
+      ("@B2 + {3, $C}", "=B2+$C3"),
+      ("sum(1, [0, 1]) * (2 - 1)", "=SUM(1,A2)*(2-1)"),
+      ("1 - (2 - 3) / 4", "=1-(2-3)/4"),
+      ("avg(max(1, 2), abs(3))", "=AVERAGE(MAX(1,2),ABS(3))"),
+      ("'say \"hi\"'", "=\"say \"\"hi\"\"\""),
+      ("true", "=TRUE"),
+      ("sum([0, 0:5]) * [$1, $1]", "=SUM(A1:A5)*$B$2"),
+      ("[0:3, :1]", "=A1:C1"),
+      ("[0, :]", "=A:A"),
+      ("[:, $1:$3]", "=$2:$3"),
+      ("if [0, 0] > 1 2 else 3", "=IF(A1>1,2,3)"),
+      ("1 == 2 != (3 < 4)", "=1=2<>(3<4)"),
+      ("2 ^ 3 ^ 2", "=2^(3^2)"),
+      ("(1 + 2) ^ 2", "=(1+2)^2"),
+      ("'a' ++ 1 + 2", "=\"a\"&1+2"),
+    ];
+    for (src, expected) in cases {
+      assert_eq!(expected, export_src(src), "{src}");
+    }
+  }
+
+  #[test]
+  fn test_export_unsupported() {
+    let cases = [
+      ("let x = 1 in x", "=#UNSUPPORTED(let x = 1 in x)"),
+      ("map((1, 2), x -> x * 2) + 1", "=#UNSUPPORTED(map((1, 2), x -> x * 2))+1"),
+      ("{Tomatoes, Cost}", "=#UNSUPPORTED({Tomatoes, Cost})"),
+      ("[0, x]", "=#UNSUPPORTED([0, x])"),
+      ("sum(a: 1)", "=#UNSUPPORTED(sum(a: 1))"),
+      ("1, 2", "=#UNSUPPORTED(1, 2)"),
+      ("[0, 2:]", "=#UNSUPPORTED([0, 2:])"),
+      ("[:, :]", "=#UNSUPPORTED([:, :])"),
+      ("1 in (1, 2)", "=#UNSUPPORTED(1 in (1, 2))"),
+    ];
+    for (src, expected) in cases {
+      assert_eq!(expected, export_src(src), "{src}");
+    }
+  }
+
+  #[test]
+  fn test_export_translate() {
+    let formulas = [
+      "=SUM(A1,$B$2)*C3",
+      "=AVERAGE(AA10,1)-2/4",
+      "=(1+2)*3",
+      "=SUM(A1:A5)*$B$2",
+      "=SUM($A$1:C$2)",
+      "=SUM(B:D)+SUM($2:3)",
+      "=IF(C3>0,1,IF(A1<>B1,A1&\"x\",-2))",
+      "=IF(A1<=1,A1>=B1,A1=B1)",
+      "=(2^3)^2+2^-1",
+    ];
+    for formula in formulas {
+      let mut p = Parser::new(translate(formula).unwrap());
+      let ast = p.parse().unwrap();
+      assert_eq!(formula, export(&ast, &p));
+    }
+    for index in [0, 25, 26, 701, 702, 16383] {
+      assert_eq!(Some(index), col_index(&col_name(index)));
+    }
+  }
+}
@@ -7,6 +7,10 @@ export enum TypeUi {
   List,
   Array,
   Record,
+  Lambda,
+  OrderedMap,
+  SparseArray,
+  Error,
 }
 
 export interface ScalarValueUi {
@@ -31,11 +35,27 @@ export interface RecordValueUi {
   fields: number,
 }
 
+export interface SparseArrayValueUi {
+  typ: TypeUi,
+  fill: string,
+  dims: Array<number>,
+  index: Array<number>, // row-major positions of value, other elements are fill
+  value: Array<String>,
+}
+
+export interface ErrorValueUi {
+  typ: TypeUi,
+  value: string, // error code, e.g. #DIV/0
+  origin: string, // formula text the error arose from
+}
+
 export type ValueUi = 
   | ScalarValueUi 
   | ListValueUi
   | ArrayValueUi
   | RecordValueUi
+  | SparseArrayValueUi
+  | ErrorValueUi
 
 
 export interface CellUi {
@@ -48,11 +68,13 @@ export interface CellUi {
 * UI Data for a Tile.
 * 
 * @interface TileUi 
+* @member name is the name used to reference the tile, as in `&name`
 * @member rows is the number of rows in tile
 * @member cells contains the cell contents in row-major order
 */
 export interface TileUi {
   tag: number,
+  name: string,
   rows: number,
   cells: Array<CellUi>,
   rowLabels: Array<String>,
@@ -69,3 +91,52 @@ export interface TileUi {
 export interface BoardUi {
   tiles: Array<TileUi>,
 }
+
+
+export enum TokenTypeUi {
+  Number,
+  String,
+  Operator,
+  Reference,
+  Keyword,
+  Symbol,
+  Punctuation,
+  Error,
+}
+
+/**
+ * A classified formula token for syntax highlighting.
+ *
+ * @interface TokenUi
+ * @member start offset of the token in UTF-16 code units
+ * @member end offset just past the token in UTF-16 code units
+ */
+export interface TokenUi {
+  typ: TokenTypeUi,
+  start: number,
+  end: number,
+}
+
+export enum CompletionKindUi {
+  Label,
+  Tile,
+  Function,
+  Keyword,
+}
+
+/**
+ * A formula completion candidate, best match first.
+ *
+ * @interface CompletionUi
+ * @member label text to insert in place of `start..end`
+ * @member detail signature or short description of the candidate
+ * @member start offset of the replaced text in UTF-16 code units
+ * @member end offset just past the replaced text in UTF-16 code units
+ */
+export interface CompletionUi {
+  kind: CompletionKindUi,
+  label: string,
+  detail: string,
+  start: number,
+  end: number,
+}
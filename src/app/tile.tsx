@@ -5,7 +5,7 @@ import { invoke } from '@tauri-apps/api/tauri'
 import { headers } from 'next/dist/client/components/headers'
 import Board, { BoardContext } from './board'
 import { useRef, MutableRefObject } from 'react';
-import { TileUi, BoardUi, CellUi,TypeUi, ValueUi } from './rpc';
+import { TileUi, BoardUi, CellUi,TypeUi, ValueUi, SparseArrayValueUi, ErrorValueUi } from './rpc';
 
 function TileHeader(props: {
   headers: Array<String>,
@@ -39,6 +39,26 @@ function renderValue(value: ValueUi): any {
       {value.value}
     </div>
 
+    case TypeUi.OrderedMap:
+    const entries = value.value as String[];
+    return <table>
+        <tbody>{Array.from({length: entries.length / 2}, (_, i) => {
+            return <tr><td className='border'>{entries[2 * i]}</td><td className='border'>{entries[2 * i + 1]}</td></tr>
+        })}</tbody>
+    </table>
+
+    case TypeUi.SparseArray:
+    const sparse = value as SparseArrayValueUi;
+    return <div>
+      {sparse.dims.join('×')} of {sparse.fill}, {sparse.index.length} set
+    </div>
+
+    case TypeUi.Error:
+    const error = value as ErrorValueUi;
+    return <div className='font-medium text-red-600' title={error.origin}>
+      {error.value}
+    </div>
+
     default: return <div>
       {(()=>{console.log(value); return "Unknown Cell Value with Type: "+value.typ})()}
     </div>